@@ -0,0 +1,13 @@
+#version 330 core
+
+smooth in vec2 vuv;
+smooth in vec4 vcolor;
+flat in float vtextured;
+out vec4 ocolor;
+
+uniform sampler2D block_texture;
+
+void main() {
+     vec4 texel = mix(vec4(1.0), texture(block_texture, vuv), vtextured);
+     ocolor = texel * vcolor;
+}
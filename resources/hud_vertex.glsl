@@ -0,0 +1,17 @@
+#version 330 core
+
+in vec2 position;
+in vec2 uv;
+in vec4 color;
+in float textured;
+
+smooth out vec2 vuv;
+smooth out vec4 vcolor;
+flat out float vtextured;
+
+void main() {
+     gl_Position = vec4(position, 0.0, 1.0);
+     vuv = uv;
+     vcolor = color;
+     vtextured = textured;
+}
@@ -0,0 +1,169 @@
+//! Saving changed chunks on a timer, with the file writing done on a thread
+//! so the frame does not wait for the disk.
+
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use world::{World, WorldPoint};
+use world::chunk::Chunk;
+
+pub const DEFAULT_INTERVAL: u64 = 60;
+
+pub struct Autosave {
+    /// `None` turns the timer off; `finish` still saves.
+    interval: Option<Duration>,
+    last_save: Instant,
+    /// The thread writing the last save, which gives back the chunks it
+    /// could not write.
+    writer: Option<JoinHandle<Vec<WorldPoint>>>,
+    /// Chunks handed to `writer`, in case it panics.
+    in_flight: Vec<WorldPoint>,
+}
+
+impl Autosave {
+    pub fn new(interval: Option<Duration>) -> Autosave {
+        Autosave {
+            interval: interval,
+            last_save: Instant::now(),
+            writer: None,
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Starts a background save once the interval has passed. Call every
+    /// frame.
+    pub fn update(&mut self, world: &mut World) {
+        let due = match self.interval {
+            Some(interval) => self.last_save.elapsed() >= interval,
+            None => false,
+        };
+        if !due {
+            return;
+        }
+
+        // The last save has had a whole interval, so this rarely waits
+        self.wait_for_writer(world);
+        self.last_save = Instant::now();
+
+        let changed = world.take_changed();
+        if changed.is_empty() {
+            return;
+        }
+        let root = world.root().to_path_buf();
+        self.in_flight = changed.iter().map(|&(origin, _)| origin).collect();
+        self.writer = Some(thread::spawn(move || write_chunks("Autosave", root, changed)));
+    }
+
+    /// Saves everything changed and waits until it is on disk, for a clean
+    /// exit.
+    pub fn finish(&mut self, world: &mut World) {
+        self.wait_for_writer(world);
+        let changed = world.take_changed();
+        let failed = write_chunks("Exit save", world.root().to_path_buf(), changed);
+        world.mark_unsaved(failed);
+    }
+
+    /// Waits for a background save to finish, so that a save made after
+    /// this cannot be overwritten by an older copy. Chunks it failed to
+    /// write count as unsaved again. Call before writing chunks any other
+    /// way.
+    pub fn wait_for_writer(&mut self, world: &mut World) {
+        if let Some(writer) = self.writer.take() {
+            let in_flight = self.in_flight.drain(..).collect();
+            match writer.join() {
+                Ok(failed) => world.mark_unsaved(failed),
+                Err(_) => {
+                    error!("Autosave thread panicked");
+                    world.mark_unsaved(in_flight);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `changed` under `root`, returning the chunks that could not be
+/// written.
+fn write_chunks(label: &str,
+                root: PathBuf,
+                changed: Vec<(WorldPoint, Vec<u8>)>)
+                -> Vec<WorldPoint> {
+    let start = Instant::now();
+    let mut bytes = 0;
+    let mut failed = Vec::new();
+    for &(origin, ref data) in &changed {
+        match Chunk::write_bytes(origin, &root, data) {
+            Ok(()) => bytes += data.len(),
+            Err(e) => {
+                error!("{}", e);
+                failed.push(origin);
+            }
+        }
+    }
+
+    let elapsed = start.elapsed();
+    let millis = elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+    if failed.is_empty() {
+        info!("{}: wrote {} chunks ({} KiB) in {} ms",
+              label,
+              changed.len(),
+              bytes / 1024,
+              millis);
+    } else {
+        warn!("{}: wrote {} chunks ({} KiB) in {} ms, {} failed",
+              label,
+              changed.len() - failed.len(),
+              bytes / 1024,
+              millis,
+              failed.len());
+    }
+    failed
+}
+
+#[test]
+fn saves_changes_in_background() {
+    use std::env;
+    use std::fs;
+    use std::iter;
+    use prelude::*;
+    use world::Access;
+    use world::block::BlockID;
+    use world::terrain::FlatGenerator;
+
+    let root = env::temp_dir().join("subsurface-autosave-test");
+    let _ = fs::remove_dir_all(&root);
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(&root,
+                                     (vec3(0, 0, 0), vec3(16, 16, 16)),
+                                     chunk_gen,
+                                     Access::ReadWrite)
+        .unwrap();
+
+    let mut autosave = Autosave::new(Some(Duration::from_secs(0)));
+    autosave.update(&mut world);
+    autosave.wait_for_writer(&mut world);
+    assert!(!Chunk::path(point3(0, 0, 0), &root).exists());
+
+    world.apply_changes(iter::once((point3(1, 1, 1), BlockID(2))));
+    autosave.update(&mut world);
+    autosave.wait_for_writer(&mut world);
+    let saved = Chunk::read(point3(0, 0, 0), &root).unwrap();
+    assert_eq!(saved.get_block(point3(1, 1, 1)).id, BlockID(2));
+
+    world.apply_changes(iter::once((point3(17, 1, 1), BlockID(3))));
+    Autosave::new(None).finish(&mut world);
+    assert_eq!(Chunk::read(point3(16, 0, 0), &root).unwrap().get_block(point3(17, 1, 1)).id,
+               BlockID(3));
+
+    // A chunk that cannot be written stays unsaved for the next attempt
+    let blocked = Chunk::path(point3(0, 16, 0), &root).join("chunk.bincode");
+    fs::create_dir_all(&blocked).unwrap();
+    world.apply_changes(iter::once((point3(1, 17, 1), BlockID(2))));
+    autosave.update(&mut world);
+    autosave.wait_for_writer(&mut world);
+    let retry: Vec<WorldPoint> = world.take_changed().into_iter().map(|c| c.0).collect();
+    assert_eq!(retry, vec![point3(0, 16, 0)]);
+
+    drop(world);
+    fs::remove_dir_all(&root).unwrap();
+}
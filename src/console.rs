@@ -0,0 +1,444 @@
+//! The in-game developer console: a line editor with history and tab
+//! completion, and a registry of named commands that run against the game.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use glutin::{Event, ElementState, VirtualKeyCode};
+
+use prelude::*;
+use game::Game;
+use world::{WorldMeta, WorldPoint};
+use world::edit::Shape;
+use world::snapshot;
+use world::terrain;
+use world::registry::Registry;
+
+/// Lines of output kept for display.
+const SCROLLBACK: usize = 12;
+/// The most blocks one `fill` may change, so a typo cannot hang the game.
+const MAX_FILL: i64 = 64 * 64 * 64;
+
+pub type CommandFn = Box<Fn(&mut Game, &[&str]) -> Result<String, String>>;
+
+pub struct ConsoleCommand {
+    pub usage: &'static str,
+    pub run: CommandFn,
+}
+
+/// Commands by name. Names may be several words, such as `gen reload`; the
+/// longest name matching the start of a line wins.
+pub struct CommandRegistry {
+    commands: BTreeMap<String, ConsoleCommand>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry { commands: BTreeMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, usage: &'static str, run: CommandFn) {
+        self.commands.insert(name.into(),
+                             ConsoleCommand {
+                                 usage: usage,
+                                 run: run,
+                             });
+    }
+
+    /// Splits `line` into the matching command and its arguments.
+    fn find<'a, 'b>(&'a self,
+                    words: &'b [&'b str])
+                    -> Option<(&'a ConsoleCommand, &'b [&'b str])> {
+        (1..words.len() + 1)
+            .rev()
+            .filter_map(|n| {
+                self.commands.get(&words[..n].join(" ")).map(|command| (command, &words[n..]))
+            })
+            .next()
+    }
+
+    pub fn run(&self, game: &mut Game, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return Ok(String::new());
+        }
+        if words == ["help"] {
+            let usages: Vec<&str> = self.commands.values().map(|c| c.usage).collect();
+            return Ok(usages.join("\n"));
+        }
+
+        match self.find(&words) {
+            Some((command, args)) => {
+                (command.run)(game, args).map_err(|e| format!("{} (usage: {})", e, command.usage))
+            }
+            None => Err(format!("Unknown command \"{}\", try help", words[0])),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        self.commands.keys().collect()
+    }
+}
+
+pub struct Console {
+    pub open: bool,
+    pub commands: CommandRegistry,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    /// Position while stepping back through `history`.
+    recall: Option<usize>,
+    /// Lines submitted since `take_entered`, for recording.
+    entered: Vec<String>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let mut commands = CommandRegistry::new();
+        register_builtins(&mut commands);
+        Console {
+            open: false,
+            commands: commands,
+            input: String::new(),
+            output: VecDeque::new(),
+            history: Vec::new(),
+            recall: None,
+            entered: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.input.clear();
+        self.recall = None;
+    }
+
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            self.output.push_back(line.into());
+        }
+        while self.output.len() > SCROLLBACK {
+            self.output.pop_front();
+        }
+    }
+
+    /// Lines to draw, oldest first, ending with the prompt.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.output.iter().cloned().collect();
+        lines.push(format!("> {}_", self.input));
+        lines
+    }
+
+    /// Edits the line for a window event, running it on return.
+    pub fn handle_event(&mut self, event: &Event, game: &mut Game) {
+        match *event {
+            Event::ReceivedCharacter(c) if !c.is_control() && c != '`' => self.input.push(c),
+            Event::KeyboardInput(ElementState::Pressed, _, Some(key)) => {
+                match key {
+                    VirtualKeyCode::Return => self.submit(game),
+                    VirtualKeyCode::Back => {
+                        self.input.pop();
+                    }
+                    VirtualKeyCode::Tab => self.complete(&game.world.registry),
+                    VirtualKeyCode::Up => self.step_history(-1),
+                    VirtualKeyCode::Down => self.step_history(1),
+                    VirtualKeyCode::Escape | VirtualKeyCode::Grave => self.toggle(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn submit(&mut self, game: &mut Game) {
+        let line = self.input.trim().to_string();
+        self.input.clear();
+        self.recall = None;
+        if line.is_empty() {
+            return;
+        }
+
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        self.execute(game, &line);
+        self.entered.push(line);
+    }
+
+    /// Runs `line` as if it were typed, printing it and what it gave.
+    pub fn execute(&mut self, game: &mut Game, line: &str) {
+        self.print(&format!("> {}", line));
+        let result = self.commands.run(game, line);
+        match result {
+            Ok(message) => self.print(&message),
+            Err(error) => self.print(&format!("Error: {}", error)),
+        }
+    }
+
+    /// The lines submitted since this was last called.
+    pub fn take_entered(&mut self) -> Vec<String> {
+        self.entered.drain(..).collect()
+    }
+
+    fn step_history(&mut self, step: i32) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        let last = self.history.len() as i32 - 1;
+        let position = match self.recall {
+            Some(position) => position as i32 + step,
+            None if step < 0 => last,
+            None => return,
+        };
+
+        if position > last {
+            self.recall = None;
+            self.input.clear();
+        } else {
+            let position = clamp(position, 0, last) as usize;
+            self.recall = Some(position);
+            self.input = self.history[position].clone();
+        }
+    }
+
+    /// Completes the word being typed: command names at the start of the
+    /// line and block names after a command.
+    pub fn complete(&mut self, registry: &Registry) {
+        let (done, partial) = match self.input.rfind(' ') {
+            Some(space) => {
+                (self.input[..space + 1].to_string(), self.input[space + 1..].to_string())
+            }
+            None => (String::new(), self.input.clone()),
+        };
+
+        let command_names: Vec<String> = self.commands
+            .names()
+            .into_iter()
+            .filter(|name| name.starts_with(&self.input[..]))
+            .map(|name| name[done.len()..].to_string())
+            .collect();
+        let candidates = if !command_names.is_empty() {
+            command_names
+        } else {
+            registry.block_ids()
+                .into_iter()
+                .filter_map(|id| registry.lookup_name(id))
+                .filter(|name| name.starts_with(&partial[..]))
+                .cloned()
+                .collect()
+        };
+
+        match candidates.len() {
+            0 => (),
+            1 => self.input = format!("{}{} ", done, candidates[0]),
+            _ => {
+                let mut common = candidates[0].clone();
+                for candidate in &candidates[1..] {
+                    while !candidate.starts_with(&common[..]) {
+                        common.pop();
+                    }
+                }
+                self.input = format!("{}{}", done, common);
+                let listing = candidates.join("  ");
+                self.print(&listing);
+            }
+        }
+    }
+}
+
+fn parse_i32s(args: &[&str]) -> Result<Vec<i32>, String> {
+    args.iter()
+        .map(|a| a.parse::<i32>().map_err(|_| format!("\"{}\" is not a whole number", a)))
+        .collect()
+}
+
+fn point_at(args: &[&str]) -> Result<WorldPoint, String> {
+    let v = try!(parse_i32s(args));
+    Ok(point3(v[0], v[1], v[2]))
+}
+
+fn block_named(game: &Game, name: &str) -> Result<::world::block::BlockID, String> {
+    game.world.registry.lookup_id(&name.into()).ok_or(format!("Unknown block \"{}\"", name))
+}
+
+fn world_meta(game: &Game) -> Result<WorldMeta, String> {
+    try!(WorldMeta::read(game.world.root())).ok_or("World has no metadata".into())
+}
+
+fn register_builtins(commands: &mut CommandRegistry) {
+    commands.register("tp",
+                      "tp <x> <y> <z>",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        if args.len() != 3 {
+            return Err("Expected three coordinates".into());
+        }
+        let mut v = Vec::new();
+        for arg in args {
+            v.push(try!(arg.parse::<f32>().map_err(|_| format!("\"{}\" is not a number", arg))));
+        }
+        game.camera.teleport(Point3::new(v[0], v[1], v[2]));
+        Ok(format!("Teleported to {} {} {}", v[0], v[1], v[2]))
+    }));
+
+    commands.register("fill",
+                      "fill <x1> <y1> <z1> <x2> <y2> <z2> <block>",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        if args.len() != 7 {
+            return Err("Expected two corners and a block".into());
+        }
+        let id = try!(block_named(game, args[6]));
+        let shape = Shape::Box(try!(point_at(&args[0..3])), try!(point_at(&args[3..6])));
+        let (low, high) = shape.bounds();
+        let volume = (high.x as i64 - low.x as i64 + 1) * (high.y as i64 - low.y as i64 + 1) *
+                     (high.z as i64 - low.z as i64 + 1);
+        if volume > MAX_FILL {
+            return Err(format!("{} blocks is more than the {} one fill may change",
+                               volume,
+                               MAX_FILL));
+        }
+        let changed = game.world.fill_shape(&shape, id);
+        Ok(format!("Changed {} blocks", changed))
+    }));
+
+    commands.register("set block",
+                      "set block <x> <y> <z> <block>",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        if args.len() != 4 {
+            return Err("Expected a position and a block".into());
+        }
+        let id = try!(block_named(game, args[3]));
+        let loc = try!(point_at(&args[0..3]));
+        game.world.place_block(loc, id);
+        Ok(format!("Set {:?} to {}", loc, args[3]))
+    }));
+
+    commands.register("save",
+                      "save",
+                      Box::new(|game: &mut Game, _: &[&str]| {
+        game.autosave.wait_for_writer(&mut game.world);
+        game.world.write_all_chunks();
+        Ok(format!("Saved {} chunks", game.world.chunk_count()))
+    }));
+
+    commands.register("seed",
+                      "seed",
+                      Box::new(|game: &mut Game, _: &[&str]| {
+        let meta = try!(world_meta(game));
+        Ok(format!("Seed {} ({})", meta.seed, meta.generator))
+    }));
+
+    commands.register("gen reload",
+                      "gen reload",
+                      Box::new(|game: &mut Game, _: &[&str]| {
+        let meta = try!(world_meta(game));
+        let chunk_gen = try!(terrain::from_spec(&meta.generator,
+                                                meta.seed,
+                                                &game.world.registry));
+        // Chunks still being autosaved have no file yet but are not new
+        game.autosave.wait_for_writer(&mut game.world);
+        let count = game.world.regenerate_unsaved(chunk_gen);
+        Ok(format!("Regenerated {} unsaved chunks with {}", count, meta.generator))
+    }));
+
+    commands.register("snapshot",
+                      "snapshot [full]",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        if game.world.is_read_only() {
+            return Err("The world is open read-only".into());
+        }
+        game.autosave.wait_for_writer(&mut game.world);
+        game.world.save_changed();
+        let info = try!(snapshot::create(game.world.root(), args.get(0) == Some(&"full")));
+        Ok(format!("Created snapshot {}", info))
+    }));
+
+    commands.register("snapshot list",
+                      "snapshot list",
+                      Box::new(|game: &mut Game, _: &[&str]| {
+        let infos = try!(snapshot::list(game.world.root()));
+        if infos.is_empty() {
+            return Ok("No snapshots".into());
+        }
+        let lines: Vec<String> = infos.iter().map(|info| info.to_string()).collect();
+        Ok(lines.join("\n"))
+    }));
+
+    commands.register("snapshot restore",
+                      "snapshot restore <name>",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        if game.world.is_read_only() {
+            return Err("The world is open read-only".into());
+        }
+        let name = try!(args.get(0).ok_or("Expected a snapshot name"));
+        // Saving first lets the snapshot taken before restoring include
+        // the edits still in memory, and waiting for the autosave keeps an
+        // older copy from landing on top of the restored files
+        game.autosave.wait_for_writer(&mut game.world);
+        game.world.save_changed();
+        let written = try!(snapshot::restore(game.world.root(), name));
+        let meta = try!(world_meta(game));
+        let chunk_gen = try!(terrain::from_spec(&meta.generator,
+                                                meta.seed,
+                                                &game.world.registry));
+        game.world.reload(chunk_gen);
+        Ok(format!("Restored {} chunks from snapshot {}", written, name))
+    }));
+
+    commands.register("time set",
+                      "time set <hour|day|night>",
+                      Box::new(|game: &mut Game, args: &[&str]| {
+        let hour = match args.get(0).cloned() {
+            Some("day") => 12.0,
+            Some("night") => 0.0,
+            Some(hour) => {
+                try!(hour.parse::<f32>().map_err(|_| format!("\"{}\" is not an hour", hour)))
+            }
+            None => return Err("Expected an hour".into()),
+        };
+        game.time = ((hour % 24.0) + 24.0) % 24.0;
+        Ok(format!("Time is {:.1}", game.time))
+    }));
+}
+
+#[test]
+fn commands_and_completion() {
+    use world::{World, Access};
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(::std::path::Path::new("/nonexistent"),
+                                 (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                 chunk_gen,
+                                 Access::ReadOnly)
+        .unwrap();
+    let mut game = Game::new(world);
+    let mut console = Console::new();
+
+    assert!(console.commands.run(&mut game, "fill 0 0 0 1 1 1 dirt").is_ok());
+    assert_eq!(game.world.get_block(point3(1, 1, 1)).id,
+               game.world.registry.lookup_id(&"dirt".into()).unwrap());
+    assert!(console.commands.run(&mut game, "fill 0 0 0 1000 1000 1000 dirt").is_err());
+    assert!(console.commands.run(&mut game, "set block 0 5 0 lava").is_err());
+    assert!(console.commands.run(&mut game, "time set night").is_ok());
+    assert_eq!(game.time, 0.0);
+    assert!(console.commands.run(&mut game, "teleport 1 2 3").is_err());
+    assert!(console.commands.run(&mut game, "snapshot").is_err());
+
+    console.input = "ti".into();
+    console.complete(&game.world.registry);
+    assert_eq!(console.input, "time set ");
+
+    console.input = "set block 1 2 3 gr".into();
+    console.complete(&game.world.registry);
+    assert_eq!(console.input, "set block 1 2 3 grass ");
+
+    console.input = "tp 1 2 3".into();
+    console.submit(&mut game);
+    assert_eq!(console.take_entered(), vec!["tp 1 2 3".to_string()]);
+    assert!(console.take_entered().is_empty());
+    console.input = "save".into();
+    console.step_history(-1);
+    assert_eq!(console.input, "tp 1 2 3");
+    console.step_history(1);
+    assert_eq!(console.input, "");
+}
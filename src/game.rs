@@ -0,0 +1,216 @@
+//! The state a play session changes in response to commands, kept apart from
+//! the window and renderers so sessions can be replayed headless.
+
+use std::path::Path;
+
+use cgmath::prelude::*;
+
+use prelude::*;
+use autosave::Autosave;
+use graphics::Camera;
+use hotbar::Hotbar;
+use input::{Command, State};
+use world::{World, RayHit, Access};
+
+/// How far blocks can be placed or broken from.
+const REACH: f32 = 10.0;
+const MOVE_SPEED: f32 = 5.0;
+
+/// Where the cursor is put back to after every look.
+pub const CURSOR_CENTRE: (i32, i32) = (500, 500);
+
+pub struct Game {
+    pub world: World,
+    pub camera: Camera,
+    pub hotbar: Hotbar,
+    /// Hour of the day, from 0 to 24. Only the sky follows it for now.
+    pub time: f32,
+    /// Off unless the caller sets an interval. Anything that writes chunks
+    /// must wait for its writer first.
+    pub autosave: Autosave,
+    movement: Vector3<f32>,
+}
+
+/// What the caller needs to react to after a command.
+pub enum Outcome {
+    Nothing,
+    Exit,
+    HotbarChanged,
+    /// The camera turned; the cursor should be recentred.
+    Looked,
+    ToggleConsole,
+}
+
+impl Game {
+    pub fn new(world: World) -> Game {
+        let hotbar = Hotbar::new(&world.registry);
+        Game {
+            world: world,
+            camera: Camera::new(point3(-1.0, 40.0, -1.0), 0.0, 0.0),
+            hotbar: hotbar,
+            time: 12.0,
+            autosave: Autosave::new(None),
+            movement: vec3(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Loads the area around the spawn point of the world at `world_path`.
+    pub fn open(world_path: &Path,
+                default_generator: &str,
+                access: Access)
+                -> Result<Game, String> {
+        Ok(Game::new(try!(World::open(world_path, default_generator, access))))
+    }
+
+    /// The block under the crosshair.
+    pub fn target(&self) -> Option<RayHit> {
+        let direction = (self.camera.look_at - self.camera.position).normalize();
+        self.world.cast_ray(self.camera.position, REACH * direction)
+    }
+
+    pub fn apply(&mut self, command: &Command) -> Outcome {
+        match *command {
+            Command::Exit => return Outcome::Exit,
+            Command::CameraTranslate(State::Start, amount) => {
+                self.movement += amount;
+                self.movement.x = clamp(self.movement.x, -1.0, 1.0);
+                self.movement.y = clamp(self.movement.y, -1.0, 1.0);
+                self.movement.z = clamp(self.movement.z, -1.0, 1.0);
+            }
+            Command::CameraTranslate(State::Stop, amount) => {
+                self.movement -= amount;
+            }
+            Command::CameraLook(cursor) => {
+                let relative = cursor - vec2(CURSOR_CENTRE.0 as f32, CURSOR_CENTRE.1 as f32);
+                self.camera.look_around(0.01 * relative);
+                return Outcome::Looked;
+            }
+            Command::Place => {
+                if let Some(hit) = self.target() {
+                    if let (Some(face), Some(id)) = (hit.face, self.hotbar.current()) {
+                        self.world.place_block(hit.loc + face.normal(), id);
+                    }
+                }
+            }
+            Command::Break => {
+                if let Some(hit) = self.target() {
+                    self.world.break_block(hit.loc);
+                }
+            }
+            Command::Save => {
+                self.autosave.wait_for_writer(&mut self.world);
+                self.world.write_all_chunks();
+                info!("Saved all {} loaded chunks", self.world.chunk_count());
+            }
+            Command::Undo => {
+                if !self.world.undo() {
+                    info!("Nothing to undo");
+                }
+            }
+            Command::Redo => {
+                if !self.world.redo() {
+                    info!("Nothing to redo");
+                }
+            }
+            Command::SelectSlot(slot) => {
+                self.hotbar.select(slot);
+                return Outcome::HotbarChanged;
+            }
+            Command::ScrollHotbar(steps) => {
+                self.hotbar.scroll(steps);
+                return Outcome::HotbarChanged;
+            }
+            Command::PickBlock => {
+                if let Some(hit) = self.target() {
+                    self.hotbar.pick(hit.block.id);
+                    return Outcome::HotbarChanged;
+                }
+            }
+            Command::ToggleConsole => return Outcome::ToggleConsole,
+            Command::Noop => (),
+        }
+
+        Outcome::Nothing
+    }
+
+    /// Stops any movement under way, for when input is taken elsewhere.
+    pub fn stop_moving(&mut self) {
+        self.movement = vec3(0.0, 0.0, 0.0);
+    }
+
+    /// Sky colour for the time of day, black at night and blue at noon.
+    pub fn sky_color(&self) -> [f32; 4] {
+        use std::f32::consts::PI;
+
+        let daylight = ((self.time - 6.0) / 12.0 * PI).sin().max(0.0);
+        [0.5 * daylight, 0.7 * daylight, 1.0 * daylight, 1.0]
+    }
+
+    /// Moves the camera for a frame lasting `delta` seconds.
+    pub fn advance(&mut self, delta: f32) {
+        let movement = delta * MOVE_SPEED * self.movement;
+        self.camera.relative_translate(movement);
+    }
+}
+
+#[test]
+fn each_edit_aims_afresh() {
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(Path::new("/nonexistent"),
+                                 (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                 chunk_gen,
+                                 Access::ReadOnly)
+        .unwrap();
+    let mut game = Game::new(world);
+    let stone = game.world.registry.lookup_id(&"stone".into()).unwrap();
+    game.world.apply_changes(Some((point3(1, 0, 0), stone)));
+    game.camera = Camera::new(Point3::new(1.5, 4.5, 0.5), 0.0, 3.1);
+    let held = game.hotbar.current().unwrap();
+
+    // Two clicks in one frame stack two blocks rather than placing the
+    // second on the spot the first already filled
+    game.apply(&Command::Place);
+    game.apply(&Command::Place);
+    assert_eq!(game.world.get_block(point3(1, 1, 0)).id, held);
+    assert_eq!(game.world.get_block(point3(1, 2, 0)).id, held);
+
+    game.apply(&Command::Break);
+    game.apply(&Command::Break);
+    assert!(game.world.get_block(point3(1, 1, 0)).is_empty());
+    assert_eq!(game.world.get_block(point3(1, 0, 0)).id, stone);
+}
+
+#[test]
+fn save_waits_for_autosave() {
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+    use world::block::BlockID;
+    use world::chunk::Chunk;
+    use world::terrain::FlatGenerator;
+
+    let root = env::temp_dir().join("subsurface-game-save-test");
+    let _ = fs::remove_dir_all(&root);
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(&root,
+                                 (vec3(0, 0, 0), vec3(16, 16, 16)),
+                                 chunk_gen,
+                                 Access::ReadWrite)
+        .unwrap();
+    let mut game = Game::new(world);
+    game.autosave = Autosave::new(Some(Duration::from_secs(0)));
+
+    // Saving while the autosave still holds the older copy must not let
+    // that copy land last
+    game.world.apply_changes(Some((point3(1, 1, 1), BlockID(2))));
+    game.autosave.update(&mut game.world);
+    game.world.apply_changes(Some((point3(1, 1, 1), BlockID(3))));
+    game.apply(&Command::Save);
+    assert_eq!(Chunk::read(point3(0, 0, 0), &root).unwrap().get_block(point3(1, 1, 1)).id,
+               BlockID(3));
+
+    drop(game);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -52,6 +52,11 @@ impl Camera {
         self.recompute();
     }
 
+    pub fn teleport(&mut self, position: Point3<f32>) {
+        self.position = position;
+        self.recompute();
+    }
+
     fn recompute(&mut self) {
         let (matrix, look_at) = phi_theta_pos_to_matrix(self.phi, self.theta, self.position);
         self.view_matrix = matrix;
@@ -0,0 +1,159 @@
+use gfx;
+
+use hotbar::Hotbar;
+use world::block::Face;
+use world::registry::Registry;
+use graphics::renderer::load_texture;
+
+gfx_vertex_struct!{
+    HudVertex {
+        position: [f32; 2] = "position",
+        uv: [f32; 2] = "uv",
+        color: [f32; 4] = "color",
+        textured: f32 = "textured",
+    }
+}
+
+gfx_pipeline!{
+    hud {
+        vbo: gfx::VertexBuffer<HudVertex> = (),
+        out_color: gfx::BlendTarget<gfx::format::Srgba8> =
+            ("ocolor", gfx::state::MASK_ALL, gfx::preset::blend::ALPHA),
+        block_texture: gfx::TextureSampler<[f32; 4]> = "block_texture",
+    }
+}
+
+const SLOT_SIZE: f32 = 48.0;
+const SLOT_GAP: f32 = 4.0;
+const ICON_INSET: f32 = 6.0;
+const BOTTOM_MARGIN: f32 = 16.0;
+
+const SLOT_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+const SELECTED_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const ICON_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Atlas cells per side, as the block models lay the atlas out.
+const ATLAS_CELLS: f32 = super::model::ATLAS_CELLS as f32;
+
+/// Draws the hotbar as a strip of slots along the bottom of the screen.
+pub struct HudRenderer<R: gfx::Resources> {
+    pso: gfx::pso::PipelineState<R, hud::Meta>,
+    data: hud::Data<R>,
+    slice: gfx::Slice<R>,
+    screen: (f32, f32),
+}
+
+impl<R: gfx::Resources> HudRenderer<R> {
+    pub fn new<F>(factory: &mut F,
+                  color_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
+                  screen: (u32, u32))
+                  -> HudRenderer<R>
+        where F: gfx::traits::FactoryExt<R>
+    {
+        let pso = factory.create_pipeline_simple(include_bytes!("../../resources/hud_vertex.glsl"),
+                                    include_bytes!("../../resources/hud_fragment.glsl"),
+                                    hud::new())
+            .unwrap();
+
+        let sampler_info = gfx::texture::SamplerInfo::new(gfx::texture::FilterMethod::Scale,
+                                                          gfx::texture::WrapMode::Clamp);
+        let empty: &[HudVertex] = &[];
+        let (vbo, slice) = factory.create_vertex_buffer_with_slice(empty, ());
+
+        let data = hud::Data {
+            vbo: vbo,
+            out_color: color_target,
+            block_texture: (load_texture(factory,
+                                         &include_bytes!("../../resources/textures/blocks.png")
+                                              [..])
+                                .unwrap(),
+                            factory.create_sampler(sampler_info)),
+        };
+
+        HudRenderer {
+            pso: pso,
+            data: data,
+            slice: slice,
+            screen: (screen.0 as f32, screen.1 as f32),
+        }
+    }
+
+    /// Sets the size of the screen in pixels. The strip keeps its old layout
+    /// until the next `set_hotbar`.
+    pub fn resize(&mut self, screen: (u32, u32)) {
+        self.screen = (screen.0 as f32, screen.1 as f32);
+    }
+
+    /// Rebuilds the strip; call whenever the hotbar or screen size changes.
+    pub fn set_hotbar<F>(&mut self, factory: &mut F, hotbar: &Hotbar, registry: &Registry)
+        where F: gfx::traits::FactoryExt<R>
+    {
+        let mut vertices = Vec::new();
+        let slots = hotbar.slots();
+        let width = slots.len() as f32 * (SLOT_SIZE + SLOT_GAP) - SLOT_GAP;
+        let left = (self.screen.0 - width) / 2.0;
+        let top = self.screen.1 - BOTTOM_MARGIN - SLOT_SIZE;
+
+        for (i, slot) in slots.iter().enumerate() {
+            let x = left + i as f32 * (SLOT_SIZE + SLOT_GAP);
+            let color = if i == hotbar.selected() {
+                SELECTED_COLOR
+            } else {
+                SLOT_COLOR
+            };
+            self.push_quad(&mut vertices, (x, top), SLOT_SIZE, None, color);
+
+            let texture = slot.and_then(|id| registry.lookup_texture(id));
+            if let Some(texture) = texture {
+                let cell = texture.get_face(Face::Front);
+                self.push_quad(&mut vertices,
+                               (x + ICON_INSET, top + ICON_INSET),
+                               SLOT_SIZE - 2.0 * ICON_INSET,
+                               Some((cell.x as f32, cell.y as f32)),
+                               ICON_COLOR);
+            }
+        }
+
+        let (vbo, slice) = factory.create_vertex_buffer_with_slice(&vertices, ());
+        self.data.vbo = vbo;
+        self.slice = slice;
+    }
+
+    /// Two triangles covering a square at pixel position `corner`, textured
+    /// with atlas `cell` if given.
+    fn push_quad(&self,
+                 out: &mut Vec<HudVertex>,
+                 corner: (f32, f32),
+                 size: f32,
+                 cell: Option<(f32, f32)>,
+                 color: [f32; 4]) {
+        let to_screen = |x: f32, y: f32| {
+            [x / self.screen.0 * 2.0 - 1.0, 1.0 - y / self.screen.1 * 2.0]
+        };
+        let (u, v, textured) = match cell {
+            Some((u, v)) => (u / ATLAS_CELLS, v / ATLAS_CELLS, 1.0),
+            None => (0.0, 0.0, 0.0),
+        };
+        let step = 1.0 / ATLAS_CELLS;
+
+        // Atlas rows run upwards, as on block faces
+        let corners = [(0.0, 0.0, u, v + step),
+                       (0.0, 1.0, u, v),
+                       (1.0, 1.0, u + step, v),
+                       (0.0, 0.0, u, v + step),
+                       (1.0, 1.0, u + step, v),
+                       (1.0, 0.0, u + step, v + step)];
+        for &(dx, dy, cu, cv) in &corners {
+            out.push(HudVertex {
+                position: to_screen(corner.0 + dx * size, corner.1 + dy * size),
+                uv: [cu, cv],
+                color: color,
+                textured: textured,
+            });
+        }
+    }
+
+    pub fn render<C: gfx::CommandBuffer<R>>(&self, encoder: &mut gfx::Encoder<R, C>) {
+        encoder.draw(&self.slice, &self.pso, &self.data);
+    }
+}
@@ -1,7 +1,11 @@
 mod model;
 mod renderer;
 mod camera;
+mod selection;
+mod hud;
 
-pub use self::renderer::Renderer;
+pub use self::renderer::{Renderer, Vertex};
 pub use self::camera::Camera;
-pub use self::model::Model;
+pub use self::model::{Model, build_vertices};
+pub use self::selection::selection_lines;
+pub use self::hud::HudRenderer;
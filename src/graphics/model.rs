@@ -27,51 +27,17 @@ const TEXTURE_SIZE: u16 = 64;
 const TEXTURE_NORMALIZER: u16 = (0x10000 / TEXTURE_SIZE as u32) as u16;
 const TEXEL_SIZE: u16 = 16;
 const TEXEL_NORMALIZER: u16 = TEXTURE_NORMALIZER * TEXEL_SIZE;
+/// Block textures per side of the atlas, for other users of its layout.
+pub const ATLAS_CELLS: u16 = TEXTURE_SIZE / TEXEL_SIZE;
 
 impl<R: gfx::Resources> Model<R> {
     pub fn new<F: gfx::traits::FactoryExt<R>>(factory: &mut F,
                                               chunk: &Chunk,
                                               registry: &Registry)
                                               -> Option<Model<R>> {
-        let mut verts = Vec::new();
-
-        for loc in chunk.iter() {
-            let block = chunk.get_block_local(loc);
-            if !block.is_empty() {
-                let texture = registry.lookup_texture(block.id)
-                    .expect("Could not find texture for block id");
-
-                for face in Face::iter() {
-                    if block.is_visible(*face) {
-                        let face_texture = texture.get_face(*face);
-                        let true_texture = point2(face_texture.x as u16 * TEXEL_NORMALIZER,
-                                                  face_texture.y as u16 * TEXEL_NORMALIZER);
-
-                        let light = block.face_light(*face);
-                        match *face {
-                            Face::Bottom => {
-                                make_bottom(loc, true_texture, light, &mut verts);
-                            }
-                            Face::Top => {
-                                make_top(loc, true_texture, light, &mut verts);
-                            }
-                            Face::Left => {
-                                make_left(loc, true_texture, light, &mut verts);
-                            }
-                            Face::Right => {
-                                make_right(loc, true_texture, light, &mut verts);
-                            }
-                            Face::Front => {
-                                make_front(loc, true_texture, light, &mut verts);
-                            }
-                            Face::Back => {
-                                make_back(loc, true_texture, light, &mut verts);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        let verts = build_vertices(chunk,
+                                   registry,
+                                   |loc, face| chunk.get_block_local(loc).is_visible(face));
 
         if verts.len() > 0 {
             let (vbo, slice) = factory.create_vertex_buffer_with_slice(verts.as_slice(), ());
@@ -89,6 +55,55 @@ impl<R: gfx::Resources> Model<R> {
     }
 }
 
+/// Meshes the non-empty blocks of `chunk` on the CPU, six vertices per face,
+/// in chunk-local coordinates. `visible` decides which faces are emitted;
+/// the renderer uses each block's own visibility flags.
+pub fn build_vertices<V>(chunk: &Chunk, registry: &Registry, visible: V) -> Vec<Vertex>
+    where V: Fn(Point3<u8>, Face) -> bool
+{
+    let mut verts = Vec::new();
+
+    for loc in chunk.iter() {
+        let block = chunk.get_block_local(loc);
+        if !block.is_empty() {
+            let texture = registry.lookup_texture(block.id)
+                .expect("Could not find texture for block id");
+
+            for face in Face::iter() {
+                if visible(loc, *face) {
+                    let face_texture = texture.get_face(*face);
+                    let true_texture = point2(face_texture.x as u16 * TEXEL_NORMALIZER,
+                                              face_texture.y as u16 * TEXEL_NORMALIZER);
+
+                    let light = block.face_light(*face);
+                    match *face {
+                        Face::Bottom => {
+                            make_bottom(loc, true_texture, light, &mut verts);
+                        }
+                        Face::Top => {
+                            make_top(loc, true_texture, light, &mut verts);
+                        }
+                        Face::Left => {
+                            make_left(loc, true_texture, light, &mut verts);
+                        }
+                        Face::Right => {
+                            make_right(loc, true_texture, light, &mut verts);
+                        }
+                        Face::Front => {
+                            make_front(loc, true_texture, light, &mut verts);
+                        }
+                        Face::Back => {
+                            make_back(loc, true_texture, light, &mut verts);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    verts
+}
+
 
 fn make_bottom(origin: Point3<u8>,
                texture: Point2<u16>,
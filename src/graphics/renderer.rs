@@ -36,12 +36,12 @@ pub struct Renderer<R: gfx::Resources> {
     pso: gfx::pso::PipelineState<R, pipe::Meta>,
     models: HashMap<WorldPoint, Model<R>>,
     data: pipe::Data<R>,
-    pub camera: Camera,
     pub projection: [[f32; 4]; 4],
+    pub sky_color: [f32; 4],
 }
 
 // Borrowed from a gfx example
-fn load_texture<R, F>(factory: &mut F,
+pub fn load_texture<R, F>(factory: &mut F,
                       data: &[u8])
                       -> Result<gfx::handle::ShaderResourceView<R, [f32; 4]>, String>
     where R: gfx::Resources,
@@ -103,9 +103,9 @@ impl<R: gfx::Resources> Renderer<R> {
         Renderer {
             pso: pso,
             models: HashMap::new(),
-            camera: Camera::new(point3(-1.0, 40.0, -1.0), 0.0, 0.0),
             data: data,
             projection: perspective(Deg(90.0), 1024 as f32 / 768 as f32, 0.1, 1000.0).into(),
+            sky_color: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
@@ -113,14 +113,16 @@ impl<R: gfx::Resources> Renderer<R> {
         self.models.insert(origin, models);
     }
 
-    pub fn render<C: gfx::CommandBuffer<R>>(&mut self, encoder: &mut gfx::Encoder<R, C>) {
-        encoder.clear(&self.data.out_color, [0.0, 0.0, 0.0, 1.0]);
+    pub fn render<C: gfx::CommandBuffer<R>>(&mut self,
+                                            encoder: &mut gfx::Encoder<R, C>,
+                                            camera: &Camera) {
+        encoder.clear(&self.data.out_color, self.sky_color);
         encoder.clear_depth(&self.data.out_depth_stencil, 1.0);
         encoder.clear_stencil(&self.data.out_depth_stencil, 0);
 
         for model in &self.models {
             self.data.model = model.1.model;
-            self.data.view = *self.camera.get_view_matrix();
+            self.data.view = *camera.get_view_matrix();
             self.data.projection = self.projection;
             self.data.vbo = model.1.vbo.clone();
 
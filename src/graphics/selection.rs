@@ -0,0 +1,68 @@
+use prelude::*;
+use world::WorldPoint;
+use world::block::Face;
+
+const OUTLINE_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+const FACE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Pushes the outline out a little so it is not hidden by the block itself.
+const INSET: f32 = 0.005;
+
+pub type Line = ([f32; 3], [f32; 3], [f32; 4]);
+
+/// Lines for a wireframe box around the block at `loc`, with the `face` the
+/// ray entered through outlined and crossed in a brighter colour.
+pub fn selection_lines(loc: WorldPoint, face: Option<Face>) -> Vec<Line> {
+    let low = [loc.x as f32 - INSET, loc.y as f32 - INSET, loc.z as f32 - INSET];
+    let high = [loc.x as f32 + 1.0 + INSET,
+                loc.y as f32 + 1.0 + INSET,
+                loc.z as f32 + 1.0 + INSET];
+    let corner = |i: usize| {
+        [if i & 1 == 0 { low[0] } else { high[0] },
+         if i & 2 == 0 { low[1] } else { high[1] },
+         if i & 4 == 0 { low[2] } else { high[2] }]
+    };
+
+    // Corners that differ in exactly one bit share an edge
+    let mut lines = Vec::new();
+    for a in 0..8 {
+        for bit in &[1, 2, 4] {
+            if a & bit == 0 {
+                lines.push((corner(a), corner(a | bit), OUTLINE_COLOR));
+            }
+        }
+    }
+
+    if let Some(face) = face {
+        let normal = face.normal();
+        let (axis, high_side) = if normal.x != 0 {
+            (1, normal.x > 0)
+        } else if normal.y != 0 {
+            (2, normal.y > 0)
+        } else {
+            (4, normal.z > 0)
+        };
+
+        let on_face: Vec<usize> =
+            (0..8).filter(|&i| (i & axis != 0) == high_side).collect();
+        for &a in &on_face {
+            for &b in &on_face {
+                let differing = (a ^ b).count_ones();
+                if a < b && (differing == 1 || differing == 2) {
+                    lines.push((corner(a), corner(b), FACE_COLOR));
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+#[test]
+fn outline() {
+    assert_eq!(selection_lines(point3(0, 0, 0), None).len(), 12);
+    // Four edges and two diagonals on the face
+    let lines = selection_lines(point3(-1, 2, 3), Some(Face::Top));
+    assert_eq!(lines.len(), 18);
+    assert!(lines[12..].iter().all(|l| l.0[1] > 3.0 && l.1[1] > 3.0));
+}
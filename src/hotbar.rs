@@ -0,0 +1,81 @@
+use world::block::BlockID;
+use world::registry::Registry;
+
+pub const SLOT_COUNT: usize = 9;
+
+/// The row of blocks the player can place, and which one is in hand.
+pub struct Hotbar {
+    slots: [Option<BlockID>; SLOT_COUNT],
+    selected: usize,
+}
+
+impl Hotbar {
+    /// Fills the slots with the first registered blocks.
+    pub fn new(registry: &Registry) -> Hotbar {
+        let mut slots = [None; SLOT_COUNT];
+        for (slot, id) in slots.iter_mut().zip(registry.block_ids()) {
+            *slot = Some(id);
+        }
+
+        Hotbar {
+            slots: slots,
+            selected: 0,
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<BlockID>] {
+        &self.slots
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The block that placing will use, if the selected slot is not empty.
+    pub fn current(&self) -> Option<BlockID> {
+        self.slots[self.selected]
+    }
+
+    pub fn select(&mut self, slot: usize) {
+        if slot < SLOT_COUNT {
+            self.selected = slot;
+        }
+    }
+
+    /// Moves the selection by `steps` slots, wrapping at either end.
+    pub fn scroll(&mut self, steps: i32) {
+        let count = SLOT_COUNT as i32;
+        self.selected = ((self.selected as i32 + steps) % count + count) as usize % SLOT_COUNT;
+    }
+
+    /// Selects the slot holding `id`, or puts `id` in the selected slot if
+    /// no slot has it.
+    pub fn pick(&mut self, id: BlockID) {
+        match self.slots.iter().position(|&slot| slot == Some(id)) {
+            Some(slot) => self.selected = slot,
+            None => self.slots[self.selected] = Some(id),
+        }
+    }
+}
+
+#[test]
+fn selection() {
+    let registry = Registry::new();
+    let mut hotbar = Hotbar::new(&registry);
+    assert_eq!(hotbar.current(), registry.lookup_id(&"stone".into()));
+
+    hotbar.scroll(-1);
+    assert_eq!(hotbar.selected(), SLOT_COUNT - 1);
+    assert!(hotbar.current().is_none());
+    hotbar.scroll(SLOT_COUNT as i32 + 2);
+    assert_eq!(hotbar.selected(), 1);
+
+    let grass = registry.lookup_id(&"grass".into()).unwrap();
+    hotbar.pick(grass);
+    assert_eq!(hotbar.current(), Some(grass));
+    assert_eq!(hotbar.selected(), 2);
+
+    hotbar.select(8);
+    hotbar.pick(BlockID(99));
+    assert_eq!(hotbar.slots()[8], Some(BlockID(99)));
+}
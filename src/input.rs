@@ -1,5 +1,9 @@
-use glutin::{Event, ElementState, VirtualKeyCode, MouseButton};
+use std::collections::HashMap;
+use std::path::Path;
+
+use glutin::{Event, ElementState, VirtualKeyCode, MouseButton, MouseScrollDelta};
 use cgmath::{Vector3, vec3, Vector2, vec2};
+use rustc_serialize::json::Json;
 
 pub enum Command {
     Noop,
@@ -9,6 +13,12 @@ pub enum Command {
     Place,
     Break,
     Save,
+    Undo,
+    Redo,
+    SelectSlot(usize),
+    ScrollHotbar(i32),
+    PickBlock,
+    ToggleConsole,
 }
 
 pub enum State {
@@ -16,47 +26,454 @@ pub enum State {
     Stop,
 }
 
-pub fn glutin_event_to_command(event: Event) -> Command {
-    match event {
-        Event::Closed => Command::Exit,
-        Event::KeyboardInput(state, _, key) => glutin_key_to_command(state, key),
-        Event::MouseMoved(x, y) => Command::CameraLook(vec2(x as f32, y as f32)),
-        Event::MouseInput(ElementState::Pressed, MouseButton::Right) => Command::Place,
-        Event::MouseInput(ElementState::Pressed, MouseButton::Left) => Command::Break,
-        _ => Command::Noop,
+/// The bindings used when no file overrides them.
+pub const DEFAULT_BINDINGS: &'static str = include_str!("../resources/bindings.json");
+
+/// Something that can be bound: a key, a mouse button or a wheel direction.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    fn contains(&self, other: Modifiers) -> bool {
+        (self.ctrl || !other.ctrl) && (self.shift || !other.shift) && (self.alt || !other.alt)
+    }
+
+    fn count(&self) -> usize {
+        self.ctrl as usize + self.shift as usize + self.alt as usize
     }
 }
 
-fn glutin_key_to_command(state: ElementState, key: Option<VirtualKeyCode>) -> Command {
-    match key {
-        Some(VirtualKeyCode::Escape) => Command::Exit,
-        Some(VirtualKeyCode::S) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(-1.0, 0.0, 0.0))
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Trigger {
+    pub input: Input,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Exit,
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    Place,
+    Break,
+    PickBlock,
+    Save,
+    Undo,
+    Redo,
+    HotbarNext,
+    HotbarPrevious,
+    Slot(usize),
+    Console,
+}
+
+const ACTIONS: &'static [(&'static str, Action)] =
+    &[("exit", Action::Exit),
+      ("forward", Action::Forward),
+      ("back", Action::Back),
+      ("left", Action::Left),
+      ("right", Action::Right),
+      ("up", Action::Up),
+      ("down", Action::Down),
+      ("place", Action::Place),
+      ("break", Action::Break),
+      ("pick_block", Action::PickBlock),
+      ("save", Action::Save),
+      ("undo", Action::Undo),
+      ("redo", Action::Redo),
+      ("hotbar_next", Action::HotbarNext),
+      ("hotbar_previous", Action::HotbarPrevious),
+      ("slot_1", Action::Slot(0)),
+      ("slot_2", Action::Slot(1)),
+      ("slot_3", Action::Slot(2)),
+      ("slot_4", Action::Slot(3)),
+      ("slot_5", Action::Slot(4)),
+      ("slot_6", Action::Slot(5)),
+      ("slot_7", Action::Slot(6)),
+      ("slot_8", Action::Slot(7)),
+      ("slot_9", Action::Slot(8)),
+      ("console", Action::Console)];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|a| a.0 == name).map(|a| a.1)
+    }
+
+    pub fn name(&self) -> &'static str {
+        ACTIONS.iter().find(|a| a.1 == *self).unwrap().0
+    }
+
+    /// Camera movement, which lasts for as long as the input is held.
+    fn movement(&self) -> Option<Vector3<f32>> {
+        match *self {
+            Action::Forward => Some(vec3(1.0, 0.0, 0.0)),
+            Action::Back => Some(vec3(-1.0, 0.0, 0.0)),
+            Action::Right => Some(vec3(0.0, 0.0, 1.0)),
+            Action::Left => Some(vec3(0.0, 0.0, -1.0)),
+            Action::Up => Some(vec3(0.0, 1.0, 0.0)),
+            Action::Down => Some(vec3(0.0, -1.0, 0.0)),
+            _ => None,
+        }
+    }
+
+    fn command(&self) -> Command {
+        match *self {
+            Action::Exit => Command::Exit,
+            Action::Place => Command::Place,
+            Action::Break => Command::Break,
+            Action::PickBlock => Command::PickBlock,
+            Action::Save => Command::Save,
+            Action::Undo => Command::Undo,
+            Action::Redo => Command::Redo,
+            Action::HotbarNext => Command::ScrollHotbar(1),
+            Action::HotbarPrevious => Command::ScrollHotbar(-1),
+            Action::Slot(slot) => Command::SelectSlot(slot),
+            Action::Console => Command::ToggleConsole,
+            movement => Command::CameraTranslate(State::Start, movement.movement().unwrap()),
+        }
+    }
+}
+
+/// Turns window events into commands according to a set of bindings,
+/// tracking held modifiers and movement keys along the way.
+pub struct Bindings {
+    bindings: Vec<(Trigger, Action)>,
+    modifiers: Modifiers,
+    held: HashMap<Input, Action>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        Bindings::from_json(&Json::from_str(DEFAULT_BINDINGS).unwrap()).unwrap()
+    }
+
+    /// Parses an object mapping action names to lists of triggers such as
+    /// `"W"`, `"Ctrl+Z"`, `"MouseLeft"` or `"WheelUp"`.
+    pub fn from_json(json: &Json) -> Result<Bindings, String> {
+        let object = try!(json.as_object().ok_or("Bindings must be an object"));
+        let mut bindings = Vec::new();
+        for (name, triggers) in object {
+            let action = try!(Action::from_name(name)
+                .ok_or(format!("Unknown action \"{}\"", name)));
+            let triggers = try!(triggers.as_array()
+                .ok_or(format!("Bindings for \"{}\" must be a list", name)));
+            for trigger in triggers {
+                let trigger = try!(trigger.as_string()
+                    .ok_or(format!("Bindings for \"{}\" must be strings", name)));
+                bindings.push((try!(parse_trigger(trigger)), action));
+            }
         }
-        Some(VirtualKeyCode::W) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(1.0, 0.0, 0.0))
+
+        Ok(Bindings {
+            bindings: bindings,
+            modifiers: Modifiers::default(),
+            held: HashMap::new(),
+        })
+    }
+
+    /// Reads bindings from `path`. Actions the file does not mention keep
+    /// their default bindings; an empty list unbinds an action.
+    pub fn load(path: &Path) -> Result<Bindings, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let mut text = String::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+        let json = try!(Json::from_str(&text)
+            .map_err(|e| format!("Could not parse bindings {:?}: {}", path, e)));
+
+        let overrides = try!(Bindings::from_json(&json));
+        let mentioned: Vec<Action> = json.as_object()
+            .unwrap()
+            .keys()
+            .filter_map(|name| Action::from_name(name))
+            .collect();
+        let mut bindings = Bindings::new();
+        bindings.bindings.retain(|&(_, action)| !mentioned.contains(&action));
+        bindings.bindings.extend(overrides.bindings);
+        Ok(bindings)
+    }
+
+    /// Describes every trigger bound to more than one action, and every key
+    /// bound to an action while also serving as a modifier in another
+    /// binding, since holding it for one sets off the other.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut by_trigger: HashMap<Trigger, Vec<Action>> = HashMap::new();
+        for &(trigger, action) in &self.bindings {
+            let actions = by_trigger.entry(trigger).or_insert_with(Vec::new);
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
         }
-        Some(VirtualKeyCode::D) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(0.0, 0.0, 1.0))
+
+        let mut conflicts: Vec<String> = by_trigger.into_iter()
+            .filter(|&(_, ref actions)| actions.len() > 1)
+            .map(|(trigger, actions)| {
+                let names: Vec<&str> = actions.iter().map(|a| a.name()).collect();
+                format!("{} is bound to {}", trigger_name(trigger), names.join(", "))
+            })
+            .collect();
+
+        for &(trigger, action) in &self.bindings {
+            let modifier = match trigger.input {
+                Input::Key(key) => modifier_for(key),
+                _ => None,
+            };
+            let modifier = match modifier {
+                Some(modifier) => modifier,
+                None => continue,
+            };
+            let mut users = Vec::new();
+            for &(other, other_action) in &self.bindings {
+                if other.modifiers.contains(modifier) && !users.contains(&other_action.name()) {
+                    users.push(other_action.name());
+                }
+            }
+            if !users.is_empty() {
+                conflicts.push(format!("{} is bound to {} but is also a modifier for {}",
+                                       trigger_name(trigger),
+                                       action.name(),
+                                       users.join(", ")));
+            }
         }
-        Some(VirtualKeyCode::A) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(0.0, 0.0, -1.0))
+        conflicts.sort();
+        conflicts
+    }
+
+    /// The action for `input` given the held modifiers. A binding needing
+    /// more of the held modifiers wins, so `Ctrl+Z` beats a plain `Z`.
+    fn lookup(&self, input: Input, modifiers: Modifiers) -> Option<Action> {
+        self.bindings
+            .iter()
+            .filter(|&&(trigger, _)| {
+                trigger.input == input && modifiers.contains(trigger.modifiers)
+            })
+            .max_by_key(|&&(trigger, _)| trigger.modifiers.count())
+            .map(|&(_, action)| action)
+    }
+
+    pub fn command_for(&mut self, event: Event) -> Command {
+        match event {
+            Event::Closed => Command::Exit,
+            Event::MouseMoved(x, y) => Command::CameraLook(vec2(x as f32, y as f32)),
+            Event::KeyboardInput(state, _, Some(key)) => {
+                self.track_modifier(key, state);
+                self.input_changed(Input::Key(key), state)
+            }
+            Event::MouseInput(state, button) => self.input_changed(Input::Mouse(button), state),
+            Event::MouseWheel(delta, _) => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(_, y) => y / 16.0,
+                };
+                let input = if lines > 0.0 {
+                    Input::WheelUp
+                } else if lines < 0.0 {
+                    Input::WheelDown
+                } else {
+                    return Command::Noop;
+                };
+                match self.lookup(input, self.modifiers) {
+                    Some(action) if action.movement().is_none() => action.command(),
+                    _ => Command::Noop,
+                }
+            }
+            _ => Command::Noop,
         }
-        Some(VirtualKeyCode::Space) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(0.0, 1.0, 0.0))
+    }
+
+    fn input_changed(&mut self, input: Input, state: ElementState) -> Command {
+        match state {
+            ElementState::Pressed => {
+                if self.held.contains_key(&input) {
+                    // Key repeat; movement is already under way
+                    return Command::Noop;
+                }
+
+                match self.lookup(input, self.modifiers) {
+                    Some(action) => {
+                        if action.movement().is_some() {
+                            self.held.insert(input, action);
+                        }
+                        action.command()
+                    }
+                    None => Command::Noop,
+                }
+            }
+            ElementState::Released => {
+                match self.held.remove(&input) {
+                    Some(action) => {
+                        Command::CameraTranslate(State::Stop, action.movement().unwrap())
+                    }
+                    None => Command::Noop,
+                }
+            }
         }
-        Some(VirtualKeyCode::LShift) => {
-            Command::CameraTranslate(glutin_state_to_state(state), vec3(0.0, -1.0, 0.0))
+    }
+
+    /// Forgets held keys and modifiers, for when input is taken elsewhere
+    /// and their releases will not be seen.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.modifiers = Modifiers::default();
+    }
+
+    fn track_modifier(&mut self, key: VirtualKeyCode, state: ElementState) {
+        let down = state == ElementState::Pressed;
+        match key {
+            VirtualKeyCode::LControl | VirtualKeyCode::RControl => self.modifiers.ctrl = down,
+            VirtualKeyCode::LShift | VirtualKeyCode::RShift => self.modifiers.shift = down,
+            VirtualKeyCode::LAlt | VirtualKeyCode::RAlt => self.modifiers.alt = down,
+            _ => (),
         }
-        Some(VirtualKeyCode::F3) => Command::Save,
-        _ => Command::Noop,
     }
 }
 
+/// The modifier that `key` holds down, if any.
+fn modifier_for(key: VirtualKeyCode) -> Option<Modifiers> {
+    let mut modifier = Modifiers::default();
+    match key {
+        VirtualKeyCode::LControl | VirtualKeyCode::RControl => modifier.ctrl = true,
+        VirtualKeyCode::LShift | VirtualKeyCode::RShift => modifier.shift = true,
+        VirtualKeyCode::LAlt | VirtualKeyCode::RAlt => modifier.alt = true,
+        _ => return None,
+    }
+    Some(modifier)
+}
+
+const NAMED_KEYS: &'static [VirtualKeyCode] =
+    &[VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+     VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+     VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+     VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+     VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+     VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+     VirtualKeyCode::Y, VirtualKeyCode::Z, VirtualKeyCode::Key0, VirtualKeyCode::Key1,
+     VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4, VirtualKeyCode::Key5,
+     VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+     VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4,
+     VirtualKeyCode::F5, VirtualKeyCode::F6, VirtualKeyCode::F7, VirtualKeyCode::F8,
+     VirtualKeyCode::F9, VirtualKeyCode::F10, VirtualKeyCode::F11, VirtualKeyCode::F12,
+     VirtualKeyCode::Escape, VirtualKeyCode::Space, VirtualKeyCode::Return,
+     VirtualKeyCode::Tab, VirtualKeyCode::Back, VirtualKeyCode::Delete, VirtualKeyCode::Insert,
+     VirtualKeyCode::Home, VirtualKeyCode::End, VirtualKeyCode::PageUp,
+     VirtualKeyCode::PageDown, VirtualKeyCode::Up, VirtualKeyCode::Down, VirtualKeyCode::Left,
+     VirtualKeyCode::Right, VirtualKeyCode::LShift, VirtualKeyCode::RShift,
+     VirtualKeyCode::LControl, VirtualKeyCode::RControl, VirtualKeyCode::LAlt,
+     VirtualKeyCode::RAlt, VirtualKeyCode::Grave, VirtualKeyCode::Minus,
+     VirtualKeyCode::Equals, VirtualKeyCode::Comma, VirtualKeyCode::Period,
+     VirtualKeyCode::Slash];
+
+const NAMED_BUTTONS: &'static [(&'static str, MouseButton)] = &[("MouseLeft", MouseButton::Left),
+                                                         ("MouseRight", MouseButton::Right),
+                                                         ("MouseMiddle", MouseButton::Middle)];
+
+fn parse_input(name: &str) -> Option<Input> {
+    match name {
+        "WheelUp" => return Some(Input::WheelUp),
+        "WheelDown" => return Some(Input::WheelDown),
+        _ => (),
+    }
+
+    if let Some(&(_, button)) = NAMED_BUTTONS.iter().find(|b| b.0 == name) {
+        return Some(Input::Mouse(button));
+    }
+    NAMED_KEYS.iter().find(|&key| format!("{:?}", key) == name).map(|&key| Input::Key(key))
+}
+
+/// Parses a trigger such as `"Ctrl+Shift+S"`.
+pub fn parse_trigger(text: &str) -> Result<Trigger, String> {
+    let mut parts: Vec<&str> = text.split('+').map(|p| p.trim()).collect();
+    let input_name = parts.pop().unwrap();
+
+    let mut modifiers = Modifiers::default();
+    for part in parts {
+        match part {
+            "Ctrl" => modifiers.ctrl = true,
+            "Shift" => modifiers.shift = true,
+            "Alt" => modifiers.alt = true,
+            other => return Err(format!("Unknown modifier \"{}\" in \"{}\"", other, text)),
+        }
+    }
 
-fn glutin_state_to_state(state: ElementState) -> State {
-    match state {
-        ElementState::Pressed => State::Start,
-        ElementState::Released => State::Stop,
+    let input = try!(parse_input(input_name)
+        .ok_or(format!("Unknown key or button \"{}\" in \"{}\"", input_name, text)));
+    Ok(Trigger {
+        input: input,
+        modifiers: modifiers,
+    })
+}
+
+fn trigger_name(trigger: Trigger) -> String {
+    let mut name = String::new();
+    if trigger.modifiers.ctrl {
+        name.push_str("Ctrl+");
+    }
+    if trigger.modifiers.shift {
+        name.push_str("Shift+");
     }
+    if trigger.modifiers.alt {
+        name.push_str("Alt+");
+    }
+    match trigger.input {
+        Input::Key(key) => name.push_str(&format!("{:?}", key)),
+        Input::Mouse(button) => {
+            name.push_str(NAMED_BUTTONS.iter().find(|b| b.1 == button).map_or("Mouse", |b| b.0))
+        }
+        Input::WheelUp => name.push_str("WheelUp"),
+        Input::WheelDown => name.push_str("WheelDown"),
+    }
+    name
+}
+
+#[test]
+fn triggers() {
+    let trigger = parse_trigger("Ctrl+Shift+S").unwrap();
+    assert_eq!(trigger.input, Input::Key(VirtualKeyCode::S));
+    assert!(trigger.modifiers.ctrl && trigger.modifiers.shift && !trigger.modifiers.alt);
+    assert_eq!(trigger_name(trigger), "Ctrl+Shift+S");
+    assert_eq!(parse_trigger("MouseLeft").unwrap().input, Input::Mouse(MouseButton::Left));
+    assert!(parse_trigger("Hyper+S").is_err());
+    assert!(parse_trigger("Banana").is_err());
+}
+
+#[test]
+fn modifiers_and_conflicts() {
+    let mut bindings = Bindings::from_json(&Json::from_str(r#"{ "undo": ["Ctrl+Z"],
+                                                                "save": ["Z", "F3"],
+                                                                "exit": ["F3"] }"#)
+            .unwrap())
+        .unwrap();
+    assert_eq!(bindings.conflicts(), vec!["F3 is bound to exit, save".to_string()]);
+
+    let ctrl = Modifiers { ctrl: true, ..Modifiers::default() };
+    let z = Input::Key(VirtualKeyCode::Z);
+    assert_eq!(bindings.lookup(z, Modifiers::default()), Some(Action::Save));
+    assert_eq!(bindings.lookup(z, ctrl), Some(Action::Undo));
+
+    // Holding a modifier does not stop plain bindings from firing
+    bindings.modifiers.shift = true;
+    let shift = bindings.modifiers;
+    assert_eq!(bindings.lookup(z, shift), Some(Action::Save));
+
+    // The defaults keep sinking on LShift, which redo also uses as a modifier
+    assert_eq!(Bindings::new().conflicts(),
+               vec!["LShift is bound to down but is also a modifier for redo".to_string()]);
 }
@@ -15,6 +15,8 @@ extern crate clap;
 extern crate image;
 extern crate noise;
 extern crate num;
+extern crate rand;
+extern crate rhai;
 
 extern crate gfx_debug_draw;
 extern crate gfx_text;
@@ -30,16 +32,36 @@ mod input;
 mod prelude;
 mod world;
 mod logger;
+mod tools;
+mod hotbar;
+mod game;
+mod replay;
+mod console;
+mod script;
+mod net;
+mod autosave;
 
 use prelude::*;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 fn main() {
-    use gfx::Device;
-
     logger::init().unwrap();
 
+    let world_arg = Arg::with_name("WORLD")
+        .help("Path to world directory")
+        .required(true);
+    let from_arg = Arg::with_name("from")
+        .help("One corner of the box, as x,y,z")
+        .long("from")
+        .takes_value(true)
+        .required(true);
+    let to_arg = Arg::with_name("to")
+        .help("Opposite corner of the box, as x,y,z")
+        .long("to")
+        .takes_value(true)
+        .required(true);
+
     let matches = App::new("subsurface")
         .version("0.1.0")
         .about("Rust voxel engine")
@@ -50,13 +72,410 @@ fn main() {
             .takes_value(true)
             .default_value("test_world"))
         .arg(Arg::with_name("generator")
-            .help("Use flat terrain generator")
+            .help("Terrain generator for new worlds: flat, flat:<layers> (e.g. \
+                   flat:1*stone,3*dirt,1*grass, where the flat: prefix may be left out), \
+                   simplex or a path to a preset file")
             .long("generator")
             .short("g")
             .takes_value(true)
             .default_value("simplex"))
+        .arg(Arg::with_name("bindings")
+            .help("Key bindings file; actions it leaves out keep their defaults")
+            .long("bindings")
+            .takes_value(true)
+            .default_value("bindings.json"))
+        .arg(Arg::with_name("record")
+            .help("Record every input command to this file")
+            .long("record")
+            .takes_value(true))
+        .arg(Arg::with_name("replay")
+            .help("Play back a recording instead of taking live input")
+            .long("replay")
+            .takes_value(true)
+            .conflicts_with("record"))
+        .arg(Arg::with_name("autosave")
+            .help("Seconds between saves of changed chunks, or 0 to only save on exit. A \
+                   replay saves nothing unless this is given")
+            .long("autosave")
+            .takes_value(true)
+            .default_value("60"))
+        .subcommand(SubCommand::with_name("map")
+            .about("Render a top-down map of a saved world to a PNG")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("output")
+                .help("PNG file to write")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .default_value("map.png")))
+        .subcommand(SubCommand::with_name("generate")
+            .about("Generate and save every chunk in a box")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("generator")
+                .help("Terrain generator if the world is new")
+                .long("generator")
+                .short("g")
+                .takes_value(true)
+                .default_value("simplex")))
+        .subcommand(SubCommand::with_name("info")
+            .about("Show chunk count, size on disk, block histogram and metadata")
+            .arg(world_arg.clone()))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Decode every chunk and report corrupt ones")
+            .arg(world_arg.clone()))
+        .subcommand(SubCommand::with_name("export-obj")
+            .about("Export a box of blocks to a Wavefront OBJ")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("output")
+                .help("OBJ file to write; the MTL and atlas are written beside it")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .default_value("region.obj")))
+        .subcommand(SubCommand::with_name("export-gltf")
+            .about("Export a box of blocks to a binary glTF 2.0 file")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("output")
+                .help("GLB file to write")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .default_value("region.glb")))
+        .subcommand(SubCommand::with_name("import-vox")
+            .about("Place a MagicaVoxel model in a world")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("VOX")
+                .help("MagicaVoxel file to import")
+                .required(true))
+            .arg(Arg::with_name("at")
+                .help("World position of the model's minimum corner, as x,y,z")
+                .long("at")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("mapping")
+                .help("File mapping palette indices to block names")
+                .long("mapping")
+                .short("m")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("export-vox")
+            .about("Export a box of blocks to a MagicaVoxel file")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("mapping")
+                .help("File mapping palette indices to block names")
+                .long("mapping")
+                .short("m")
+                .takes_value(true))
+            .arg(Arg::with_name("output")
+                .help("VOX file to write")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .default_value("region.vox")))
+        .subcommand(SubCommand::with_name("prefab-save")
+            .about("Save a box of blocks as a prefab")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("output")
+                .help("Prefab file to write")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("prefab-paste")
+            .about("Paste a prefab into a world")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("PREFAB")
+                .help("Prefab file to paste")
+                .required(true))
+            .arg(Arg::with_name("at")
+                .help("World position of the pasted box's minimum corner, as x,y,z")
+                .long("at")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("rotate")
+                .help("Clockwise quarter turns about the vertical axis")
+                .long("rotate")
+                .takes_value(true)
+                .possible_values(&["0", "1", "2", "3"])
+                .default_value("0"))
+            .arg(Arg::with_name("mirror-x")
+                .help("Mirror along the x axis")
+                .long("mirror-x"))
+            .arg(Arg::with_name("mirror-z")
+                .help("Mirror along the z axis")
+                .long("mirror-z"))
+            .arg(Arg::with_name("skip-air")
+                .help("Keep existing blocks where the prefab has air")
+                .long("skip-air")))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Play back a recording against a world without opening a window")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("RECORDING")
+                .help("Recording made with --record")
+                .required(true))
+            .arg(Arg::with_name("generator")
+                .help("Terrain generator if the world is new")
+                .long("generator")
+                .takes_value(true)
+                .default_value("simplex"))
+            .arg(Arg::with_name("save")
+                .help("Save the world once the recording has played")
+                .long("save")))
+        .subcommand(SubCommand::with_name("script")
+            .about("Run a Rhai script against a box of a saved world")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("SCRIPT")
+                .help("Script to run; it sees the world as `world`")
+                .required(true))
+            .arg(from_arg.clone())
+            .arg(to_arg.clone())
+            .arg(Arg::with_name("dry-run")
+                .help("Run the script without saving its changes")
+                .long("dry-run")))
+        .subcommand(SubCommand::with_name("server")
+            .about("Share a world with clients over TCP, without opening a window")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("bind")
+                .help("Address to listen on; use 0.0.0.0:25590 to let other machines join")
+                .long("bind")
+                .takes_value(true)
+                .default_value("127.0.0.1:25590"))
+            .arg(Arg::with_name("generator")
+                .help("Terrain generator if the world is new")
+                .long("generator")
+                .takes_value(true)
+                .default_value("simplex"))
+            .arg(Arg::with_name("save-interval")
+                .help("Seconds between saves of changed chunks")
+                .long("save-interval")
+                .takes_value(true)
+                .default_value("60")))
+        .subcommand(SubCommand::with_name("probe")
+            .about("Connect to a server, fetch a chunk and print what comes back")
+            .arg(Arg::with_name("ADDRESS")
+                .help("Server to connect to, as host:port")
+                .required(true))
+            .arg(Arg::with_name("name")
+                .help("Player name to connect as")
+                .long("name")
+                .takes_value(true)
+                .default_value("probe"))
+            .arg(Arg::with_name("at")
+                .help("Where to stand, as x,y,z; the chunk around it is fetched")
+                .long("at")
+                .takes_value(true)
+                .default_value("0,32,0"))
+            .arg(Arg::with_name("set")
+                .help("Set a block first, as x,y,z=block")
+                .long("set")
+                .takes_value(true))
+            .arg(Arg::with_name("wait")
+                .help("Seconds to wait for further messages")
+                .long("wait")
+                .takes_value(true)
+                .default_value("2")))
+        .subcommand(SubCommand::with_name("prune")
+            .about("Delete saved chunks outside a box")
+            .arg(world_arg.clone())
+            .arg(from_arg.clone())
+            .arg(to_arg.clone()))
+        .subcommand(SubCommand::with_name("snapshot")
+            .about("Archive the saved chunks and metadata, storing only what changed since the \
+                    last snapshot")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("full")
+                .help("Store every chunk, not just the changed ones")
+                .long("full")))
+        .subcommand(SubCommand::with_name("snapshot-list")
+            .about("List a world's snapshots, oldest first")
+            .arg(world_arg.clone()))
+        .subcommand(SubCommand::with_name("snapshot-restore")
+            .about("Put a world back as it was when a snapshot was taken")
+            .arg(world_arg.clone())
+            .arg(Arg::with_name("NAME")
+                .help("Snapshot to restore, as shown by snapshot-list")
+                .required(true)))
         .get_matches();
 
+    let result = match matches.subcommand() {
+        ("", None) => run_client(&matches),
+        ("probe", Some(sub_matches)) => run_probe(sub_matches),
+        (name, Some(sub_matches)) => run_tool(name, sub_matches),
+        _ => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs a headless subcommand. None of these create a window.
+fn run_tool(name: &str, matches: &ArgMatches) -> Result<(), String> {
+    let world_path = Path::new(matches.value_of("WORLD").unwrap());
+    let corners = || -> Result<(world::WorldPoint, world::WorldPoint), String> {
+        Ok((try!(tools::parse_point(matches.value_of("from").unwrap())),
+            try!(tools::parse_point(matches.value_of("to").unwrap()))))
+    };
+
+    match name {
+        "map" => tools::map::render(world_path, Path::new(matches.value_of("output").unwrap())),
+        "generate" => {
+            let (from, to) = try!(corners());
+            tools::manage::generate(world_path,
+                                    from,
+                                    to,
+                                    matches.value_of("generator").unwrap())
+        }
+        "info" => tools::manage::info(world_path),
+        "verify" => tools::manage::verify(world_path),
+        "export-obj" => {
+            let (from, to) = try!(corners());
+            tools::obj::export(world_path,
+                               from,
+                               to,
+                               Path::new(matches.value_of("output").unwrap()))
+        }
+        "export-gltf" => {
+            let (from, to) = try!(corners());
+            tools::gltf::export(world_path,
+                                from,
+                                to,
+                                Path::new(matches.value_of("output").unwrap()))
+        }
+        "import-vox" => {
+            tools::magica::import(world_path,
+                                  Path::new(matches.value_of("VOX").unwrap()),
+                                  try!(tools::parse_point(matches.value_of("at").unwrap())),
+                                  Path::new(matches.value_of("mapping").unwrap()))
+        }
+        "export-vox" => {
+            let (from, to) = try!(corners());
+            tools::magica::export(world_path,
+                                  from,
+                                  to,
+                                  matches.value_of("mapping").map(Path::new),
+                                  Path::new(matches.value_of("output").unwrap()))
+        }
+        "prefab-save" => {
+            let (from, to) = try!(corners());
+            tools::prefab::save(world_path,
+                                from,
+                                to,
+                                Path::new(matches.value_of("output").unwrap()))
+        }
+        "prefab-paste" => {
+            let options = world::prefab::PasteOptions {
+                rotation: matches.value_of("rotate").unwrap().parse().unwrap(),
+                mirror_x: matches.is_present("mirror-x"),
+                mirror_z: matches.is_present("mirror-z"),
+                skip_air: matches.is_present("skip-air"),
+            };
+            tools::prefab::paste(world_path,
+                                 Path::new(matches.value_of("PREFAB").unwrap()),
+                                 try!(tools::parse_point(matches.value_of("at").unwrap())),
+                                 options)
+        }
+        "prune" => {
+            let (from, to) = try!(corners());
+            tools::manage::prune(world_path, from, to)
+        }
+        "replay" => {
+            tools::replay::run(world_path,
+                               Path::new(matches.value_of("RECORDING").unwrap()),
+                               matches.value_of("generator").unwrap(),
+                               matches.is_present("save"))
+        }
+        "script" => {
+            let (from, to) = try!(corners());
+            tools::script::run(world_path,
+                               Path::new(matches.value_of("SCRIPT").unwrap()),
+                               from,
+                               to,
+                               matches.is_present("dry-run"))
+        }
+        "server" => {
+            let interval = try!(matches.value_of("save-interval")
+                .unwrap()
+                .parse::<u64>()
+                .map_err(|_| "The save interval must be a whole number of seconds".to_string()));
+            let generator = matches.value_of("generator").unwrap();
+            let world = try!(world::World::open(world_path, generator, world::Access::ReadWrite));
+            let mut server = try!(net::Server::bind(world, matches.value_of("bind").unwrap()));
+            server.save_interval = std::time::Duration::from_secs(interval);
+            server.run();
+            Ok(())
+        }
+        "snapshot" => tools::snapshot::create(world_path, matches.is_present("full")),
+        "snapshot-list" => tools::snapshot::list(world_path),
+        "snapshot-restore" => {
+            tools::snapshot::restore(world_path, matches.value_of("NAME").unwrap())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Runs the `probe` subcommand, the one tool that talks to a server rather
+/// than opening a world directory.
+fn run_probe(matches: &ArgMatches) -> Result<(), String> {
+    let set = match matches.value_of("set") {
+        Some(text) => {
+            let mut parts = text.splitn(2, '=');
+            let loc = try!(tools::parse_point(parts.next().unwrap()));
+            let block = try!(parts.next()
+                .ok_or(format!("Expected x,y,z=block but got \"{}\"", text)));
+            Some((loc, block.to_string()))
+        }
+        None => None,
+    };
+    let wait = try!(matches.value_of("wait")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|_| "The wait must be a whole number of seconds".to_string()));
+
+    tools::probe::run(matches.value_of("ADDRESS").unwrap(),
+                      matches.value_of("name").unwrap(),
+                      try!(tools::parse_point(matches.value_of("at").unwrap())),
+                      set,
+                      std::time::Duration::from_secs(wait))
+}
+
+fn run_client(matches: &ArgMatches) -> Result<(), String> {
+    use gfx::Device;
+
+    // Open the world first so a locked world fails before a window appears.
+    // A replay leaves the world alone unless asked to autosave.
+    let world_path = Path::new(matches.value_of("world").unwrap());
+    let access = if matches.is_present("replay") && matches.occurrences_of("autosave") == 0 {
+        world::Access::ReadOnly
+    } else {
+        world::Access::ReadWrite
+    };
+    let mut game = try!(game::Game::open(world_path,
+                                         matches.value_of("generator").unwrap(),
+                                         access));
+
+    // Either play a recording back in place of live input, or record. Both
+    // are opened before the window too, so a bad path is reported cleanly
+    let mut replay = match matches.value_of("replay") {
+        Some(path) => Some(try!(replay::Recording::read(Path::new(path))).frames.into_iter()),
+        None => None,
+    };
+    let mut recorder = match matches.value_of("record") {
+        Some(path) => Some(try!(replay::Recorder::create(Path::new(path)))),
+        None => None,
+    };
+
     let builder = glutin::WindowBuilder::new()
         .with_depth_buffer(24)
         .with_stencil_buffer(8)
@@ -73,111 +492,199 @@ fn main() {
         graphics::Renderer::new(&mut factory, main_color.clone(), main_depth.clone());
     let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
 
-    let mut camera_frame_translator = vec3(0.0, 0.0, 0.0);
+    let bindings_path = Path::new(matches.value_of("bindings").unwrap());
+    let mut bindings = if bindings_path.exists() {
+        input::Bindings::load(bindings_path).unwrap_or_else(|e| {
+            warn!("{}, using default bindings", e);
+            input::Bindings::new()
+        })
+    } else {
+        input::Bindings::new()
+    };
+    for conflict in bindings.conflicts() {
+        warn!("Conflicting binding: {}", conflict);
+    }
+
     let mut reference_time = Instant::now();
 
     let mut cycler: u64 = 0;
 
-    let world_path = Path::new(matches.value_of("world").unwrap());
-    let chunk_gen: Box<world::terrain::ChunkGenerator> = match matches.value_of("generator")
-        .unwrap() {
-        "flat" => Box::new(world::terrain::FlatGenerator::new(50, 1, "stone".into())),
-        _ => Box::new(world::terrain::SimplexGenerator::new(50, 1)),
+    let autosave_interval = match matches.value_of("autosave").unwrap().parse::<u64>() {
+        Ok(0) => None,
+        Ok(seconds) => Some(std::time::Duration::from_secs(seconds)),
+        Err(_) => {
+            warn!("Autosave interval must be a whole number of seconds, using {}",
+                  autosave::DEFAULT_INTERVAL);
+            Some(std::time::Duration::from_secs(autosave::DEFAULT_INTERVAL))
+        }
     };
+    game.autosave = autosave::Autosave::new(autosave_interval);
 
-    let mut world = world::World::from_path(&world_path,
-                                            (vec3(-64, 0, -64), vec3(64, 64, 64)),
-                                            chunk_gen);
+    game.world.subscribe(Box::new(|event| {
+        if let world::events::WorldEvent::BlockChanged { loc, cause, .. } = *event {
+            debug!("{:?} changed block at {:?}", cause, loc);
+        }
+    }));
 
-    let mut models = world.make_models(&mut factory);
+    let mut models = game.world.make_models(&mut factory);
     for model in models.drain(..) {
         voxrender.set_model(model.0, model.1);
     }
 
 
+    let mut screen = window.get_inner_size_pixels().unwrap_or((1024, 768));
+    let mut hud = graphics::HudRenderer::new(&mut factory, main_color.clone(), screen);
+    hud.set_hotbar(&mut factory, &game.hotbar, &game.world.registry);
+
+    let mut console = console::Console::new();
+
     let text_renderer = gfx_text::new(factory.clone()).unwrap();
     let mut debug_renderer = gfx_debug_draw::DebugRenderer::new(factory.clone(), text_renderer, 64)
         .unwrap();
 
     info!("Starting main loop");
-    loop {
+    'main: loop {
         let new_time = Instant::now();
         let elapsed = new_time.duration_since(reference_time);
         reference_time = new_time;
-        let delta = elapsed.as_secs() as f32 + (elapsed.subsec_nanos() as f32 / 1_000_000_000.0);
+        let mut delta = elapsed.as_secs() as f32 +
+                        (elapsed.subsec_nanos() as f32 / 1_000_000_000.0);
 
         if cycler % 1000 == 0 || (delta > 0.016 && cycler % 10 == 0) {
             info!("Frame time {}", (delta * 1000.0) as u64);
         }
 
-        for command in window.poll_events().map(input::glutin_event_to_command) {
-            match command {
-                input::Command::Exit => return,
-                input::Command::CameraTranslate(input::State::Start, ammount) => {
-                    camera_frame_translator += ammount;
-                    // Key-repeat is dumb
-                    camera_frame_translator.x = clamp(camera_frame_translator.x, -1.0, 1.0);
-                    camera_frame_translator.y = clamp(camera_frame_translator.y, -1.0, 1.0);
-                    camera_frame_translator.z = clamp(camera_frame_translator.z, -1.0, 1.0);
+        // The console takes all input while it is open
+        let mut live = Vec::new();
+        for event in window.poll_events() {
+            if let glutin::Event::Resized(width, height) = event {
+                screen = (width, height);
+                hud.resize(screen);
+                hud.set_hotbar(&mut factory, &game.hotbar, &game.world.registry);
+                continue;
+            }
+            if !console.open {
+                live.push(bindings.command_for(event));
+            } else if let glutin::Event::Closed = event {
+                live.push(input::Command::Exit);
+            } else {
+                console.handle_event(&event, &mut game);
+            }
+        }
+        let entered = console.take_entered();
+        let commands = match replay {
+            Some(ref mut frames) => {
+                if live.iter().any(|c| if let input::Command::Exit = *c { true } else { false }) {
+                    break 'main;
                 }
-                input::Command::CameraTranslate(input::State::Stop, ammount) => {
-                    camera_frame_translator -= ammount;
+                match frames.next() {
+                    Some(frame) => {
+                        delta = frame.delta;
+                        for line in &frame.console {
+                            console.execute(&mut game, line);
+                        }
+                        frame.commands
+                    }
+                    None => {
+                        info!("Replay finished");
+                        break 'main;
+                    }
                 }
-                input::Command::CameraLook(vec) => {
-                    let relative = vec - vec2(500.0, 500.0);
-                    voxrender.camera.look_around(0.01 * relative);
+            }
+            None => live,
+        };
+
+        if let Some(ref mut recorder) = recorder {
+            recorder.record_frame(delta, &entered, &commands);
+        }
 
-                    window.set_cursor_position(500, 500)
+        for command in &commands {
+            match game.apply(command) {
+                game::Outcome::Exit => break 'main,
+                game::Outcome::Looked => {
+                    window.set_cursor_position(game::CURSOR_CENTRE.0, game::CURSOR_CENTRE.1)
                         .unwrap();
                 }
-                input::Command::Place => {
-                    let direction = (voxrender.camera.look_at - voxrender.camera.position)
-                        .normalize();
-                    let casted = world.cast_ray(voxrender.camera.position, 10.0 * direction);
-                    println!("Raycast result {:?}", casted);
-
-                    if let Some((loc, face)) = casted {
-                        let id = world.registry.lookup_id(&"stone".into()).unwrap();
-                        world.place_block(loc + face.normal(), id);
-                    }
+                game::Outcome::HotbarChanged => {
+                    hud.set_hotbar(&mut factory, &game.hotbar, &game.world.registry);
                 }
-                input::Command::Break => {
-                    let direction = (voxrender.camera.look_at - voxrender.camera.position)
-                        .normalize();
-                    let casted = world.cast_ray(voxrender.camera.position, 10.0 * direction);
-                    println!("Raycast result {:?}", casted);
-
-                    if let Some((loc, _)) = casted {
-                        world.break_block(loc);
-                    }
+                game::Outcome::ToggleConsole => {
+                    console.toggle();
+                    bindings.release_all();
+                    game.stop_moving();
                 }
-                input::Command::Save => {
-                    world.write_all_chunks();
-                }
-                input::Command::Noop => (),
+                game::Outcome::Nothing => (),
             }
         }
 
         // Update as much as one dirty chunk per frame
-        world.clean_chunk(&mut factory).map(|o| voxrender.set_model(o.0, o.1));
+        game.world.clean_chunk(&mut factory).map(|o| voxrender.set_model(o.0, o.1));
 
-        voxrender.camera.relative_translate(delta * 5.0 * camera_frame_translator);
-        debug_renderer.draw_text_on_screen(&format!("Camera {:?}", voxrender.camera.position),
+        game.advance(delta);
+        game.autosave.update(&mut game.world);
+        let target = game.target();
+        let camera = &game.camera;
+        debug_renderer.draw_text_on_screen(&format!("Camera {:?}", camera.position),
                                            [0, 0],
                                            [1.0, 1.0, 1.0, 1.0]);
         debug_renderer.draw_text_on_screen(&format!("Phi: {} Theta: {}",
-                                                    voxrender.camera.phi,
-                                                    voxrender.camera.theta),
+                                                    camera.phi,
+                                                    camera.theta),
                                            [0, 20],
                                            [1.0, 1.0, 1.0, 1.0]);
 
-        voxrender.render(&mut encoder);
+        let holding = game.hotbar
+            .current()
+            .and_then(|id| game.world.registry.lookup_name(id))
+            .map_or("nothing", |name| name.as_str());
+        debug_renderer.draw_text_on_screen(&format!("Holding {}", holding),
+                                           [0, 80],
+                                           [1.0, 1.0, 1.0, 1.0]);
+
+        // Outline and describe the block under the crosshair
+        if let Some(hit) = target {
+            for (start, end, color) in graphics::selection_lines(hit.loc, hit.face) {
+                debug_renderer.draw_line(start, end, color);
+            }
+
+            let name = game.world
+                .registry
+                .lookup_name(hit.block.id)
+                .map_or("unknown", |name| name.as_str());
+            debug_renderer.draw_text_on_screen(&format!("Target {} at {:?} face {:?}",
+                                                        name,
+                                                        hit.loc,
+                                                        hit.face),
+                                               [0, 40],
+                                               [1.0, 1.0, 1.0, 1.0]);
+            debug_renderer.draw_text_on_screen(&format!("Hit ({:.2}, {:.2}, {:.2}) distance {:.2}",
+                                                        hit.point.x,
+                                                        hit.point.y,
+                                                        hit.point.z,
+                                                        hit.distance),
+                                               [0, 60],
+                                               [1.0, 1.0, 1.0, 1.0]);
+        }
+
+        if console.open {
+            let lines = console.lines();
+            let top = screen.1 as i32 - 100 - 20 * lines.len() as i32;
+            for (i, line) in lines.iter().enumerate() {
+                debug_renderer.draw_text_on_screen(line,
+                                                   [8, top + 20 * i as i32],
+                                                   [1.0, 1.0, 0.6, 1.0]);
+            }
+        }
+
+        voxrender.sky_color = game.sky_color();
+        voxrender.render(&mut encoder, camera);
+        hud.render(&mut encoder);
 
         debug_renderer.render(&mut encoder,
                     &main_color,
                     &main_depth,
                     (Matrix4::from(voxrender.projection) *
-                     Matrix4::from(*voxrender.camera.get_view_matrix()))
+                     Matrix4::from(*camera.get_view_matrix()))
                         .into())
             .unwrap();
 
@@ -187,4 +694,9 @@ fn main() {
 
         cycler += 1;
     }
+
+    if !game.world.is_read_only() {
+        game.autosave.finish(&mut game.world);
+    }
+    Ok(())
 }
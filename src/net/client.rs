@@ -0,0 +1,235 @@
+use std::net::{TcpStream, Shutdown};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use world::WorldPoint;
+use world::block::BlockID;
+use world::registry::Registry;
+use super::{ClientMessage, ServerMessage, PlayerState, DisconnectReason, PROTOCOL_VERSION,
+            read_message, write_message};
+
+/// How long to wait for the server to answer `Hello`.
+const HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// A client's side of a connection. Messages from the server are read on a
+/// thread of their own and collected with `wait`.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Receiver<ServerMessage>,
+    pub player: u32,
+}
+
+impl Connection {
+    /// Connects and says hello. Fails if the server turns the client away or
+    /// numbers its blocks differently from `registry`.
+    pub fn connect(addr: &str, name: &str, registry: &Registry) -> Result<Connection, String> {
+        let mut stream = try!(TcpStream::connect(addr)
+            .map_err(|e| format!("Could not connect to {}: {}", addr, e)));
+        try!(stream.set_nodelay(true)
+            .and_then(|_| stream.set_read_timeout(Some(Duration::from_secs(HANDSHAKE_TIMEOUT))))
+            .map_err(|e| e.to_string()));
+        try!(write_message(&mut stream,
+                           &ClientMessage::Hello {
+                               version: PROTOCOL_VERSION,
+                               name: name.into(),
+                           }));
+
+        let player = match try!(read_message(&mut stream)) {
+            ServerMessage::Welcome { player, registry: table } => {
+                if let Err(e) = check_registry(&table, registry) {
+                    let reason = DisconnectReason::RegistryMismatch(e.clone());
+                    let _ = write_message(&mut stream, &ClientMessage::Disconnect(reason));
+                    return Err(e);
+                }
+                player
+            }
+            ServerMessage::Disconnect(reason) => {
+                return Err(format!("Server turned us away: {}", reason))
+            }
+            _ => return Err("Server did not answer with a welcome".into()),
+        };
+
+        try!(stream.set_read_timeout(None).map_err(|e| e.to_string()));
+        let mut reader = try!(stream.try_clone().map_err(|e| e.to_string()));
+        let (incoming_tx, incoming) = channel();
+        thread::spawn(move || {
+            loop {
+                match read_message(&mut reader) {
+                    Ok(message) => {
+                        let last = if let ServerMessage::Disconnect(_) = message {
+                            true
+                        } else {
+                            false
+                        };
+                        if incoming_tx.send(message).is_err() || last {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let lost = DisconnectReason::ConnectionLost(e);
+                        let _ = incoming_tx.send(ServerMessage::Disconnect(lost));
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Connection {
+            stream: stream,
+            incoming: incoming,
+            player: player,
+        })
+    }
+
+    pub fn request_chunk(&mut self, origin: WorldPoint) -> Result<(), String> {
+        write_message(&mut self.stream,
+                      &ClientMessage::RequestChunk((origin.x, origin.y, origin.z)))
+    }
+
+    pub fn set_block(&mut self, loc: WorldPoint, id: BlockID) -> Result<(), String> {
+        write_message(&mut self.stream,
+                      &ClientMessage::SetBlock {
+                          loc: (loc.x, loc.y, loc.z),
+                          id: id,
+                      })
+    }
+
+    pub fn send_position(&mut self, state: PlayerState) -> Result<(), String> {
+        write_message(&mut self.stream, &ClientMessage::Move(state))
+    }
+
+    /// The next message, if one arrives within `timeout`. A lost connection
+    /// shows up as `Disconnect(ConnectionLost)`.
+    pub fn wait(&self, timeout: Duration) -> Option<ServerMessage> {
+        self.incoming.recv_timeout(timeout).ok()
+    }
+
+    pub fn disconnect(mut self) {
+        let goodbye = ClientMessage::Disconnect(DisconnectReason::Quit);
+        let _ = write_message(&mut self.stream, &goodbye);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Checks that the server and `registry` know the same blocks by the same
+/// IDs.
+fn check_registry(table: &[(BlockID, String)], registry: &Registry) -> Result<(), String> {
+    for &(id, ref name) in table {
+        match registry.lookup_id(name) {
+            Some(local) if local == id => (),
+            Some(local) => {
+                return Err(format!("\"{}\" is {} on the server but {} here", name, id.0, local.0))
+            }
+            None => return Err(format!("\"{}\" is not a known block", name)),
+        }
+    }
+    for id in registry.block_ids() {
+        if !table.iter().any(|&(server_id, _)| server_id == id) {
+            let name = registry.lookup_name(id).map_or("?", |name| name.as_str());
+            return Err(format!("\"{}\" is not known to the server", name));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn registries_compared_both_ways() {
+    let registry = Registry::new();
+    let table: Vec<(BlockID, String)> = registry.block_ids()
+        .into_iter()
+        .map(|id| (id, registry.lookup_name(id).unwrap().clone()))
+        .collect();
+    assert!(check_registry(&table, &registry).is_ok());
+    assert!(check_registry(&table[1..], &registry).is_err());
+
+    let mut extra = table.clone();
+    extra.push((BlockID(200), "marble".into()));
+    assert!(check_registry(&extra, &registry).is_err());
+}
+
+#[test]
+fn loopback() {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use prelude::*;
+    use world::{World, Access};
+    use world::chunk::Chunk;
+    use world::terrain::FlatGenerator;
+    use super::Server;
+
+    // Worlds cannot cross threads, so the server builds its own
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopping = stop.clone();
+    let (addr_tx, addr) = channel();
+    let server = thread::spawn(move || {
+        let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+        let world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+            .unwrap();
+        let mut server = Server::bind(world, "127.0.0.1:0").unwrap();
+        server.save_interval = Duration::from_secs(3600);
+        addr_tx.send(server.local_addr().to_string()).unwrap();
+        while !stopping.load(Ordering::SeqCst) {
+            server.tick();
+            thread::sleep(Duration::from_millis(5));
+        }
+    });
+    let addr = addr.recv().unwrap();
+    let timeout = Duration::from_secs(5);
+    let registry = Registry::new();
+
+    let mut alice = Connection::connect(&addr, "alice", &registry).unwrap();
+    let bob = Connection::connect(&addr, "bob", &registry).unwrap();
+    assert!(alice.player != bob.player);
+
+    let state = PlayerState {
+        position: (1.0, 20.0, 2.0),
+        phi: 0.25,
+        theta: 1.0,
+    };
+    alice.send_position(state).unwrap();
+    assert_eq!(bob.wait(timeout),
+               Some(ServerMessage::PlayerMoved {
+                   player: alice.player,
+                   name: "alice".into(),
+                   state: state,
+               }));
+
+    alice.request_chunk(point3(0, 0, 0)).unwrap();
+    match alice.wait(timeout) {
+        Some(ServerMessage::ChunkData { origin, blocks }) => {
+            assert_eq!(origin, (0, 0, 0));
+            let chunk = Chunk::from_bytes(point3(0, 0, 0), &blocks).unwrap();
+            assert_eq!(chunk.get_block(point3(3, 0, 4)).id, BlockID(1));
+            assert!(chunk.get_block(point3(3, 1, 4)).is_empty());
+        }
+        other => panic!("Expected chunk data, got {:?}", other),
+    }
+
+    alice.set_block(point3(3, 1, 4), BlockID(2)).unwrap();
+    let changed = ServerMessage::BlockChanged {
+        loc: (3, 1, 4),
+        id: BlockID(2),
+    };
+    assert_eq!(alice.wait(timeout), Some(changed.clone()));
+    assert_eq!(bob.wait(timeout), Some(changed));
+
+    let alice_player = alice.player;
+    alice.disconnect();
+    assert_eq!(bob.wait(timeout),
+               Some(ServerMessage::PlayerLeft { player: alice_player }));
+    assert_eq!(bob.wait(Duration::from_millis(100)), None);
+
+    let mut renumbered = Registry::new();
+    renumbered.register_block("stone".into(),
+                              BlockID(9),
+                              ::world::registry::BlockUV::one_face(point2(2, 0)));
+    assert!(Connection::connect(&addr, "carol", &renumbered).is_err());
+
+    stop.store(true, Ordering::SeqCst);
+    server.join().unwrap();
+}
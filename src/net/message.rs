@@ -0,0 +1,142 @@
+use std::fmt;
+
+use world::block::BlockID;
+
+/// Bumped whenever a message changes shape. Clients and servers only talk
+/// to their own version.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// A block position, as cgmath points do not implement `RustcEncodable`.
+pub type Position = (i32, i32, i32);
+
+/// Where a player is and which way they face, as in `Camera`.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct PlayerState {
+    pub position: (f32, f32, f32),
+    pub phi: f32,
+    pub theta: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum DisconnectReason {
+    /// The client left of its own accord.
+    Quit,
+    ServerShutdown,
+    VersionMismatch { server: u32, client: u32 },
+    /// The client's block registry disagrees with the server's.
+    RegistryMismatch(String),
+    /// The other side sent something out of turn.
+    ProtocolError(String),
+    /// The connection dropped without a goodbye. Never sent; `Connection`
+    /// reports it when its stream fails.
+    ConnectionLost(String),
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisconnectReason::Quit => write!(f, "Quit"),
+            DisconnectReason::ServerShutdown => write!(f, "Server shut down"),
+            DisconnectReason::VersionMismatch { server, client } => {
+                write!(f, "Server speaks version {} but client speaks {}", server, client)
+            }
+            DisconnectReason::RegistryMismatch(ref e) => write!(f, "Registries differ: {}", e),
+            DisconnectReason::ProtocolError(ref e) => write!(f, "Protocol error: {}", e),
+            DisconnectReason::ConnectionLost(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum ClientMessage {
+    /// Must come first.
+    Hello { version: u32, name: String },
+    /// Ask for the chunk with this origin.
+    RequestChunk(Position),
+    /// Set a block; `BlockID(0)` removes it.
+    SetBlock { loc: Position, id: BlockID },
+    Move(PlayerState),
+    Disconnect(DisconnectReason),
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum ServerMessage {
+    /// The answer to `Hello`: the client's player number and every block
+    /// name the server knows by ID.
+    Welcome { player: u32, registry: Vec<(BlockID, String)> },
+    /// A chunk's blocks, encoded as by `Chunk::to_bytes`.
+    ChunkData { origin: Position, blocks: Vec<u8> },
+    /// The answer to a chunk request the server could not load.
+    ChunkError { origin: Position, error: String },
+    /// A block changed, whoever changed it.
+    BlockChanged { loc: Position, id: BlockID },
+    /// Another player joined or moved.
+    PlayerMoved { player: u32, name: String, state: PlayerState },
+    PlayerLeft { player: u32 },
+    Disconnect(DisconnectReason),
+}
+
+#[test]
+fn round_trips() {
+    use std::io::Cursor;
+    use super::{read_message, write_message};
+
+    let state = PlayerState {
+        position: (1.5, 40.0, -3.25),
+        phi: 0.5,
+        theta: 1.5,
+    };
+    let client = vec![ClientMessage::Hello {
+                          version: PROTOCOL_VERSION,
+                          name: "tester".into(),
+                      },
+                      ClientMessage::RequestChunk((16, -32, 0)),
+                      ClientMessage::SetBlock {
+                          loc: (1, 2, 3),
+                          id: BlockID(4),
+                      },
+                      ClientMessage::Move(state),
+                      ClientMessage::Disconnect(DisconnectReason::Quit)];
+    let server = vec![ServerMessage::Welcome {
+                          player: 3,
+                          registry: vec![(BlockID(1), "stone".into())],
+                      },
+                      ServerMessage::ChunkData {
+                          origin: (0, 16, 0),
+                          blocks: vec![1, 2, 3],
+                      },
+                      ServerMessage::ChunkError {
+                          origin: (0, 32, 0),
+                          error: "corrupt".into(),
+                      },
+                      ServerMessage::BlockChanged {
+                          loc: (-1, 0, 7),
+                          id: BlockID(0),
+                      },
+                      ServerMessage::PlayerMoved {
+                          player: 2,
+                          name: "other".into(),
+                          state: state,
+                      },
+                      ServerMessage::PlayerLeft { player: 2 },
+                      ServerMessage::Disconnect(DisconnectReason::VersionMismatch {
+                          server: 2,
+                          client: 1,
+                      })];
+
+    let mut stream = Vec::new();
+    for message in &client {
+        write_message(&mut stream, message).unwrap();
+    }
+    for message in &server {
+        write_message(&mut stream, message).unwrap();
+    }
+
+    let mut input = Cursor::new(stream);
+    for message in &client {
+        assert_eq!(read_message::<_, ClientMessage>(&mut input).unwrap(), *message);
+    }
+    for message in &server {
+        assert_eq!(read_message::<_, ServerMessage>(&mut input).unwrap(), *message);
+    }
+}
@@ -0,0 +1,74 @@
+//! Sharing a world over TCP. Every message is a big-endian `u32` length
+//! followed by that many bytes of bincode.
+//!
+//! A client opens with `Hello`, giving its protocol version, and the server
+//! answers with `Welcome` and its block registry, or with `Disconnect` and a
+//! reason. After that either side may send at any time. A client that does
+//! not say hello within a few seconds is dropped.
+
+use std::io::{Read, Write};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+use rustc_serialize::{Encodable, Decodable};
+
+mod message;
+pub mod server;
+pub mod client;
+
+pub use self::message::{ClientMessage, ServerMessage, Position, PlayerState, DisconnectReason,
+                        PROTOCOL_VERSION};
+pub use self::server::Server;
+pub use self::client::Connection;
+
+/// Larger messages are treated as a broken stream. A chunk is well under this.
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+pub fn write_message<W: Write, T: Encodable>(out: &mut W, message: &T) -> Result<(), String> {
+    let bytes = try!(encode(message, SizeLimit::Bounded(MAX_MESSAGE_SIZE as u64))
+        .map_err(|e| format!("Could not encode message: {}", e)));
+    let len = bytes.len() as u32;
+    let header = [(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+
+    out.write_all(&header)
+        .and_then(|_| out.write_all(&bytes))
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Could not send message: {}", e))
+}
+
+pub fn read_message<R: Read, T: Decodable>(input: &mut R) -> Result<T, String> {
+    let mut header = [0u8; 4];
+    try!(input.read_exact(&mut header).map_err(|e| format!("Connection lost: {}", e)));
+    let len = (header[0] as usize) << 24 | (header[1] as usize) << 16 |
+              (header[2] as usize) << 8 | header[3] as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(format!("Message of {} bytes is too large", len));
+    }
+
+    let mut bytes = vec![0; len];
+    try!(input.read_exact(&mut bytes).map_err(|e| format!("Connection lost: {}", e)));
+    decode(&bytes).map_err(|e| format!("Could not decode message: {}", e))
+}
+
+#[test]
+fn framing() {
+    use std::io::Cursor;
+    use world::block::BlockID;
+
+    let messages = vec![ClientMessage::RequestChunk((16, -32, 0)),
+                        ClientMessage::SetBlock {
+                            loc: (1, 2, 3),
+                            id: BlockID(4),
+                        }];
+    let mut stream = Vec::new();
+    for message in &messages {
+        write_message(&mut stream, message).unwrap();
+    }
+
+    let mut input = Cursor::new(stream);
+    for message in &messages {
+        assert_eq!(read_message::<_, ClientMessage>(&mut input).unwrap(), *message);
+    }
+    assert!(read_message::<_, ClientMessage>(&mut input).is_err());
+    assert!(read_message::<_, ClientMessage>(&mut Cursor::new(vec![255, 0, 0, 0])).is_err());
+}
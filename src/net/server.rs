@@ -0,0 +1,646 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, ErrorKind};
+use std::iter;
+use std::net::{TcpListener, TcpStream, SocketAddr, Shutdown};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use prelude::*;
+use world::{World, WorldPoint, find_chunk_origin};
+use world::block::BlockID;
+use world::chunk::CHUNK_SIZE;
+use world::events::WorldEvent;
+use super::{ClientMessage, ServerMessage, Position, PlayerState, DisconnectReason,
+            PROTOCOL_VERSION, read_message, write_message};
+
+pub const TICK_RATE: u32 = 20;
+pub const DEFAULT_SAVE_INTERVAL: u64 = 60;
+
+/// A client that stops reading for this long is dropped rather than holding
+/// up the tick.
+const WRITE_TIMEOUT: u64 = 5;
+/// A client that has not said hello within this many seconds is dropped.
+const HELLO_TIMEOUT: u64 = 10;
+/// How far from a player, in blocks along each axis, chunks may be asked for
+/// or edited. Chunks no player is this close to are unloaded.
+const VIEW_DISTANCE: i32 = 8 * CHUNK_SIZE;
+
+/// Doubles as the player number once the client has said hello.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct ClientId(u32);
+
+/// What a client's reader thread passes back to the tick.
+enum Incoming {
+    Message(ClientMessage),
+    Closed(String),
+}
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    connected: Instant,
+    /// Set once the handshake is done.
+    player: Option<Player>,
+}
+
+struct Player {
+    name: String,
+    state: Option<PlayerState>,
+}
+
+/// Owns a world and shares it with clients over TCP. Each client gets a
+/// thread that reads its messages; everything else, the world included,
+/// stays on the thread calling `tick`.
+pub struct Server {
+    world: World,
+    listener: TcpListener,
+    clients: HashMap<ClientId, Client>,
+    next_client: u32,
+    incoming: Receiver<(ClientId, Incoming)>,
+    incoming_tx: Sender<(ClientId, Incoming)>,
+    /// Block changes since the last broadcast, filled by a world listener.
+    changes: Rc<RefCell<Vec<(WorldPoint, BlockID)>>>,
+    pub save_interval: Duration,
+    last_save: Instant,
+    hello_timeout: Duration,
+    ticks: u64,
+}
+
+fn to_point(p: Position) -> WorldPoint {
+    point3(p.0, p.1, p.2)
+}
+
+fn to_position(p: WorldPoint) -> Position {
+    (p.x, p.y, p.z)
+}
+
+/// Whether `loc` is within `VIEW_DISTANCE` of a player at `state`. Players
+/// who have not moved yet count as being at the origin.
+fn in_view(state: Option<PlayerState>, loc: WorldPoint) -> bool {
+    let (x, y, z) = state.map_or((0.0, 0.0, 0.0), |state| state.position);
+    let reach = VIEW_DISTANCE as f32;
+    (loc.x as f32 - x).abs() <= reach && (loc.y as f32 - y).abs() <= reach &&
+    (loc.z as f32 - z).abs() <= reach
+}
+
+fn chunk_centre(origin: WorldPoint) -> WorldPoint {
+    origin + vec3(CHUNK_SIZE / 2, CHUNK_SIZE / 2, CHUNK_SIZE / 2)
+}
+
+impl Server {
+    /// Listens on `addr`; use port 0 to have one picked.
+    pub fn bind(mut world: World, addr: &str) -> Result<Server, String> {
+        let listener = try!(TcpListener::bind(addr)
+            .map_err(|e| format!("Could not listen on {}: {}", addr, e)));
+        try!(listener.set_nonblocking(true).map_err(|e| e.to_string()));
+
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let sink = changes.clone();
+        world.subscribe(Box::new(move |event| {
+            if let WorldEvent::BlockChanged { loc, new, .. } = *event {
+                sink.borrow_mut().push((loc, new));
+            }
+        }));
+
+        let (incoming_tx, incoming) = channel();
+        Ok(Server {
+            world: world,
+            listener: listener,
+            clients: HashMap::new(),
+            next_client: 0,
+            incoming: incoming,
+            incoming_tx: incoming_tx,
+            changes: changes,
+            save_interval: Duration::from_secs(DEFAULT_SAVE_INTERVAL),
+            last_save: Instant::now(),
+            hello_timeout: Duration::from_secs(HELLO_TIMEOUT),
+            ticks: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Ticks `TICK_RATE` times a second until `stop` is typed on standard
+    /// input, then disconnects everyone and saves.
+    pub fn run(&mut self) {
+        let (lines_tx, lines) = channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines().filter_map(|l| l.ok()) {
+                if lines_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let tick_length = Duration::new(0, 1_000_000_000 / TICK_RATE);
+        info!("Serving {:?} on {}; type stop to shut down",
+              self.world.root(),
+              self.local_addr());
+        loop {
+            let start = Instant::now();
+            if lines.try_iter().any(|line| line.trim() == "stop") {
+                break;
+            }
+            self.tick();
+            let elapsed = start.elapsed();
+            if elapsed < tick_length {
+                thread::sleep(tick_length - elapsed);
+            }
+        }
+
+        self.shutdown();
+    }
+
+    /// Tells every client the server is going away, then saves.
+    pub fn shutdown(&mut self) {
+        let ids: Vec<ClientId> = self.clients.keys().cloned().collect();
+        for id in ids {
+            self.kick(id, DisconnectReason::ServerShutdown);
+        }
+        self.save();
+    }
+
+    /// Accepts new clients, handles everything they have sent, tells them
+    /// about the resulting block changes and saves if it is time to.
+    pub fn tick(&mut self) {
+        self.accept();
+        self.kick_silent();
+
+        while let Ok((id, incoming)) = self.incoming.try_recv() {
+            match incoming {
+                Incoming::Message(message) => self.handle(id, message),
+                Incoming::Closed(reason) => self.drop_client(id, &reason),
+            }
+        }
+
+        let changes: Vec<(WorldPoint, BlockID)> = self.changes.borrow_mut().drain(..).collect();
+        for (loc, id) in changes {
+            self.broadcast(&ServerMessage::BlockChanged {
+                loc: to_position(loc),
+                id: id,
+            });
+        }
+
+        if self.ticks % TICK_RATE as u64 == 0 {
+            self.unload_distant();
+        }
+        if self.last_save.elapsed() >= self.save_interval {
+            self.save();
+        }
+        self.ticks += 1;
+    }
+
+    /// Drops clients that connected but never said hello.
+    fn kick_silent(&mut self) {
+        let timeout = self.hello_timeout;
+        let silent: Vec<ClientId> = self.clients
+            .iter()
+            .filter(|&(_, client)| client.player.is_none() && client.connected.elapsed() >= timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in silent {
+            let reason = format!("No hello within {} seconds", timeout.as_secs());
+            self.kick(id, DisconnectReason::ProtocolError(reason));
+        }
+    }
+
+    /// Saves and drops every chunk no player is near.
+    fn unload_distant(&mut self) {
+        let states: Vec<Option<PlayerState>> = self.clients
+            .values()
+            .filter_map(|client| client.player.as_ref().map(|player| player.state))
+            .collect();
+        let distant: Vec<WorldPoint> = self.world
+            .loaded_chunks()
+            .into_iter()
+            .filter(|&origin| !states.iter().any(|&state| in_view(state, chunk_centre(origin))))
+            .collect();
+        for origin in distant {
+            self.world.unload_chunk(origin);
+        }
+    }
+
+    /// Writes every changed chunk.
+    pub fn save(&mut self) {
+        let written = self.world.save_changed();
+        if written > 0 {
+            info!("Saved {} chunks after {} ticks with {} clients connected",
+                  written,
+                  self.ticks,
+                  self.client_count());
+        }
+        self.last_save = Instant::now();
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(e) = self.add_client(stream, addr) {
+                        warn!("Could not accept {}: {}", addr, e);
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Could not accept a client: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn add_client(&mut self, stream: TcpStream, addr: SocketAddr) -> Result<(), String> {
+        try!(stream.set_nonblocking(false)
+            .and_then(|_| stream.set_nodelay(true))
+            .and_then(|_| stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT))))
+            .map_err(|e| e.to_string()));
+        let mut reader = try!(stream.try_clone().map_err(|e| e.to_string()));
+
+        let id = ClientId(self.next_client);
+        self.next_client += 1;
+        let incoming = self.incoming_tx.clone();
+        thread::spawn(move || {
+            loop {
+                match read_message(&mut reader) {
+                    Ok(message) => {
+                        if incoming.send((id, Incoming::Message(message))).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = incoming.send((id, Incoming::Closed(e)));
+                        break;
+                    }
+                }
+            }
+        });
+
+        info!("Client {} connected from {}", id.0, addr);
+        self.clients.insert(id,
+                            Client {
+                                stream: stream,
+                                addr: addr,
+                                connected: Instant::now(),
+                                player: None,
+                            });
+        Ok(())
+    }
+
+    fn drop_client(&mut self, id: ClientId, reason: &str) {
+        if let Some(client) = self.clients.remove(&id) {
+            info!("Client {} at {} left: {}", id.0, client.addr, reason);
+            let _ = client.stream.shutdown(Shutdown::Both);
+            if client.player.is_some() {
+                self.broadcast(&ServerMessage::PlayerLeft { player: id.0 });
+            }
+        }
+    }
+
+    /// Says why, then hangs up.
+    fn kick(&mut self, id: ClientId, reason: DisconnectReason) {
+        self.send(id, &ServerMessage::Disconnect(reason.clone()));
+        self.drop_client(id, &reason.to_string());
+    }
+
+    fn welcome(&mut self, id: ClientId, name: String) {
+        let registry = self.world
+            .registry
+            .block_ids()
+            .into_iter()
+            .filter_map(|block| self.world.registry.lookup_name(block).map(|n| (block, n.clone())))
+            .collect();
+        self.send(id,
+                  &ServerMessage::Welcome {
+                      player: id.0,
+                      registry: registry,
+                  });
+
+        let others: Vec<ServerMessage> = self.clients
+            .iter()
+            .filter_map(|(&other, client)| {
+                client.player.as_ref().and_then(|player| {
+                    player.state.map(|state| {
+                        ServerMessage::PlayerMoved {
+                            player: other.0,
+                            name: player.name.clone(),
+                            state: state,
+                        }
+                    })
+                })
+            })
+            .collect();
+        for message in &others {
+            self.send(id, message);
+        }
+
+        info!("Client {} is {}", id.0, name);
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.player = Some(Player {
+                name: name,
+                state: None,
+            });
+        }
+    }
+
+    fn handle(&mut self, id: ClientId, message: ClientMessage) {
+        let welcomed = self.clients.get(&id).map_or(false, |c| c.player.is_some());
+        match message {
+            ClientMessage::Hello { version, name } => {
+                if welcomed {
+                    self.kick(id, DisconnectReason::ProtocolError("Hello sent twice".into()));
+                } else if version != PROTOCOL_VERSION {
+                    self.kick(id,
+                              DisconnectReason::VersionMismatch {
+                                  server: PROTOCOL_VERSION,
+                                  client: version,
+                              });
+                } else {
+                    self.welcome(id, name);
+                }
+            }
+            ClientMessage::Disconnect(reason) => {
+                self.drop_client(id, &reason.to_string());
+            }
+            _ if !welcomed => {
+                self.kick(id, DisconnectReason::ProtocolError("Expected Hello first".into()));
+            }
+            ClientMessage::RequestChunk(origin) => {
+                let origin = to_point(origin);
+                if origin.x % CHUNK_SIZE != 0 || origin.y % CHUNK_SIZE != 0 ||
+                   origin.z % CHUNK_SIZE != 0 {
+                    warn!("Client {} asked for a chunk at {:?}, which is not an origin",
+                          id.0,
+                          origin);
+                    return;
+                }
+                if !in_view(self.player_state(id), chunk_centre(origin)) {
+                    warn!("Client {} asked for a chunk at {:?}, which is out of view",
+                          id.0,
+                          origin);
+                    return;
+                }
+
+                let loaded = self.world.require_chunk(origin).map(|chunk| chunk.to_bytes());
+                let message = match loaded {
+                    Ok(blocks) => {
+                        ServerMessage::ChunkData {
+                            origin: to_position(origin),
+                            blocks: blocks,
+                        }
+                    }
+                    Err(e) => {
+                        error!("Could not load chunk {:?} for client {}: {}", origin, id.0, e);
+                        ServerMessage::ChunkError {
+                            origin: to_position(origin),
+                            error: e,
+                        }
+                    }
+                };
+                self.send(id, &message);
+            }
+            ClientMessage::SetBlock { loc, id: block } => {
+                if block != BlockID(0) && self.world.registry.lookup_name(block).is_none() {
+                    warn!("Client {} tried to place unknown block {:?}", id.0, block);
+                    return;
+                }
+                if !in_view(self.player_state(id), to_point(loc)) {
+                    warn!("Client {} tried to set a block at {:?}, which is out of view",
+                          id.0,
+                          loc);
+                    return;
+                }
+                // Editing a chunk that is not loaded would start it empty and
+                // save that over the one on disk
+                if let Err(e) = self.world.require_chunk(find_chunk_origin(to_point(loc))) {
+                    error!("Could not load the chunk client {} is editing: {}", id.0, e);
+                    return;
+                }
+                self.world.apply_changes(iter::once((to_point(loc), block)));
+            }
+            ClientMessage::Move(state) => {
+                let name = match self.clients.get_mut(&id).and_then(|c| c.player.as_mut()) {
+                    Some(player) => {
+                        player.state = Some(state);
+                        player.name.clone()
+                    }
+                    None => return,
+                };
+                let message = ServerMessage::PlayerMoved {
+                    player: id.0,
+                    name: name,
+                    state: state,
+                };
+                for other in self.players().into_iter().filter(|&other| other != id) {
+                    self.send(other, &message);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, id: ClientId, message: &ServerMessage) {
+        let result = match self.clients.get_mut(&id) {
+            Some(client) => write_message(&mut client.stream, message),
+            None => return,
+        };
+        if let Err(e) = result {
+            self.drop_client(id, &e);
+        }
+    }
+
+    /// Where a player last said they were.
+    fn player_state(&self, id: ClientId) -> Option<PlayerState> {
+        self.clients.get(&id).and_then(|client| client.player.as_ref()).and_then(|p| p.state)
+    }
+
+    /// Clients that have said hello.
+    fn players(&self) -> Vec<ClientId> {
+        self.clients
+            .iter()
+            .filter(|&(_, client)| client.player.is_some())
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    fn broadcast(&mut self, message: &ServerMessage) {
+        for id in self.players() {
+            self.send(id, message);
+        }
+    }
+}
+
+#[test]
+fn handshake_rules() {
+    use std::path::Path;
+    use world::Access;
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(Path::new("/nonexistent"),
+                                 (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                 chunk_gen,
+                                 Access::ReadOnly)
+        .unwrap();
+    let mut server = Server::bind(world, "127.0.0.1:0").unwrap();
+    server.save_interval = Duration::from_secs(3600);
+
+    server.hello_timeout = Duration::from_millis(200);
+
+    let mut rude = TcpStream::connect(server.local_addr()).unwrap();
+    let mut outdated = TcpStream::connect(server.local_addr()).unwrap();
+    let mut silent = TcpStream::connect(server.local_addr()).unwrap();
+    rude.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    outdated.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    silent.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write_message(&mut rude, &ClientMessage::RequestChunk((16, 0, 0))).unwrap();
+    write_message(&mut outdated,
+                  &ClientMessage::Hello {
+                      version: PROTOCOL_VERSION + 1,
+                      name: "old".into(),
+                  })
+        .unwrap();
+
+    // The reader threads pass messages over in their own time
+    for _ in 0..500 {
+        server.tick();
+        if server.next_client == 3 && server.client_count() == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.client_count(), 0);
+
+    match read_message(&mut rude).unwrap() {
+        ServerMessage::Disconnect(DisconnectReason::ProtocolError(_)) => (),
+        other => panic!("Expected a protocol error, got {:?}", other),
+    }
+    assert_eq!(read_message::<_, ServerMessage>(&mut outdated).unwrap(),
+               ServerMessage::Disconnect(DisconnectReason::VersionMismatch {
+                   server: PROTOCOL_VERSION,
+                   client: PROTOCOL_VERSION + 1,
+               }));
+    match read_message(&mut silent).unwrap() {
+        ServerMessage::Disconnect(DisconnectReason::ProtocolError(_)) => (),
+        other => panic!("Expected a protocol error, got {:?}", other),
+    }
+}
+
+#[test]
+fn chunks_follow_players() {
+    use std::path::Path;
+    use world::Access;
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(Path::new("/nonexistent"),
+                                 (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                 chunk_gen,
+                                 Access::ReadOnly)
+        .unwrap();
+    let mut server = Server::bind(world, "127.0.0.1:0").unwrap();
+    server.save_interval = Duration::from_secs(3600);
+
+    let mut player = TcpStream::connect(server.local_addr()).unwrap();
+    write_message(&mut player,
+                  &ClientMessage::Hello {
+                      version: PROTOCOL_VERSION,
+                      name: "walker".into(),
+                  })
+        .unwrap();
+    let state = PlayerState {
+        position: (500.0, 8.0, 8.0),
+        phi: 0.0,
+        theta: 0.0,
+    };
+    write_message(&mut player, &ClientMessage::Move(state)).unwrap();
+    let far = point3(0, 0, 0);
+    write_message(&mut player, &ClientMessage::RequestChunk(to_position(far))).unwrap();
+    write_message(&mut player,
+                  &ClientMessage::SetBlock {
+                      loc: (1, 1, 1),
+                      id: BlockID(2),
+                  })
+        .unwrap();
+    for _ in 0..500 {
+        server.tick();
+        if server.player_state(ClientId(0)).is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.player_state(ClientId(0)), Some(state));
+
+    // Neither the request nor the edit far from the player loads a chunk,
+    // and chunks loaded some other way go once nobody is near
+    for _ in 0..10 {
+        server.tick();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(server.world.chunk(far).is_none());
+    let near = point3(496, 0, 0);
+    server.world.require_chunk(near).unwrap();
+    server.world.require_chunk(far).unwrap();
+    server.unload_distant();
+    assert!(server.world.chunk(near).is_some());
+    assert!(server.world.chunk(far).is_none());
+}
+
+#[test]
+fn edits_keep_saved_chunks() {
+    use std::env;
+    use std::fs;
+    use world::Access;
+    use world::chunk::Chunk;
+    use world::terrain::FlatGenerator;
+
+    let root = env::temp_dir().join("subsurface-server-edit-test");
+    let _ = fs::remove_dir_all(&root);
+    let open = |extents: (Vector3<i32>, Vector3<i32>)| {
+        let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+        World::from_path(&root, extents, chunk_gen, Access::ReadWrite).unwrap()
+    };
+    let mut world = open((vec3(0, 0, 0), vec3(16, 16, 16)));
+    world.write_all_chunks();
+    drop(world);
+
+    // Nothing is loaded when the edit arrives
+    let mut server = Server::bind(open((vec3(0, 0, 0), vec3(0, 0, 0))), "127.0.0.1:0").unwrap();
+    server.save_interval = Duration::from_secs(3600);
+    let mut player = TcpStream::connect(server.local_addr()).unwrap();
+    write_message(&mut player,
+                  &ClientMessage::Hello {
+                      version: PROTOCOL_VERSION,
+                      name: "builder".into(),
+                  })
+        .unwrap();
+    write_message(&mut player,
+                  &ClientMessage::SetBlock {
+                      loc: (1, 1, 1),
+                      id: BlockID(2),
+                  })
+        .unwrap();
+    for _ in 0..500 {
+        server.tick();
+        if server.world.get_block(point3(1, 1, 1)).id == BlockID(2) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.save();
+
+    let saved = Chunk::read(point3(0, 0, 0), &root).unwrap();
+    assert_eq!(saved.get_block(point3(1, 1, 1)).id, BlockID(2));
+    assert_eq!(saved.get_block(point3(5, 0, 5)).id, BlockID(1));
+
+    drop(server);
+    fs::remove_dir_all(&root).unwrap();
+}
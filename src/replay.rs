@@ -0,0 +1,271 @@
+//! Recording the command stream of a session and playing it back.
+//!
+//! Recordings are text, one frame per `frame <seconds>` line followed by the
+//! lines entered at the console and the commands handled during that frame:
+//!
+//! ```text
+//! frame 0.016
+//! console fill 0 0 0 3 3 3 stone
+//! move start 1 0 0
+//! look 512 497
+//! place
+//! ```
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use prelude::*;
+use console::CommandRegistry;
+use game::{Game, Outcome};
+use hotbar::SLOT_COUNT;
+use input::{Command, State};
+
+pub struct Frame {
+    pub delta: f32,
+    /// Lines entered at the console, which run before `commands`.
+    pub console: Vec<String>,
+    pub commands: Vec<Command>,
+}
+
+pub struct Recording {
+    pub frames: Vec<Frame>,
+}
+
+impl Recording {
+    pub fn read(path: &Path) -> Result<Recording, String> {
+        use std::io::Read;
+
+        let mut text = String::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+        Recording::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Recording, String> {
+        let mut frames = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with("frame ") {
+                let delta = try!(line[6..]
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| format!("Line {}: bad frame time", number + 1)));
+                frames.push(Frame {
+                    delta: delta,
+                    console: Vec::new(),
+                    commands: Vec::new(),
+                });
+                continue;
+            }
+
+            if line.starts_with("console ") {
+                match frames.last_mut() {
+                    Some(frame) => frame.console.push(line[8..].trim().into()),
+                    None => {
+                        return Err(format!("Line {}: console line before the first frame",
+                                           number + 1))
+                    }
+                }
+                continue;
+            }
+
+            let command = try!(parse_command(line)
+                .map_err(|e| format!("Line {}: {}", number + 1, e)));
+            match frames.last_mut() {
+                Some(frame) => frame.commands.push(command),
+                None => {
+                    return Err(format!("Line {}: command before the first frame", number + 1))
+                }
+            }
+        }
+
+        Ok(Recording { frames: frames })
+    }
+
+    /// Applies every frame to `game` in order, running console lines with
+    /// `console` and stopping early at an exit. Returns the number of frames
+    /// played.
+    pub fn play(&self, game: &mut Game, console: &CommandRegistry) -> usize {
+        for (played, frame) in self.frames.iter().enumerate() {
+            for line in &frame.console {
+                if let Err(e) = console.run(game, line) {
+                    warn!("Console line \"{}\" failed: {}", line, e);
+                }
+            }
+            for command in &frame.commands {
+                if let Outcome::Exit = game.apply(command) {
+                    return played + 1;
+                }
+            }
+            game.advance(frame.delta);
+        }
+        self.frames.len()
+    }
+}
+
+/// Writes frames as they happen, so a recording survives a crash.
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Recorder, String> {
+        let file = try!(File::create(path)
+            .map_err(|e| format!("Could not create {:?}: {}", path, e)));
+        Ok(Recorder { out: BufWriter::new(file) })
+    }
+
+    pub fn record_frame(&mut self, delta: f32, console: &[String], commands: &[Command]) {
+        let mut text = format!("frame {}\n", delta);
+        for line in console {
+            text.push_str(&format!("console {}\n", line));
+        }
+        for line in commands.iter().filter_map(command_to_line) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        if let Err(e) = self.out.write_all(text.as_bytes()).and_then(|_| self.out.flush()) {
+            warn!("Could not write recording: {}", e);
+        }
+    }
+}
+
+/// The recording line for `command`. `Noop` is not recorded, and neither is
+/// opening the console; the lines entered there are recorded separately.
+pub fn command_to_line(command: &Command) -> Option<String> {
+    Some(match *command {
+        Command::Noop | Command::ToggleConsole => return None,
+        Command::Exit => "exit".into(),
+        Command::CameraTranslate(ref state, amount) => {
+            let state = match *state {
+                State::Start => "start",
+                State::Stop => "stop",
+            };
+            format!("move {} {} {} {}", state, amount.x, amount.y, amount.z)
+        }
+        Command::CameraLook(cursor) => format!("look {} {}", cursor.x, cursor.y),
+        Command::Place => "place".into(),
+        Command::Break => "break".into(),
+        Command::Save => "save".into(),
+        Command::Undo => "undo".into(),
+        Command::Redo => "redo".into(),
+        Command::SelectSlot(slot) => format!("slot {}", slot),
+        Command::ScrollHotbar(steps) => format!("scroll {}", steps),
+        Command::PickBlock => "pick".into(),
+    })
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let numbers = |from: usize, count: usize| -> Result<Vec<f32>, String> {
+        if words.len() != from + count {
+            return Err(format!("Expected {} numbers in \"{}\"", count, line));
+        }
+        words[from..]
+            .iter()
+            .map(|w| w.parse::<f32>().map_err(|_| format!("Bad number \"{}\"", w)))
+            .collect()
+    };
+
+    match words.get(0).cloned().unwrap_or("") {
+        "exit" => Ok(Command::Exit),
+        "place" => Ok(Command::Place),
+        "break" => Ok(Command::Break),
+        "save" => Ok(Command::Save),
+        "undo" => Ok(Command::Undo),
+        "redo" => Ok(Command::Redo),
+        "pick" => Ok(Command::PickBlock),
+        "move" => {
+            let state = match words.get(1).cloned() {
+                Some("start") => State::Start,
+                Some("stop") => State::Stop,
+                _ => return Err(format!("Expected start or stop in \"{}\"", line)),
+            };
+            let v = try!(numbers(2, 3));
+            Ok(Command::CameraTranslate(state, vec3(v[0], v[1], v[2])))
+        }
+        "look" => {
+            let v = try!(numbers(1, 2));
+            Ok(Command::CameraLook(vec2(v[0], v[1])))
+        }
+        "slot" => {
+            if words.len() != 2 {
+                return Err(format!("Expected a slot in \"{}\"", line));
+            }
+            match words[1].parse::<usize>() {
+                Ok(slot) if slot < SLOT_COUNT => Ok(Command::SelectSlot(slot)),
+                _ => Err(format!("Bad slot \"{}\"", words[1])),
+            }
+        }
+        "scroll" => {
+            let v = try!(numbers(1, 1));
+            Ok(Command::ScrollHotbar(v[0] as i32))
+        }
+        other => Err(format!("Unknown command \"{}\"", other)),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let commands = vec![Command::CameraTranslate(State::Stop, vec3(0.0, -1.0, 0.5)),
+                        Command::CameraLook(vec2(512.25, 497.0)),
+                        Command::SelectSlot(3),
+                        Command::ScrollHotbar(-1),
+                        Command::Place];
+    for command in &commands {
+        let line = command_to_line(command).unwrap();
+        assert_eq!(command_to_line(&parse_command(&line).unwrap()).unwrap(), line);
+    }
+    assert!(command_to_line(&Command::Noop).is_none());
+    assert!(parse_command("jump").is_err());
+    assert!(parse_command("look 1").is_err());
+    assert!(parse_command("slot -1").is_err());
+    assert!(parse_command("slot 1.5").is_err());
+    assert!(parse_command("slot 9").is_err());
+    assert!(Recording::parse("place\n").is_err());
+}
+
+#[test]
+fn replay_edits_world() {
+    use world::{World, Access};
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    let stone = world.registry.lookup_id(&"stone".into()).unwrap();
+    let dirt = world.registry.lookup_id(&"dirt".into()).unwrap();
+    world.apply_changes((0..3).map(|x| (point3(x, 0, 0), stone)));
+
+    // Look straight down at the middle block, stack dirt on it, break the
+    // dirt and undo that
+    let mut game = Game::new(world);
+    game.camera = ::graphics::Camera::new(Point3::new(1.5, 3.5, 0.5), 0.0, 3.1);
+    let recording = Recording::parse("frame 0.016\n\
+                                      slot 1\n\
+                                      place\n\
+                                      frame 0.016\n\
+                                      console set block 0 1 0 dirt\n\
+                                      break\n\
+                                      undo\n\
+                                      exit\n\
+                                      frame 0.016\n\
+                                      break\n")
+        .unwrap();
+    assert_eq!(recording.play(&mut game, &::console::Console::new().commands), 2);
+
+    assert_eq!(game.world.get_block(point3(1, 0, 0)).id, stone);
+    assert_eq!(game.world.get_block(point3(1, 1, 0)).id, dirt);
+    assert_eq!(game.world.get_block(point3(0, 1, 0)).id, dirt);
+    assert_eq!(game.hotbar.current(), Some(dirt));
+}
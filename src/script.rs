@@ -0,0 +1,207 @@
+//! Rhai scripting against a loaded world, for build tools that should not
+//! need a recompile. Scripts see the world as `world`:
+//!
+//! ```text
+//! let below = world.get_block(0, 40, 0);        // block name, or "air"
+//! world.set_block(0, 41, 0, "log");             // "air" removes a block
+//! let id = world.block_id("stone");             // () if there is no such block
+//! let name = world.block_name(id);
+//! let hit = world.raycast(0.5, 60.0, 0.5, 0.0, -60.0, 0.0);
+//! if hit != () { print(hit.block + " at " + hit.y); }
+//! ```
+//!
+//! A raycast reaches as far as the length of its direction, as
+//! `World::cast_ray` does, and gives a map with `x`, `y`, `z`, `block`,
+//! `distance` and, unless the ray started inside the block, `face`.
+//!
+//! Scripts may set blocks anywhere; chunks outside the loaded region are
+//! loaded first. Reading a chunk that is not loaded gives air.
+
+use std::cell::RefCell;
+use std::iter;
+use std::rc::Rc;
+
+use rhai::{Engine, Scope, Dynamic, Map, EvalAltResult, INT, FLOAT};
+
+use prelude::*;
+use world::{World, find_chunk_origin};
+use world::block::BlockID;
+use world::prefab::AIR;
+
+/// The world as scripts see it. Shared so the caller gets it back once the
+/// script is done.
+#[derive(Clone)]
+pub struct ScriptWorld(pub Rc<RefCell<World>>);
+
+impl ScriptWorld {
+    fn name_of(&self, id: BlockID) -> String {
+        if id == BlockID(0) {
+            return AIR.into();
+        }
+        match self.0.borrow().registry.lookup_name(id) {
+            Some(name) => name.clone(),
+            None => format!("#{}", id.0),
+        }
+    }
+
+    fn id_of(&self, name: &str) -> Option<BlockID> {
+        if name == AIR {
+            Some(BlockID(0))
+        } else {
+            self.0.borrow().registry.lookup_id(&name.into())
+        }
+    }
+}
+
+/// Limits that stop a runaway script, which would otherwise hang the tool
+/// with the world half edited.
+const MAX_OPERATIONS: u64 = 50_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.register_type_with_name::<ScriptWorld>("World");
+
+    engine.register_fn("get_block", |world: &mut ScriptWorld, x: INT, y: INT, z: INT| {
+        let id = world.0.borrow().get_block(point3(x as i32, y as i32, z as i32)).id;
+        world.name_of(id)
+    });
+
+    engine.register_fn("set_block",
+                       |world: &mut ScriptWorld,
+                        x: INT,
+                        y: INT,
+                        z: INT,
+                        name: &str|
+                        -> Result<(), Box<EvalAltResult>> {
+        let id = try!(world.id_of(name).ok_or(format!("Unknown block \"{}\"", name)));
+        let loc = point3(x as i32, y as i32, z as i32);
+        // A chunk outside the loaded region would otherwise start out empty
+        // and be saved over the real one
+        try!(world.0.borrow_mut().require_chunk(find_chunk_origin(loc)).map(|_| ()));
+        world.0.borrow_mut().apply_changes(iter::once((loc, id)));
+        Ok(())
+    });
+
+    engine.register_fn("block_id", |world: &mut ScriptWorld, name: &str| {
+        match world.id_of(name) {
+            Some(id) => Dynamic::from(id.0 as INT),
+            None => Dynamic::UNIT,
+        }
+    });
+
+    engine.register_fn("block_name",
+                       |world: &mut ScriptWorld, id: INT| world.name_of(BlockID(id as u32)));
+
+    engine.register_fn("raycast",
+                       |world: &mut ScriptWorld,
+                        x: FLOAT,
+                        y: FLOAT,
+                        z: FLOAT,
+                        dx: FLOAT,
+                        dy: FLOAT,
+                        dz: FLOAT| {
+        let origin = Point3::new(x as f32, y as f32, z as f32);
+        let direction = vec3(dx as f32, dy as f32, dz as f32);
+        let hit = world.0.borrow().cast_ray(origin, direction);
+        match hit {
+            Some(hit) => {
+                let mut map = Map::new();
+                map.insert("x".into(), Dynamic::from(hit.loc.x as INT));
+                map.insert("y".into(), Dynamic::from(hit.loc.y as INT));
+                map.insert("z".into(), Dynamic::from(hit.loc.z as INT));
+                map.insert("block".into(), Dynamic::from(world.name_of(hit.block.id)));
+                map.insert("distance".into(), Dynamic::from(hit.distance as FLOAT));
+                if let Some(face) = hit.face {
+                    let face = format!("{:?}", face).to_lowercase();
+                    map.insert("face".into(), Dynamic::from(face));
+                }
+                Dynamic::from_map(map)
+            }
+            None => Dynamic::UNIT,
+        }
+    });
+
+    engine
+}
+
+/// Runs `source` against `world` as a single undo step. `name` is only used
+/// in error messages.
+pub fn run(world: &Rc<RefCell<World>>, source: &str, name: &str) -> Result<(), String> {
+    let engine = new_engine();
+    let mut scope = Scope::new();
+    scope.push("world", ScriptWorld(world.clone()));
+
+    world.borrow_mut().begin_edit();
+    let result = engine.run_with_scope(&mut scope, source);
+    world.borrow_mut().end_edit();
+
+    result.map_err(|e| format!("Script error in {}: {}", name, e))
+}
+
+#[test]
+fn world_from_scripts() {
+    use std::path::Path;
+    use world::Access;
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let world = World::from_path(Path::new("/nonexistent"),
+                                 (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                 chunk_gen,
+                                 Access::ReadOnly)
+        .unwrap();
+    let world = Rc::new(RefCell::new(world));
+
+    let source = "world.set_block(1, 2, 3, \"dirt\");\n\
+                  let hit = world.raycast(1.5, 10.0, 3.5, 0.0, -10.0, 0.0);\n\
+                  if hit.block != \"dirt\" || hit.y != 2 { throw \"hit\"; }\n\
+                  if hit.face != \"top\" { throw \"face\"; }\n\
+                  if world.get_block(1, 3, 3) != \"air\" { throw \"air\"; }\n\
+                  world.set_block(4, 4, 4, world.block_name(world.block_id(\"log\")));";
+    run(&world, source, "test").unwrap();
+    {
+        let world = world.borrow();
+        assert_eq!(world.get_block(point3(1, 2, 3)).id, BlockID(2));
+        assert_eq!(world.get_block(point3(4, 4, 4)).id, BlockID(4));
+    }
+
+    assert!(run(&world, "world.set_block(0, 0, 0, \"lava\");", "test").is_err());
+    assert!(run(&world, "world.raycast(0.5, 9.0, 0.5, 0.0, 1.0, 0.0) == ()", "test").is_ok());
+
+    // Both edits undo together
+    assert!(world.borrow_mut().undo());
+    assert!(world.borrow().get_block(point3(1, 2, 3)).is_empty());
+    assert!(world.borrow().get_block(point3(4, 4, 4)).is_empty());
+}
+
+#[test]
+fn scripts_load_chunks_they_edit() {
+    use std::env;
+    use std::fs;
+    use world::Access;
+    use world::chunk::Chunk;
+    use world::terrain::FlatGenerator;
+
+    let root = env::temp_dir().join("subsurface-script-edit-test");
+    let _ = fs::remove_dir_all(&root);
+    let open = |extents: (Vector3<i32>, Vector3<i32>)| {
+        let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+        World::from_path(&root, extents, chunk_gen, Access::ReadWrite).unwrap()
+    };
+    open((vec3(0, 0, 0), vec3(32, 16, 16))).write_all_chunks();
+
+    // Only the first chunk is loaded, but the script edits the second
+    let world = Rc::new(RefCell::new(open((vec3(0, 0, 0), vec3(16, 16, 16)))));
+    run(&world, "world.set_block(17, 1, 1, \"dirt\");", "test").unwrap();
+    world.borrow_mut().write_all_chunks();
+
+    let saved = Chunk::read(point3(16, 0, 0), &root).unwrap();
+    assert_eq!(saved.get_block(point3(17, 1, 1)).id, BlockID(2));
+    assert_eq!(saved.get_block(point3(20, 0, 5)).id, BlockID(1));
+
+    drop(world);
+    fs::remove_dir_all(&root).unwrap();
+}
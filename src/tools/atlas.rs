@@ -0,0 +1,54 @@
+use image;
+use image::RgbaImage;
+
+use prelude::*;
+
+/// Side of one block texture in the atlas, in pixels.
+pub const TEXEL_SIZE: u32 = 16;
+
+pub const ATLAS_PNG: &'static [u8] = include_bytes!("../../resources/textures/blocks.png");
+
+/// CPU side copy of the block texture atlas, for tools that need block
+/// colours without a GPU.
+pub struct Atlas {
+    image: RgbaImage,
+}
+
+impl Atlas {
+    pub fn load() -> Atlas {
+        Atlas { image: image::load_from_memory(ATLAS_PNG).unwrap().to_rgba() }
+    }
+
+    /// Atlas size in texels, matching the units of `BlockUV`.
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height) = self.image.dimensions();
+        (width / TEXEL_SIZE, height / TEXEL_SIZE)
+    }
+
+    /// Mean colour of the texel at `uv`, or `None` if `uv` lies outside the
+    /// atlas.
+    pub fn average_color(&self, uv: Point2<u8>) -> Option<[u8; 4]> {
+        let (width, height) = self.size();
+        if uv.x as u32 >= width || uv.y as u32 >= height {
+            return None;
+        }
+
+        let mut sum = [0u32; 4];
+        for y in 0..TEXEL_SIZE {
+            for x in 0..TEXEL_SIZE {
+                let pixel = self.image
+                    .get_pixel(uv.x as u32 * TEXEL_SIZE + x, uv.y as u32 * TEXEL_SIZE + y)
+                    .data;
+                for i in 0..4 {
+                    sum[i] += pixel[i] as u32;
+                }
+            }
+        }
+
+        let count = TEXEL_SIZE * TEXEL_SIZE;
+        Some([(sum[0] / count) as u8,
+              (sum[1] / count) as u8,
+              (sum[2] / count) as u8,
+              (sum[3] / count) as u8])
+    }
+}
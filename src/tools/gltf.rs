@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rustc_serialize::json::Json;
+
+use world::{WorldPoint, Access};
+use super::atlas::ATLAS_PNG;
+use super::mesh::{mesh_region, RegionMesh};
+
+const ARRAY_BUFFER: u64 = 34962;
+const ELEMENT_ARRAY_BUFFER: u64 = 34963;
+const FLOAT: u64 = 5126;
+const UNSIGNED_INT: u64 = 5125;
+const NEAREST: u64 = 9728;
+const CLAMP_TO_EDGE: u64 = 33071;
+
+/// Writes the blocks in the inclusive box `from`..`to` as a binary glTF 2.0
+/// file with the block atlas embedded. Light levels become vertex colours,
+/// using the same curve as the block shader.
+pub fn export(world_root: &Path,
+              from: WorldPoint,
+              to: WorldPoint,
+              output: &Path)
+              -> Result<(), String> {
+    use std::io::Write;
+    use std::fs::File;
+
+    let world = try!(super::open_region(world_root, from, to, None, Access::ReadOnly));
+    let mesh = mesh_region(&world, from, to);
+    if mesh.indices.is_empty() {
+        return Err(format!("No blocks between {:?} and {:?}", from, to));
+    }
+
+    try!(File::create(output)
+        .and_then(|mut f| f.write_all(&encode_glb(&mesh)))
+        .map_err(|e| format!("Could not write {:?}: {}", output, e)));
+
+    println!("Wrote {} vertices and {} triangles to {:?}",
+             mesh.vertices.len(),
+             mesh.indices.len() / 3,
+             output);
+    Ok(())
+}
+
+/// Brightness of a vertex, matching `resources/vertex.glsl`.
+fn brightness(light: u8) -> f32 {
+    let sun = ((light >> 4) & 0xF) as f32 / 16.0;
+    let block = (light & 0xF) as f32 / 16.0;
+    ((sun + block) * 0.9 + 0.1).max(0.0).min(1.0).powf(2.2)
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    let mut map = BTreeMap::new();
+    for (key, value) in fields {
+        map.insert(key.to_string(), value);
+    }
+    Json::Object(map)
+}
+
+fn num(value: u64) -> Json {
+    Json::U64(value)
+}
+
+fn floats(values: &[f32]) -> Json {
+    Json::Array(values.iter().map(|&v| Json::F64(v as f64)).collect())
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[value as u8,
+                            (value >> 8) as u8,
+                            (value >> 16) as u8,
+                            (value >> 24) as u8]);
+}
+
+fn put_f32(out: &mut Vec<u8>, value: f32) {
+    put_u32(out, value.to_bits());
+}
+
+fn pad(out: &mut Vec<u8>, with: u8) {
+    while out.len() % 4 != 0 {
+        out.push(with);
+    }
+}
+
+/// Appends `data` to the binary chunk and describes it with a buffer view,
+/// returning the view's index.
+fn add_view(bin: &mut Vec<u8>, views: &mut Vec<Json>, data: &[u8], target: Option<u64>) -> u64 {
+    pad(bin, 0);
+    let mut fields = vec![("buffer", num(0)),
+                          ("byteOffset", num(bin.len() as u64)),
+                          ("byteLength", num(data.len() as u64))];
+    if let Some(target) = target {
+        fields.push(("target", num(target)));
+    }
+    bin.extend_from_slice(data);
+    views.push(object(fields));
+    views.len() as u64 - 1
+}
+
+pub fn encode_glb(mesh: &RegionMesh) -> Vec<u8> {
+    let mut bin = Vec::new();
+    let mut views = Vec::new();
+
+    let mut min = [::std::f32::MAX; 3];
+    let mut max = [::std::f32::MIN; 3];
+    let (mut positions, mut normals, mut uvs, mut colors) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for v in &mesh.vertices {
+        for i in 0..3 {
+            let p = v.position[i] as f32;
+            min[i] = min[i].min(p);
+            max[i] = max[i].max(p);
+            put_f32(&mut positions, p);
+            put_f32(&mut normals, v.normal[i] as f32);
+        }
+        put_f32(&mut uvs, v.uv[0] as f32 / 65535.0);
+        put_f32(&mut uvs, v.uv[1] as f32 / 65535.0);
+        let b = brightness(v.light);
+        for _ in 0..3 {
+            put_f32(&mut colors, b);
+        }
+    }
+    let mut indices = Vec::new();
+    for &i in &mesh.indices {
+        put_u32(&mut indices, i);
+    }
+
+    let count = mesh.vertices.len() as u64;
+    let position_view = add_view(&mut bin, &mut views, &positions, Some(ARRAY_BUFFER));
+    let normal_view = add_view(&mut bin, &mut views, &normals, Some(ARRAY_BUFFER));
+    let uv_view = add_view(&mut bin, &mut views, &uvs, Some(ARRAY_BUFFER));
+    let color_view = add_view(&mut bin, &mut views, &colors, Some(ARRAY_BUFFER));
+    let index_view = add_view(&mut bin, &mut views, &indices, Some(ELEMENT_ARRAY_BUFFER));
+    let image_view = add_view(&mut bin, &mut views, ATLAS_PNG, None);
+    pad(&mut bin, 0);
+
+    let accessor = |view: u64, kind: &str, component: u64, count: u64| {
+        object(vec![("bufferView", num(view)),
+                    ("componentType", num(component)),
+                    ("count", num(count)),
+                    ("type", Json::String(kind.into()))])
+    };
+    let mut position_accessor = accessor(position_view, "VEC3", FLOAT, count);
+    if let Json::Object(ref mut fields) = position_accessor {
+        fields.insert("min".into(), floats(&min));
+        fields.insert("max".into(), floats(&max));
+    }
+
+    let gltf = object(vec![
+        ("asset", object(vec![("version", Json::String("2.0".into())),
+                              ("generator", Json::String("subsurface".into()))])),
+        ("scene", num(0)),
+        ("scenes", Json::Array(vec![object(vec![("nodes", Json::Array(vec![num(0)]))])])),
+        ("nodes", Json::Array(vec![object(vec![("mesh", num(0)),
+                                               ("name", Json::String("region".into()))])])),
+        ("meshes", Json::Array(vec![object(vec![
+            ("primitives", Json::Array(vec![object(vec![
+                ("attributes", object(vec![("POSITION", num(0)),
+                                           ("NORMAL", num(1)),
+                                           ("TEXCOORD_0", num(2)),
+                                           ("COLOR_0", num(3))])),
+                ("indices", num(4)),
+                ("material", num(0)),
+            ])])),
+        ])])),
+        ("accessors", Json::Array(vec![
+            position_accessor,
+            accessor(normal_view, "VEC3", FLOAT, count),
+            accessor(uv_view, "VEC2", FLOAT, count),
+            accessor(color_view, "VEC3", FLOAT, count),
+            accessor(index_view, "SCALAR", UNSIGNED_INT, mesh.indices.len() as u64),
+        ])),
+        ("materials", Json::Array(vec![object(vec![
+            ("name", Json::String("blocks".into())),
+            ("pbrMetallicRoughness", object(vec![
+                ("baseColorTexture", object(vec![("index", num(0))])),
+                ("metallicFactor", Json::F64(0.0)),
+                ("roughnessFactor", Json::F64(1.0)),
+            ])),
+        ])])),
+        ("textures", Json::Array(vec![object(vec![("source", num(0)), ("sampler", num(0))])])),
+        ("samplers", Json::Array(vec![object(vec![("magFilter", num(NEAREST)),
+                                                  ("minFilter", num(NEAREST)),
+                                                  ("wrapS", num(CLAMP_TO_EDGE)),
+                                                  ("wrapT", num(CLAMP_TO_EDGE))])])),
+        ("images", Json::Array(vec![object(vec![
+            ("bufferView", num(image_view)),
+            ("mimeType", Json::String("image/png".into())),
+        ])])),
+        ("bufferViews", Json::Array(views)),
+        ("buffers", Json::Array(vec![object(vec![("byteLength", num(bin.len() as u64))])])),
+    ]);
+
+    let mut json = gltf.to_string().into_bytes();
+    pad(&mut json, b' ');
+
+    let mut glb = Vec::new();
+    glb.extend_from_slice(b"glTF");
+    put_u32(&mut glb, 2);
+    put_u32(&mut glb, (12 + 8 + json.len() + 8 + bin.len()) as u32);
+    put_u32(&mut glb, json.len() as u32);
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    put_u32(&mut glb, bin.len() as u32);
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&bin);
+    glb
+}
+
+#[test]
+fn single_block_glb() {
+    let mesh = super::mesh::single_block_mesh();
+    let glb = encode_glb(&mesh);
+    let u32_at = |at: usize| {
+        glb[at] as u32 | (glb[at + 1] as u32) << 8 | (glb[at + 2] as u32) << 16 |
+        (glb[at + 3] as u32) << 24
+    };
+
+    assert_eq!(&glb[0..4], b"glTF");
+    assert_eq!(u32_at(4), 2);
+    assert_eq!(u32_at(8) as usize, glb.len());
+
+    let json_len = u32_at(12) as usize;
+    assert_eq!(&glb[16..20], b"JSON");
+    let gltf = Json::from_str(::std::str::from_utf8(&glb[20..20 + json_len]).unwrap()).unwrap();
+    let bin_start = 20 + json_len + 8;
+    assert_eq!(&glb[bin_start - 4..bin_start], b"BIN\0");
+    assert_eq!(u32_at(20 + json_len) as usize, glb.len() - bin_start);
+
+    let field = |json: &Json, key: &str| json.find(key).and_then(|v| v.as_u64()).unwrap();
+    let accessors = gltf.find("accessors").and_then(|a| a.as_array()).unwrap();
+    let views = gltf.find("bufferViews").and_then(|v| v.as_array()).unwrap();
+    let counts: Vec<u64> = accessors.iter().map(|a| field(a, "count")).collect();
+    assert_eq!(counts, vec![24, 24, 24, 24, 36]);
+
+    // The embedded image is the atlas, byte for byte
+    let images = gltf.find("images").and_then(|i| i.as_array()).unwrap();
+    let image_view = &views[field(&images[0], "bufferView") as usize];
+    let offset = bin_start + field(image_view, "byteOffset") as usize;
+    assert_eq!(&glb[offset..offset + field(image_view, "byteLength") as usize],
+               ATLAS_PNG);
+
+    // COLOR_0 is grey at the shader's brightness for each vertex's light
+    let color_view = &views[field(&accessors[3], "bufferView") as usize];
+    let offset = bin_start + field(color_view, "byteOffset") as usize;
+    assert_eq!(field(color_view, "byteLength"), 24 * 3 * 4);
+    for (i, vertex) in mesh.vertices.iter().enumerate() {
+        let sun = (vertex.light >> 4) as f32 / 16.0;
+        let block = (vertex.light & 0xF) as f32 / 16.0;
+        let expected = ((sun + block) * 0.9 + 0.1).min(1.0).powf(2.2);
+        for c in 0..3 {
+            let value = f32::from_bits(u32_at(offset + (i * 3 + c) * 4));
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::hash_map::HashMap;
+use std::path::Path;
+
+use prelude::*;
+use world::{WorldPoint, Access};
+use world::block::{BlockID, Face};
+use world::registry::Registry;
+use world::vox::{VoxFile, VoxMapping};
+use super::atlas::Atlas;
+
+/// Places a MagicaVoxel model in a saved world with its minimum corner at
+/// `origin`, and saves the chunks it touches.
+pub fn import(world_root: &Path,
+              vox_path: &Path,
+              origin: WorldPoint,
+              mapping_path: &Path)
+              -> Result<(), String> {
+    let vox = try!(VoxFile::read(vox_path));
+    let mapping = try!(VoxMapping::from_path(mapping_path));
+    let size = vox.world_size();
+    if size.x == 0 {
+        return Err(format!("{:?} has no voxels", vox_path));
+    }
+
+    let to = origin + size - vec3(1, 1, 1);
+    let mut world = try!(super::open_region(world_root, origin, to, None, Access::ReadWrite));
+    let placed = try!(world.import_vox(&vox, origin, &mapping));
+    world.write_all_chunks();
+
+    println!("Placed {} blocks from {:?} between {:?} and {:?}",
+             placed,
+             vox_path,
+             origin,
+             to);
+    Ok(())
+}
+
+/// Writes the inclusive box `from`..`to` of a saved world to a MagicaVoxel
+/// file. `mapping_path` optionally pins palette indices and colours; other
+/// blocks are coloured from the texture atlas.
+pub fn export(world_root: &Path,
+              from: WorldPoint,
+              to: WorldPoint,
+              mapping_path: Option<&Path>,
+              output: &Path)
+              -> Result<(), String> {
+    let mapping = match mapping_path {
+        Some(path) => try!(VoxMapping::from_path(path)),
+        None => {
+            VoxMapping {
+                blocks: HashMap::new(),
+                colors: HashMap::new(),
+            }
+        }
+    };
+
+    let world = try!(super::open_region(world_root, from, to, None, Access::ReadOnly));
+    let vox = try!(world.export_vox(from, to, &mapping, &block_colors(&world.registry)));
+    try!(vox.write(output));
+
+    println!("Wrote {} models with {} voxels to {:?}",
+             vox.models.len(),
+             vox.models.iter().map(|m| m.voxels.len()).sum::<usize>(),
+             output);
+    Ok(())
+}
+
+/// Mean colour of the top face of every registered block.
+fn block_colors(registry: &Registry) -> HashMap<BlockID, [u8; 4]> {
+    let atlas = Atlas::load();
+    registry.block_ids()
+        .into_iter()
+        .filter_map(|id| {
+            registry.lookup_texture(id)
+                .and_then(|uv| atlas.average_color(uv.get_face(Face::Top)))
+                .map(|color| (id, color))
+        })
+        .collect()
+}
@@ -0,0 +1,192 @@
+use std::collections::hash_map::HashMap;
+use std::path::Path;
+
+use prelude::*;
+use world::{WorldMeta, WorldPoint, Access};
+use world::block::BlockID;
+use world::chunk::{Chunk, CHUNK_SIZE};
+use world::lock::SessionLock;
+use world::registry::Registry;
+
+/// Generates every chunk touching the box `from`..`to` and saves it, using
+/// the generator from the world's metadata. Stops without writing anything if
+/// a saved chunk in the box cannot be read.
+pub fn generate(world_root: &Path,
+                from: WorldPoint,
+                to: WorldPoint,
+                default_generator: &str)
+                -> Result<(), String> {
+    let (low, high) = chunk_aligned(from, to);
+    let mut world = try!(super::open_region(world_root,
+                                            from,
+                                            to,
+                                            Some(default_generator),
+                                            Access::ReadWrite)
+        .map_err(|e| format!("{} (see the verify and prune commands)", e)));
+    world.write_all_chunks();
+
+    println!("Generated {} chunks from {:?} to {:?}",
+             world.chunk_count(),
+             low,
+             high);
+    Ok(())
+}
+
+/// Prints the chunk count, size on disk, block histogram and metadata.
+pub fn info(world_root: &Path) -> Result<(), String> {
+    use std::fs::metadata;
+
+    let registry = Registry::new();
+    let origins = Chunk::list(world_root);
+    let meta = try!(WorldMeta::read(world_root));
+    if origins.is_empty() && meta.is_none() {
+        return Err(format!("No world found at {:?}", world_root));
+    }
+
+    let mut bytes = 0;
+    let mut histogram: HashMap<BlockID, u64> = HashMap::new();
+    let mut corrupt = 0;
+    for &origin in &origins {
+        let path = Chunk::path(origin, world_root).join("chunk.bincode");
+        bytes += metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+        match Chunk::try_read(origin, world_root) {
+            Ok(Some(chunk)) => {
+                for loc in chunk.iter() {
+                    *histogram.entry(chunk.get_block_local(loc).id).or_insert(0) += 1;
+                }
+            }
+            Ok(None) => corrupt += 1,
+            Err(e) => {
+                warn!("Skipping chunk at {:?}: {}", origin, e);
+                corrupt += 1;
+            }
+        }
+    }
+
+    println!("World:     {:?}", world_root);
+    match meta {
+        Some(meta) => {
+            println!("Generator: {}", meta.generator);
+            println!("Seed:      {}", meta.seed);
+        }
+        None => println!("Metadata:  missing"),
+    }
+    println!("Chunks:    {} ({} unreadable)", origins.len(), corrupt);
+    println!("On disk:   {} KiB", bytes / 1024);
+
+    let mut counts: Vec<(BlockID, u64)> = histogram.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    println!("Blocks:");
+    for (id, count) in counts {
+        let name = match registry.lookup_name(id) {
+            Some(name) => name.clone(),
+            None if id == BlockID(0) => "air".into(),
+            None => format!("unknown ({})", id.0),
+        };
+        println!("  {:>12} {}", count, name);
+    }
+
+    Ok(())
+}
+
+/// Decodes every saved chunk and reports the ones that fail. Returns an
+/// error if any chunk is corrupt.
+pub fn verify(world_root: &Path) -> Result<(), String> {
+    let origins = Chunk::list(world_root);
+    let mut corrupt = 0;
+    for &origin in &origins {
+        match Chunk::try_read(origin, world_root) {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                println!("{:?}: missing", origin);
+                corrupt += 1;
+            }
+            Err(e) => {
+                println!("{:?}: {}", origin, e);
+                corrupt += 1;
+            }
+        }
+    }
+
+    println!("Checked {} chunks, {} corrupt", origins.len(), corrupt);
+    if corrupt > 0 {
+        Err(format!("{} corrupt chunks", corrupt))
+    } else {
+        Ok(())
+    }
+}
+
+/// Deletes every saved chunk that does not touch the box `from`..`to`.
+pub fn prune(world_root: &Path, from: WorldPoint, to: WorldPoint) -> Result<(), String> {
+    // Deleting chunks under a running game would lose its edits
+    let _lock = try!(SessionLock::acquire(world_root));
+    let (low, high) = chunk_aligned(from, to);
+    let mut removed = 0;
+    for origin in Chunk::list(world_root) {
+        if origin.x >= low.x && origin.y >= low.y && origin.z >= low.z && origin.x < high.x &&
+           origin.y < high.y && origin.z < high.z {
+            continue;
+        }
+
+        try!(Chunk::remove(origin, world_root));
+        removed += 1;
+    }
+
+    println!("Removed {} chunks outside {:?} to {:?}", removed, low, high);
+    Ok(())
+}
+
+/// Expands a block box, inclusive at both ends, to chunk boundaries. The
+/// result is inclusive at the low end and exclusive at the high end, as
+/// `World::from_path` expects.
+pub fn chunk_aligned(from: WorldPoint, to: WorldPoint) -> (Vector3<i32>, Vector3<i32>) {
+    fn floor(v: i32) -> i32 {
+        v & -CHUNK_SIZE
+    }
+
+    let low = vec3(floor(from.x.min(to.x)),
+                   floor(from.y.min(to.y)),
+                   floor(from.z.min(to.z)));
+    let high = vec3(floor(from.x.max(to.x)) + CHUNK_SIZE,
+                    floor(from.y.max(to.y)) + CHUNK_SIZE,
+                    floor(from.z.max(to.z)) + CHUNK_SIZE);
+    (low, high)
+}
+
+#[test]
+fn aligned_boxes() {
+    assert_eq!(chunk_aligned(point3(0, 0, 0), point3(15, 15, 15)),
+               (vec3(0, 0, 0), vec3(16, 16, 16)));
+    assert_eq!(chunk_aligned(point3(5, -1, 20), point3(-20, 3, 16)),
+               (vec3(-32, -16, 16), vec3(16, 16, 32)));
+}
+
+#[test]
+fn verify_and_prune() {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    let root = env::temp_dir().join("subsurface-manage-test");
+    let _ = fs::remove_dir_all(&root);
+    for &origin in &[point3(0, 0, 0), point3(16, 0, 0), point3(64, 16, 0)] {
+        Chunk::new(origin).write(&root);
+    }
+    assert!(verify(&root).is_ok());
+
+    fs::File::create(Chunk::path(point3(16, 0, 0), &root).join("chunk.bincode"))
+        .unwrap()
+        .write_all(b"junk")
+        .unwrap();
+    assert!(verify(&root).is_err());
+    // Generating over a corrupt chunk would replace it, so it stops instead
+    assert!(generate(&root, point3(16, 0, 0), point3(16, 0, 0), "flat").is_err());
+    assert!(info(&root).is_ok());
+
+    prune(&root, point3(0, 0, 0), point3(15, 15, 15)).unwrap();
+    assert_eq!(Chunk::list(&root), vec![point3(0, 0, 0)]);
+    assert!(verify(&root).is_ok());
+
+    fs::remove_dir_all(&root).unwrap();
+}
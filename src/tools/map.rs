@@ -0,0 +1,122 @@
+use std::collections::hash_map::HashMap;
+use std::path::Path;
+
+use image::{ImageBuffer, Rgba};
+
+use prelude::*;
+use world::block::{BlockID, Face};
+use world::chunk::Chunk;
+use world::registry::Registry;
+use super::atlas::Atlas;
+
+/// Renders a top-down map of the saved chunks in `world_root` to a PNG at
+/// `output`, one pixel per column. Each pixel takes the colour of the highest
+/// non-empty block, darkened with depth and shaded by slope.
+pub fn render(world_root: &Path, output: &Path) -> Result<(), String> {
+    let registry = Registry::new();
+    let atlas = Atlas::load();
+
+    let mut columns: HashMap<(i32, i32), (i32, BlockID)> = HashMap::new();
+    for origin in Chunk::list(world_root) {
+        let chunk = try!(try!(Chunk::try_read(origin, world_root))
+            .ok_or(format!("Could not read chunk at {:?}", origin)));
+
+        for loc in chunk.iter() {
+            let block = chunk.get_block_local(loc);
+            if block.is_empty() {
+                continue;
+            }
+
+            let world_loc = origin + vec3(loc.x as i32, loc.y as i32, loc.z as i32);
+            let column = columns.entry((world_loc.x, world_loc.z))
+                .or_insert((world_loc.y, block.id));
+            if world_loc.y >= column.0 {
+                *column = (world_loc.y, block.id);
+            }
+        }
+    }
+
+    if columns.is_empty() {
+        return Err(format!("No chunks found in {:?}", world_root));
+    }
+
+    let min_x = columns.keys().map(|c| c.0).min().unwrap();
+    let max_x = columns.keys().map(|c| c.0).max().unwrap();
+    let min_z = columns.keys().map(|c| c.1).min().unwrap();
+    let max_z = columns.keys().map(|c| c.1).max().unwrap();
+    let min_y = columns.values().map(|c| c.0).min().unwrap();
+    let max_y = columns.values().map(|c| c.0).max().unwrap();
+
+    let mut colors: HashMap<BlockID, [u8; 4]> = HashMap::new();
+    let mut img = ImageBuffer::from_pixel((max_x - min_x + 1) as u32,
+                                          (max_z - min_z + 1) as u32,
+                                          Rgba([0, 0, 0, 0]));
+
+    for (&(x, z), &(height, id)) in &columns {
+        // Blocks without a texture in the atlas show up magenta
+        let color = *colors.entry(id).or_insert_with(|| {
+            registry.lookup_texture(id)
+                .and_then(|uv| atlas.average_color(uv.get_face(Face::Top)))
+                .unwrap_or([255, 0, 255, 255])
+        });
+
+        // Brighten higher ground, and light slopes as if from the north west
+        let depth = if max_y > min_y {
+            (height - min_y) as f32 / (max_y - min_y) as f32
+        } else {
+            1.0
+        };
+        let slope = match columns.get(&(x - 1, z - 1)) {
+            Some(&(neighbor, _)) => clamp((height - neighbor) as f32 * 0.08, -0.25, 0.25),
+            None => 0.0,
+        };
+        let shade = clamp(0.6 + 0.4 * depth + slope, 0.0, 1.3);
+
+        let mut pixel = [0u8; 4];
+        for i in 0..3 {
+            pixel[i] = clamp(color[i] as f32 * shade, 0.0, 255.0) as u8;
+        }
+        pixel[3] = 255;
+
+        img.put_pixel((x - min_x) as u32, (z - min_z) as u32, Rgba(pixel));
+    }
+
+    try!(img.save(output).map_err(|e| format!("Could not write {:?}: {}", output, e)));
+    info!("Wrote {}x{} map of {} columns to {:?}",
+          img.width(),
+          img.height(),
+          columns.len(),
+          output);
+    Ok(())
+}
+
+#[test]
+fn flat_world_map() {
+    use std::env;
+    use std::fs;
+    use image;
+    use world::terrain::{ChunkGenerator, FlatGenerator};
+
+    let root = env::temp_dir().join("subsurface-map-test");
+    let _ = fs::remove_dir_all(&root);
+    let registry = Registry::new();
+    let chunk_gen = FlatGenerator::from_layers(0, vec![(1, "stone".into()), (2, "grass".into())]);
+    for &origin in &[point3(0, 0, 0), point3(16, 0, 0)] {
+        chunk_gen.generate_chunk(origin, &registry).write(&root);
+    }
+
+    let output = root.join("map.png");
+    render(&root, &output).unwrap();
+    let map = image::open(&output).unwrap().to_rgba();
+    assert_eq!(map.dimensions(), (32, 16));
+
+    // Level ground is drawn at full brightness in the grass top colour
+    let atlas = Atlas::load();
+    let grass = registry.lookup_id(&"grass".into()).unwrap();
+    let uv = registry.lookup_texture(grass).unwrap().get_face(Face::Top);
+    let color = atlas.average_color(uv).unwrap();
+    assert!(map.pixels().all(|p| p.data == [color[0], color[1], color[2], 255]));
+    assert!(atlas.average_color(Point2::new(255, 255)).is_none());
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,142 @@
+use std::collections::hash_map::HashMap;
+
+use num_iter::range_step;
+
+use prelude::*;
+use graphics;
+use world::{World, WorldPoint};
+use world::chunk::CHUNK_SIZE;
+
+/// A vertex of an exported mesh in world coordinates.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshVertex {
+    pub position: [i32; 3],
+    /// Atlas coordinates, normalised to `u16::MAX` like the renderer's.
+    pub uv: [u16; 2],
+    pub normal: [i8; 3],
+    /// Sun light in the high nibble, block light in the low nibble.
+    pub light: u8,
+}
+
+/// An indexed triangle mesh with duplicate vertices merged.
+pub struct RegionMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+fn in_box(loc: WorldPoint, from: WorldPoint, to: WorldPoint) -> bool {
+    loc.x >= from.x && loc.y >= from.y && loc.z >= from.z && loc.x <= to.x && loc.y <= to.y &&
+    loc.z <= to.z
+}
+
+/// Meshes the blocks of `world` inside the inclusive box `from`..`to` with
+/// the renderer's face culling. Faces on the sides of the box are kept so
+/// the exported region is closed.
+pub fn mesh_region(world: &World, from: WorldPoint, to: WorldPoint) -> RegionMesh {
+    let low = point3(from.x.min(to.x), from.y.min(to.y), from.z.min(to.z));
+    let high = point3(from.x.max(to.x), from.y.max(to.y), from.z.max(to.z));
+
+    let mut mesh = RegionMesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+    };
+    let mut seen: HashMap<MeshVertex, u32> = HashMap::new();
+
+    let (chunk_low, chunk_high) = super::manage::chunk_aligned(low, high);
+    let mut chunk_origins = Vec::new();
+    for x in range_step(chunk_low.x, chunk_high.x, CHUNK_SIZE) {
+        for y in range_step(chunk_low.y, chunk_high.y, CHUNK_SIZE) {
+            for z in range_step(chunk_low.z, chunk_high.z, CHUNK_SIZE) {
+                chunk_origins.push(point3(x, y, z));
+            }
+        }
+    }
+
+    for chunk_origin in chunk_origins {
+        let chunk = match world.chunk(chunk_origin) {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        let to_world = |loc: Point3<u8>| {
+            chunk_origin + vec3(loc.x as i32, loc.y as i32, loc.z as i32)
+        };
+        let verts = graphics::build_vertices(chunk, &world.registry, |loc, face| {
+            let world_loc = to_world(loc);
+            in_box(world_loc, low, high) &&
+            (chunk.get_block_local(loc).is_visible(face) ||
+             !in_box(world_loc + face.normal(), low, high))
+        });
+
+        for triangle in verts.chunks(3) {
+            let positions: Vec<WorldPoint> = triangle.iter()
+                .map(|v| chunk_origin + vec3(v.position[0] as i32,
+                                             v.position[1] as i32,
+                                             v.position[2] as i32))
+                .collect();
+            let normal = triangle_normal(&positions);
+
+            for (vertex, position) in triangle.iter().zip(positions.iter()) {
+                let key = MeshVertex {
+                    position: [position.x, position.y, position.z],
+                    uv: [vertex.uv[0].0, vertex.uv[1].0],
+                    normal: normal,
+                    light: vertex.position[3],
+                };
+
+                let next = mesh.vertices.len() as u32;
+                let index = *seen.entry(key).or_insert(next);
+                if index == next {
+                    mesh.vertices.push(key);
+                }
+                mesh.indices.push(index);
+            }
+        }
+    }
+
+    mesh
+}
+
+fn triangle_normal(positions: &[WorldPoint]) -> [i8; 3] {
+    let a = positions[1] - positions[0];
+    let b = positions[2] - positions[0];
+    let cross = a.cross(b);
+    [cross.x.signum() as i8, cross.y.signum() as i8, cross.z.signum() as i8]
+}
+
+#[test]
+fn normals_point_out_of_faces() {
+    assert_eq!(triangle_normal(&[point3(0, 1, 1), point3(1, 1, 1), point3(0, 1, 0)]),
+               [0, 1, 0]);
+    assert_eq!(triangle_normal(&[point3(0, 0, 0), point3(0, 1, 0), point3(1, 0, 0)]),
+               [0, 0, -1]);
+}
+
+#[cfg(test)]
+pub fn single_block_mesh() -> RegionMesh {
+    use std::iter;
+    use std::path::Path;
+    use world::Access;
+    use world::block::BlockID;
+    use world::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    world.apply_changes(iter::once((point3(5, 5, 5), BlockID(2))));
+    mesh_region(&world, point3(5, 5, 5), point3(5, 5, 5))
+}
+
+#[test]
+fn shared_corners_are_merged() {
+    // Each face is two triangles over the same four corners
+    let mesh = single_block_mesh();
+    assert_eq!(mesh.indices.len(), 6 * 2 * 3);
+    assert_eq!(mesh.vertices.len(), 6 * 4);
+    assert!(mesh.vertices.iter().all(|v| {
+        (0..3).all(|i| v.position[i] == 5 || v.position[i] == 6)
+    }));
+}
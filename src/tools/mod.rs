@@ -0,0 +1,69 @@
+//! Headless commands that work on a world directory without opening a window.
+
+pub mod atlas;
+pub mod map;
+pub mod manage;
+pub mod mesh;
+pub mod obj;
+pub mod magica;
+pub mod gltf;
+pub mod prefab;
+pub mod replay;
+pub mod probe;
+pub mod script;
+pub mod snapshot;
+
+use std::path::Path;
+
+use prelude::*;
+use world::{World, WorldMeta, WorldPoint, Access};
+use world::registry::Registry;
+use world::terrain;
+
+/// Loads every chunk touching the inclusive box `from`..`to`, generating any
+/// that have not been saved with the generator from the world's metadata.
+/// A new world is only created if `default_generator` is given. Tools that
+/// only read should pass `Access::ReadOnly` so they can run alongside a game
+/// or server that has the world open.
+pub fn open_region(world_root: &Path,
+                   from: WorldPoint,
+                   to: WorldPoint,
+                   default_generator: Option<&str>,
+                   access: Access)
+                   -> Result<World, String> {
+    // Locked first, so that two processes cannot both create the metadata
+    let lock = try!(access.lock(world_root));
+    let registry = Registry::new();
+    let chunk_gen = match default_generator {
+        Some(generator) => {
+            try!(WorldMeta::open_generator(world_root, generator, &registry, access))
+        }
+        None => {
+            let meta = try!(try!(WorldMeta::read(world_root))
+                .ok_or(format!("No world found at {:?}", world_root)));
+            try!(terrain::from_spec(&meta.generator, meta.seed, &registry))
+        }
+    };
+    World::from_path_locked(world_root, manage::chunk_aligned(from, to), chunk_gen, lock)
+}
+
+/// Parses a block position written as `x,y,z`.
+pub fn parse_point(text: &str) -> Result<WorldPoint, String> {
+    let values: Vec<i32> = try!(text.split(',')
+        .map(|v| v.trim().parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_| format!("Invalid position \"{}\"", text)));
+
+    if values.len() != 3 {
+        return Err(format!("Expected x,y,z but got \"{}\"", text));
+    }
+    Ok(point3(values[0], values[1], values[2]))
+}
+
+#[test]
+fn points() {
+    assert_eq!(parse_point("1,-2,3").unwrap(), point3(1, -2, 3));
+    assert_eq!(parse_point(" 4, 5 ,6").unwrap(), point3(4, 5, 6));
+    assert!(parse_point("1,2").is_err());
+    assert!(parse_point("a,b,c").is_err());
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use world::{WorldPoint, Access};
+use super::atlas::ATLAS_PNG;
+use super::mesh::mesh_region;
+
+/// Writes the blocks in the inclusive box `from`..`to` as a Wavefront OBJ at
+/// `output`, with a material file and a copy of the block atlas beside it,
+/// both named after `output` so exports to one directory keep their own.
+pub fn export(world_root: &Path,
+              from: WorldPoint,
+              to: WorldPoint,
+              output: &Path)
+              -> Result<(), String> {
+    let world = try!(super::open_region(world_root, from, to, None, Access::ReadOnly));
+    let mesh = mesh_region(&world, from, to);
+    if mesh.indices.is_empty() {
+        return Err(format!("No blocks between {:?} and {:?}", from, to));
+    }
+
+    let dir = output.parent().unwrap_or(Path::new("."));
+    let stem = try!(output.file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or(format!("Invalid output path {:?}", output)));
+    let mtl_name = format!("{}.mtl", stem);
+    let atlas_name = format!("{}.png", stem);
+
+    let io_err = |e: ::std::io::Error| format!("Could not write {:?}: {}", output, e);
+
+    let mut mtl = try!(File::create(dir.join(&mtl_name)).map_err(&io_err));
+    try!(write!(mtl,
+                "newmtl blocks\nKa 1.0 1.0 1.0\nKd 1.0 1.0 1.0\nillum 1\nmap_Kd {}\n",
+                atlas_name)
+        .map_err(&io_err));
+    try!(File::create(dir.join(&atlas_name))
+        .and_then(|mut f| f.write_all(ATLAS_PNG))
+        .map_err(&io_err));
+
+    let mut obj = BufWriter::new(try!(File::create(output).map_err(&io_err)));
+    try!(write_obj(&mut obj, &mesh, &mtl_name).map_err(&io_err));
+
+    println!("Wrote {} vertices and {} triangles to {:?}",
+             mesh.vertices.len(),
+             mesh.indices.len() / 3,
+             output);
+    Ok(())
+}
+
+fn write_obj<W: Write>(out: &mut W,
+                       mesh: &super::mesh::RegionMesh,
+                       mtl_name: &str)
+                       -> ::std::io::Result<()> {
+    try!(writeln!(out, "mtllib {}", mtl_name));
+    try!(writeln!(out, "o region"));
+
+    for v in &mesh.vertices {
+        try!(writeln!(out, "v {} {} {}", v.position[0], v.position[1], v.position[2]));
+    }
+    for v in &mesh.vertices {
+        // OBJ puts the texture origin at the bottom left, the atlas at the top left
+        try!(writeln!(out,
+                      "vt {:.6} {:.6}",
+                      v.uv[0] as f32 / 65535.0,
+                      1.0 - v.uv[1] as f32 / 65535.0));
+    }
+    for v in &mesh.vertices {
+        try!(writeln!(out, "vn {} {} {}", v.normal[0], v.normal[1], v.normal[2]));
+    }
+
+    try!(writeln!(out, "usemtl blocks"));
+    for triangle in mesh.indices.chunks(3) {
+        // OBJ indices start at one
+        let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+        try!(writeln!(out, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn obj_lines() {
+    let mesh = super::mesh::single_block_mesh();
+    let mut out = Vec::new();
+    write_obj(&mut out, &mesh, "region.mtl").unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines[0], "mtllib region.mtl");
+    assert!(lines.contains(&"usemtl blocks"));
+    let count = |prefix: &str| lines.iter().filter(|l| l.starts_with(prefix)).count();
+    assert_eq!(count("v "), 24);
+    assert_eq!(count("vt "), 24);
+    assert_eq!(count("vn "), 24);
+    assert_eq!(count("f "), 12);
+    assert!(lines.contains(&"v 5 5 5"));
+
+    // Indices start at one and refer to the same vertex, uv and normal
+    let faces: Vec<&str> = lines.iter().filter(|l| l.starts_with("f ")).cloned().collect();
+    for face in faces {
+        for corner in face[2..].split(' ') {
+            let parts: Vec<u32> = corner.split('/').map(|p| p.parse().unwrap()).collect();
+            assert_eq!(parts.len(), 3);
+            assert!(parts[0] >= 1 && parts[0] <= 24);
+            assert!(parts[0] == parts[1] && parts[1] == parts[2]);
+        }
+    }
+}
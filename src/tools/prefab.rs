@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use prelude::*;
+use world::{WorldPoint, Access};
+use world::prefab::{Prefab, PasteOptions};
+
+/// Saves the inclusive box `from`..`to` of a saved world as a prefab.
+pub fn save(world_root: &Path,
+            from: WorldPoint,
+            to: WorldPoint,
+            output: &Path)
+            -> Result<(), String> {
+    let world = try!(super::open_region(world_root, from, to, None, Access::ReadOnly));
+    let prefab = try!(world.copy_prefab(from, to));
+    try!(prefab.write(output));
+
+    println!("Saved {}x{}x{} prefab with {} block types to {:?}",
+             prefab.size.0,
+             prefab.size.1,
+             prefab.size.2,
+             prefab.block_names().len(),
+             output);
+    Ok(())
+}
+
+/// Pastes a prefab into a saved world with its minimum corner at `origin`.
+pub fn paste(world_root: &Path,
+             prefab_path: &Path,
+             origin: WorldPoint,
+             options: PasteOptions)
+             -> Result<(), String> {
+    let prefab = try!(Prefab::read(prefab_path));
+    let to = origin + prefab.transformed_size(options) - vec3(1, 1, 1);
+
+    let mut world = try!(super::open_region(world_root, origin, to, None, Access::ReadWrite));
+    let changed = try!(world.paste_prefab(&prefab, origin, options));
+    world.write_all_chunks();
+
+    println!("Changed {} blocks between {:?} and {:?}", changed, origin, to);
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use prelude::*;
+use net::{Connection, ServerMessage, PlayerState};
+use world::WorldPoint;
+use world::chunk::{Chunk, CHUNK_SIZE};
+use world::registry::Registry;
+
+/// Connects to a server as a player standing at `at`, optionally sets one
+/// block, fetches the chunk around `at` and reports what the server sent
+/// back within `wait`.
+pub fn run(addr: &str,
+           name: &str,
+           at: WorldPoint,
+           set: Option<(WorldPoint, String)>,
+           wait: Duration)
+           -> Result<(), String> {
+    let registry = Registry::new();
+    let mut connection = try!(Connection::connect(addr, name, &registry));
+    println!("Connected to {} as player {}", addr, connection.player);
+
+    try!(connection.send_position(PlayerState {
+        position: (at.x as f32 + 0.5, at.y as f32, at.z as f32 + 0.5),
+        phi: 0.0,
+        theta: 0.0,
+    }));
+    if let Some((loc, block)) = set {
+        let id = try!(registry.lookup_id(&block).ok_or(format!("Unknown block \"{}\"", block)));
+        try!(connection.set_block(loc, id));
+    }
+    let origin = point3(at.x & -CHUNK_SIZE, at.y & -CHUNK_SIZE, at.z & -CHUNK_SIZE);
+    try!(connection.request_chunk(origin));
+
+    while let Some(message) = connection.wait(wait) {
+        match message {
+            ServerMessage::ChunkData { origin, blocks } => {
+                let origin = point3(origin.0, origin.1, origin.2);
+                let chunk = try!(Chunk::from_bytes(origin, &blocks));
+                let solid = chunk.iter().filter(|&l| !chunk.get_block_local(l).is_empty()).count();
+                println!("Chunk {:?}: {} bytes, {} solid blocks", origin, blocks.len(), solid);
+            }
+            ServerMessage::ChunkError { origin, error } => {
+                println!("Chunk {:?} could not be loaded: {}", origin, error);
+            }
+            ServerMessage::BlockChanged { loc, id } => {
+                println!("Block {:?} is now {}", loc, id.0);
+            }
+            ServerMessage::PlayerMoved { player, name, state } => {
+                println!("Player {} ({}) is at {:?}", player, name, state.position);
+            }
+            ServerMessage::PlayerLeft { player } => println!("Player {} left", player),
+            ServerMessage::Disconnect(reason) => {
+                return Err(format!("Disconnected: {}", reason));
+            }
+            ServerMessage::Welcome { .. } => println!("Unexpected second welcome"),
+        }
+    }
+
+    connection.disconnect();
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+
+use console::Console;
+use game::Game;
+use replay::Recording;
+use world::Access;
+use world::events::WorldEvent;
+
+/// Plays a recording against a saved world, as the client would but without
+/// a window, and reports what changed.
+pub fn run(world_root: &Path,
+           recording_path: &Path,
+           default_generator: &str,
+           save: bool)
+           -> Result<(), String> {
+    let recording = try!(Recording::read(recording_path));
+    let access = if save {
+        Access::ReadWrite
+    } else {
+        Access::ReadOnly
+    };
+    let mut game = try!(Game::open(world_root, default_generator, access));
+
+    let changes = Rc::new(Cell::new(0));
+    let counter = changes.clone();
+    game.world.subscribe(Box::new(move |event| {
+        if let WorldEvent::BlockChanged { .. } = *event {
+            counter.set(counter.get() + 1);
+        }
+    }));
+
+    let played = recording.play(&mut game, &Console::new().commands);
+    if save {
+        game.world.write_all_chunks();
+    }
+
+    println!("Played {} of {} frames, {} block changes, camera ended at {:?}",
+             played,
+             recording.frames.len(),
+             changes.get(),
+             game.camera.position);
+    Ok(())
+}
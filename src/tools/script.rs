@@ -0,0 +1,48 @@
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use script;
+use world::{WorldPoint, Access};
+use world::events::WorldEvent;
+
+/// Runs a Rhai script against the chunks of a saved world touching the box
+/// `from`..`to`, saving them afterwards unless `dry_run` is set.
+pub fn run(world_root: &Path,
+           script_path: &Path,
+           from: WorldPoint,
+           to: WorldPoint,
+           dry_run: bool)
+           -> Result<(), String> {
+    let mut source = String::new();
+    try!(File::open(script_path)
+        .and_then(|mut f| f.read_to_string(&mut source))
+        .map_err(|e| format!("Could not read script {:?}: {}", script_path, e)));
+
+    let access = if dry_run {
+        Access::ReadOnly
+    } else {
+        Access::ReadWrite
+    };
+    let mut world = try!(super::open_region(world_root, from, to, None, access));
+    let changes = Rc::new(Cell::new(0));
+    let counter = changes.clone();
+    world.subscribe(Box::new(move |event| {
+        if let WorldEvent::BlockChanged { .. } = *event {
+            counter.set(counter.get() + 1);
+        }
+    }));
+
+    let world = Rc::new(RefCell::new(world));
+    try!(script::run(&world, &source, &script_path.to_string_lossy()));
+    if !dry_run {
+        world.borrow_mut().write_all_chunks();
+    }
+
+    println!("Script made {} block changes{}",
+             changes.get(),
+             if dry_run { ", not saved" } else { "" });
+    Ok(())
+}
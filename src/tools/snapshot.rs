@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use world::lock::SessionLock;
+use world::snapshot;
+
+/// Snapshots the world's saved chunks and metadata, storing only what
+/// changed since the last snapshot unless `full` is set.
+pub fn create(world_root: &Path, full: bool) -> Result<(), String> {
+    let _lock = try!(SessionLock::acquire(world_root));
+    let info = try!(snapshot::create(world_root, full));
+    println!("Created snapshot {}", info);
+    Ok(())
+}
+
+pub fn list(world_root: &Path) -> Result<(), String> {
+    let infos = try!(snapshot::list(world_root));
+    if infos.is_empty() {
+        println!("No snapshots of {:?}", world_root);
+    }
+    for info in infos {
+        println!("{}", info);
+    }
+    Ok(())
+}
+
+/// Puts the world back as it was when snapshot `name` was taken.
+pub fn restore(world_root: &Path, name: &str) -> Result<(), String> {
+    let _lock = try!(SessionLock::acquire(world_root));
+    let written = try!(snapshot::restore(world_root, name));
+    println!("Restored {} chunks from snapshot {}", written, name);
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use std;
 use prelude::*;
 
-#[derive(Copy, Clone, PartialEq, Eq, RustcEncodable, RustcDecodable, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable, Hash)]
 pub struct BlockID(pub u32);
 
 bitflags!(
@@ -35,42 +35,145 @@ fn origin_to_path(origin: WorldPoint, world_root: &Path) -> PathBuf {
 }
 
 impl Chunk {
+    /// Origins of every chunk saved under `world_root`, found by walking the
+    /// `x/y/z/chunk.bincode` directory layout.
+    pub fn list(world_root: &Path) -> Vec<WorldPoint> {
+        use std::fs::read_dir;
+
+        fn numbered_dirs(path: &Path) -> Vec<(i32, PathBuf)> {
+            match read_dir(path) {
+                Ok(entries) => {
+                    entries.filter_map(|e| e.ok())
+                        .filter(|e| e.path().is_dir())
+                        .filter_map(|e| {
+                            e.file_name()
+                                .to_str()
+                                .and_then(|name| name.parse::<i32>().ok())
+                                .map(|n| (n, e.path()))
+                        })
+                        .collect()
+                }
+                Err(_) => Vec::new(),
+            }
+        }
+
+        let mut origins = Vec::new();
+        for (x, x_path) in numbered_dirs(world_root) {
+            for (y, y_path) in numbered_dirs(&x_path) {
+                for (z, z_path) in numbered_dirs(&y_path) {
+                    if z_path.join("chunk.bincode").is_file() {
+                        origins.push(point3(x, y, z));
+                    }
+                }
+            }
+        }
+
+        origins
+    }
+
     pub fn write(&self, world_root: &Path) {
+        Chunk::write_bytes(self.origin, world_root, &self.to_bytes()).unwrap();
+    }
+
+    /// Saves blocks already encoded with `to_bytes`, so the encoding and the
+    /// writing can happen on different threads.
+    pub fn write_bytes(origin: WorldPoint, world_root: &Path, bytes: &[u8]) -> Result<(), String> {
         use std::io::Write;
         use std::fs::File;
         use std::fs::DirBuilder;
 
-        let path = origin_to_path(self.origin, world_root);
-        DirBuilder::new().recursive(true).create(path.clone()).unwrap();
+        let path = origin_to_path(origin, world_root);
         let dest = path.join("chunk.bincode");
         debug!("Writing chunk to {:?}", dest);
-        let encoded: Vec<u8> = encode(&self.blocks, SizeLimit::Infinite).unwrap();
-        File::create(dest).unwrap().write_all(encoded.as_slice()).unwrap();
+        DirBuilder::new()
+            .recursive(true)
+            .create(path)
+            .and_then(|_| File::create(&dest))
+            .and_then(|mut file| file.write_all(bytes))
+            .map_err(|e| format!("Could not write {:?}: {}", dest, e))
+    }
+
+    /// The blocks encoded as they are saved on disk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&self.blocks, SizeLimit::Infinite).unwrap()
+    }
+
+    /// Decodes blocks written by `to_bytes`.
+    pub fn from_bytes(origin: WorldPoint, bytes: &[u8]) -> Result<Chunk, String> {
+        let blocks: Vec<Block> = try!(decode(bytes).map_err(|e| e.to_string()));
+        if blocks.len() != (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize {
+            return Err(format!("Expected {} blocks but found {}",
+                               CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE,
+                               blocks.len()));
+        }
+
+        Ok(Chunk {
+            blocks: blocks,
+            origin: origin,
+            dirty: false,
+        })
     }
 
     pub fn read(origin: WorldPoint, world_root: &Path) -> Option<Chunk> {
+        Chunk::try_read(origin, world_root).unwrap()
+    }
+
+    /// Like `read`, but reports unreadable or corrupt chunk files instead of
+    /// panicking. Returns `Ok(None)` if the chunk has never been saved.
+    pub fn try_read(origin: WorldPoint, world_root: &Path) -> Result<Option<Chunk>, String> {
         use std::io::Read;
         use std::fs::File;
 
         let mut bytes = Vec::new();
         let path = origin_to_path(origin, world_root).join("chunk.bincode");
         debug!("Reading chunk from {:?}", path);
-        match File::open(path) {
+        match File::open(&path) {
             Ok(mut file) => {
-                file.read_to_end(&mut bytes).unwrap();
-                Some(Chunk {
-                    blocks: decode(bytes.as_slice()).unwrap(),
-                    origin: origin,
-                    dirty: false,
-                })
+                try!(file.read_to_end(&mut bytes)
+                    .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+                Chunk::from_bytes(origin, &bytes)
+                    .map(Some)
+                    .map_err(|e| format!("Could not decode {:?}: {}", path, e))
             }
             Err(_) => {
                 debug!("Chunk was not found");
-                None
+                Ok(None)
             }
         }
     }
 
+    /// The saved bytes of a chunk, as `to_bytes` gives them, without decoding.
+    pub fn read_bytes(origin: WorldPoint, world_root: &Path) -> Result<Vec<u8>, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let path = origin_to_path(origin, world_root).join("chunk.bincode");
+        let mut bytes = Vec::new();
+        try!(File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+        Ok(bytes)
+    }
+
+    /// Deletes a saved chunk, and its parent directories if that empties them.
+    pub fn remove(origin: WorldPoint, world_root: &Path) -> Result<(), String> {
+        use std::fs::{remove_dir, remove_dir_all};
+
+        let path = origin_to_path(origin, world_root);
+        try!(remove_dir_all(&path).map_err(|e| format!("Could not remove {:?}: {}", path, e)));
+        if let Some(parent) = path.parent() {
+            if remove_dir(parent).is_ok() {
+                parent.parent().map(|p| remove_dir(p));
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory holding the chunk at `origin`.
+    pub fn path(origin: WorldPoint, world_root: &Path) -> PathBuf {
+        origin_to_path(origin, world_root)
+    }
+
     pub fn new(origin: WorldPoint) -> Chunk {
         let mut ret = Chunk {
             blocks: Vec::with_capacity((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize),
@@ -87,7 +190,7 @@ impl Chunk {
         self.blocks[index] = block;
     }
 
-    fn is_loc_local(&self, loc: WorldPoint) -> bool {
+    pub fn is_loc_local(&self, loc: WorldPoint) -> bool {
         let offset = loc - self.origin;
         offset.x < CHUNK_SIZE && offset.y < CHUNK_SIZE && offset.z < CHUNK_SIZE &&
         offset.x >= 0 && offset.y >= 0 && offset.z >= 0
@@ -0,0 +1,127 @@
+//! Shapes for bulk edits. Each shape expands to the block positions it
+//! covers; `World::fill_shape` and friends apply them in one batch.
+
+use prelude::*;
+use super::{WorldPoint, RegionIter};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shape {
+    /// Every block in the inclusive box between two corners.
+    Box(WorldPoint, WorldPoint),
+    /// The six walls of the inclusive box between two corners.
+    Walls(WorldPoint, WorldPoint),
+    Sphere { centre: WorldPoint, radius: i32 },
+    /// A vertical cylinder standing on `base`.
+    Cylinder {
+        base: WorldPoint,
+        radius: i32,
+        height: i32,
+    },
+    Line(WorldPoint, WorldPoint),
+}
+
+fn ordered(a: WorldPoint, b: WorldPoint) -> (WorldPoint, WorldPoint) {
+    (point3(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+     point3(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)))
+}
+
+impl Shape {
+    pub fn positions(&self) -> Vec<WorldPoint> {
+        match *self {
+            Shape::Box(a, b) => {
+                let (low, high) = ordered(a, b);
+                RegionIter::new(low, high).collect()
+            }
+            Shape::Walls(a, b) => {
+                let (low, high) = ordered(a, b);
+                RegionIter::new(low, high)
+                    .filter(|p| {
+                        p.x == low.x || p.x == high.x || p.y == low.y || p.y == high.y ||
+                        p.z == low.z || p.z == high.z
+                    })
+                    .collect()
+            }
+            Shape::Sphere { centre, radius } => {
+                let extent = vec3(radius, radius, radius);
+                RegionIter::new(centre - extent, centre + extent)
+                    .filter(|p| {
+                        let d = *p - centre;
+                        d.x * d.x + d.y * d.y + d.z * d.z <= radius * radius
+                    })
+                    .collect()
+            }
+            Shape::Cylinder { base, radius, height } => {
+                if height <= 0 {
+                    return Vec::new();
+                }
+                RegionIter::new(base - vec3(radius, 0, radius),
+                                base + vec3(radius, height - 1, radius))
+                    .filter(|p| {
+                        let d = *p - base;
+                        d.x * d.x + d.z * d.z <= radius * radius
+                    })
+                    .collect()
+            }
+            Shape::Line(a, b) => line(a, b),
+        }
+    }
+
+    /// The inclusive bounding box of the shape.
+    pub fn bounds(&self) -> (WorldPoint, WorldPoint) {
+        match *self {
+            Shape::Box(a, b) | Shape::Walls(a, b) | Shape::Line(a, b) => ordered(a, b),
+            Shape::Sphere { centre, radius } => {
+                let extent = vec3(radius, radius, radius);
+                (centre - extent, centre + extent)
+            }
+            Shape::Cylinder { base, radius, height } => {
+                (base - vec3(radius, 0, radius), base + vec3(radius, height - 1, radius))
+            }
+        }
+    }
+}
+
+/// Blocks along the straight line from `a` to `b`, stepping one block at a
+/// time along the longest axis.
+fn line(a: WorldPoint, b: WorldPoint) -> Vec<WorldPoint> {
+    let d = b - a;
+    let steps = d.x.abs().max(d.y.abs()).max(d.z.abs());
+    if steps == 0 {
+        return vec![a];
+    }
+
+    (0..steps + 1)
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            point3(a.x + (d.x as f32 * t).round() as i32,
+                   a.y + (d.y as f32 * t).round() as i32,
+                   a.z + (d.z as f32 * t).round() as i32)
+        })
+        .collect()
+}
+
+#[test]
+fn shapes() {
+    assert_eq!(Shape::Box(point3(0, 0, 0), point3(2, 2, 2)).positions().len(), 27);
+    assert_eq!(Shape::Walls(point3(2, 2, 2), point3(0, 0, 0)).positions().len(), 26);
+    assert_eq!(Shape::Sphere {
+                       centre: point3(0, 0, 0),
+                       radius: 1,
+                   }
+                   .positions()
+                   .len(),
+               7);
+    assert_eq!(Shape::Cylinder {
+                       base: point3(0, 0, 0),
+                       radius: 1,
+                       height: 3,
+                   }
+                   .positions()
+                   .len(),
+               15);
+
+    let line = Shape::Line(point3(0, 0, 0), point3(4, -2, 0)).positions();
+    assert_eq!(line.len(), 5);
+    assert_eq!(line[0], point3(0, 0, 0));
+    assert_eq!(line[4], point3(4, -2, 0));
+}
@@ -0,0 +1,90 @@
+//! Notifications about changes to a `World`, so systems like lighting,
+//! networking or logging can follow along without `World` knowing about them.
+
+use super::WorldPoint;
+use super::block::BlockID;
+
+/// Why a block changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cause {
+    Place,
+    Break,
+    /// A bulk edit, import or paste.
+    Edit,
+    Undo,
+    Redo,
+}
+
+#[derive(Clone, Copy)]
+pub enum WorldEvent {
+    BlockChanged {
+        loc: WorldPoint,
+        old: BlockID,
+        new: BlockID,
+        cause: Cause,
+    },
+    ChunkLoaded(WorldPoint),
+    /// Sent just before the chunk is removed.
+    ChunkUnloaded(WorldPoint),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ListenerId(u32);
+
+pub struct Events {
+    listeners: Vec<(ListenerId, Box<FnMut(&WorldEvent)>)>,
+    next_id: u32,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events {
+            listeners: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn subscribe(&mut self, listener: Box<FnMut(&WorldEvent)>) -> ListenerId {
+        let id = ListenerId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+
+    /// Returns false if `id` was not subscribed.
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|l| l.0 != id);
+        self.listeners.len() != before
+    }
+
+    pub fn emit(&mut self, event: WorldEvent) {
+        for listener in &mut self.listeners {
+            (listener.1)(&event);
+        }
+    }
+}
+
+#[test]
+fn subscriptions() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let loads = Rc::new(Cell::new(0));
+    let mut events = Events::new();
+    let counter = loads.clone();
+    let id = events.subscribe(Box::new(move |event| {
+        if let WorldEvent::ChunkLoaded(_) = *event {
+            counter.set(counter.get() + 1);
+        }
+    }));
+
+    events.emit(WorldEvent::ChunkLoaded(::prelude::point3(0, 0, 0)));
+    events.emit(WorldEvent::ChunkUnloaded(::prelude::point3(0, 0, 0)));
+    assert_eq!(loads.get(), 1);
+
+    assert!(events.unsubscribe(id));
+    assert!(!events.unsubscribe(id));
+    events.emit(WorldEvent::ChunkLoaded(::prelude::point3(0, 0, 0)));
+    assert_eq!(loads.get(), 1);
+}
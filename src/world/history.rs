@@ -0,0 +1,189 @@
+//! Undo and redo for block edits. Changes are grouped into transactions so a
+//! single click or a whole bulk operation is undone in one step.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use super::WorldPoint;
+use super::block::BlockID;
+
+/// Roughly 4 MiB of recorded changes.
+pub const DEFAULT_BUDGET: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Change {
+    pub loc: WorldPoint,
+    pub old: BlockID,
+    pub new: BlockID,
+}
+
+pub type Transaction = Vec<Change>;
+
+pub struct History {
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+    open: Option<(usize, Transaction)>,
+    /// Bytes of changes allowed across the undo and redo stacks.
+    budget: usize,
+    used: usize,
+}
+
+fn cost(transaction: &Transaction) -> usize {
+    transaction.len() * mem::size_of::<Change>()
+}
+
+impl History {
+    pub fn new(budget: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: None,
+            budget: budget,
+            used: 0,
+        }
+    }
+
+    /// Starts grouping changes until the matching `end`. Transactions nest;
+    /// only the outermost one is committed.
+    pub fn begin(&mut self) {
+        if let Some((ref mut depth, _)) = self.open {
+            *depth += 1;
+            return;
+        }
+        self.open = Some((1, Vec::new()));
+    }
+
+    pub fn end(&mut self) {
+        let finished = match self.open {
+            Some((ref mut depth, _)) => {
+                *depth -= 1;
+                *depth == 0
+            }
+            None => false,
+        };
+
+        if finished {
+            let (_, transaction) = self.open.take().unwrap();
+            self.commit(transaction);
+        }
+    }
+
+    /// Records changes, committing them as their own transaction unless one
+    /// is open.
+    pub fn record<I: IntoIterator<Item = Change>>(&mut self, changes: I) {
+        let has_open = self.open.is_some();
+        if has_open {
+            self.open.as_mut().unwrap().1.extend(changes);
+        } else {
+            self.commit(changes.into_iter().collect());
+        }
+    }
+
+    fn commit(&mut self, transaction: Transaction) {
+        if transaction.is_empty() {
+            return;
+        }
+
+        for dropped in self.redo.drain(..) {
+            self.used -= cost(&dropped);
+        }
+
+        let size = cost(&transaction);
+        if size > self.budget {
+            warn!("Edit of {} blocks is too large to undo", transaction.len());
+            self.clear();
+            return;
+        }
+
+        self.used += size;
+        self.undo.push_back(transaction);
+        while self.used > self.budget {
+            let dropped = self.undo.pop_front().unwrap();
+            self.used -= cost(&dropped);
+        }
+    }
+
+    /// Takes the most recent transaction to undo. Pass it back to
+    /// `push_redo` once it has been reverted.
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        let transaction = self.undo.pop_back();
+        if let Some(ref t) = transaction {
+            self.used -= cost(t);
+        }
+        transaction
+    }
+
+    pub fn push_redo(&mut self, transaction: Transaction) {
+        self.used += cost(&transaction);
+        self.redo.push(transaction);
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        let transaction = self.redo.pop();
+        if let Some(ref t) = transaction {
+            self.used -= cost(t);
+        }
+        transaction
+    }
+
+    /// Puts a redone transaction back on the undo stack without discarding
+    /// the rest of the redo stack.
+    pub fn push_undo(&mut self, transaction: Transaction) {
+        self.used += cost(&transaction);
+        self.undo.push_back(transaction);
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.used = 0;
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+}
+
+#[test]
+fn transactions_and_budget() {
+    use prelude::*;
+
+    let change = |x| {
+        Change {
+            loc: point3(x, 0, 0),
+            old: BlockID(0),
+            new: BlockID(1),
+        }
+    };
+
+    let mut history = History::new(3 * mem::size_of::<Change>());
+    history.begin();
+    history.record(vec![change(0)]);
+    history.begin();
+    history.record(vec![change(1)]);
+    history.end();
+    assert_eq!(history.undo_len(), 0);
+    history.end();
+    assert_eq!(history.undo_len(), 1);
+
+    // Going over budget drops the oldest transaction
+    history.record(vec![change(2), change(3)]);
+    assert_eq!(history.undo_len(), 1);
+
+    let undone = history.pop_undo().unwrap();
+    assert_eq!(undone.len(), 2);
+    history.push_redo(undone);
+    assert_eq!(history.redo_len(), 1);
+
+    // A new edit discards anything that could be redone
+    history.record(vec![change(4)]);
+    assert_eq!(history.redo_len(), 0);
+    assert_eq!(history.undo_len(), 1);
+
+    history.record((0..4).map(change));
+    assert_eq!(history.undo_len(), 0);
+}
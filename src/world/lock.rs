@@ -0,0 +1,167 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono;
+use rustc_serialize::json;
+
+pub const LOCK_FILE: &'static str = "session.lock";
+
+/// Who holds a world, as written to its lock file.
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+struct LockInfo {
+    pid: u32,
+    started: String,
+}
+
+/// Marks a world directory as in use by this process until dropped, so that
+/// two writers never overwrite each other's chunks.
+pub struct SessionLock {
+    path: PathBuf,
+}
+
+impl SessionLock {
+    /// Takes the lock for `world_root`, creating the directory if needed. A
+    /// lock left behind by a process that is no longer running is replaced.
+    pub fn acquire(world_root: &Path) -> Result<SessionLock, String> {
+        try!(fs::create_dir_all(world_root)
+            .map_err(|e| format!("Could not create {:?}: {}", world_root, e)));
+
+        // The lock is written under a name of our own and then linked into
+        // place, so no other process ever sees it half written
+        let ours = world_root.join(format!("{}.{}", LOCK_FILE, process::id()));
+        let info = LockInfo {
+            pid: process::id(),
+            started: chrono::Local::now().to_rfc3339(),
+        };
+        try!(File::create(&ours)
+            .and_then(|mut file| file.write_all(json::encode(&info).unwrap().as_bytes()))
+            .map_err(|e| format!("Could not write {:?}: {}", ours, e)));
+
+        let result = claim(world_root, &ours);
+        let _ = fs::remove_file(&ours);
+        result
+    }
+}
+
+fn claim(world_root: &Path, ours: &Path) -> Result<SessionLock, String> {
+    let path = world_root.join(LOCK_FILE);
+
+    // A second try is only needed after removing a stale lock
+    for _ in 0..2 {
+        match fs::hard_link(ours, &path) {
+            Ok(()) => return Ok(SessionLock { path: path }),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(format!("Could not create {:?}: {}", path, e)),
+        }
+
+        let held = read_info(&path);
+        match held {
+            Some(ref info) if is_running(info.pid) != Some(false) => {
+                return Err(in_use(world_root, info, &path));
+            }
+            Some(ref info) => {
+                warn!("Removing stale lock left by process {} at {}", info.pid, info.started)
+            }
+            None => warn!("Removing unreadable lock {:?}", path),
+        }
+
+        // Another process may replace the stale lock between our reading it
+        // and removing it, so move it aside first and check it is the one we
+        // read before throwing it away
+        let aside = world_root.join(format!("{}.stale.{}", LOCK_FILE, process::id()));
+        match fs::rename(&path, &aside) {
+            Ok(()) => {
+                let moved = read_info(&aside);
+                if moved != held {
+                    let _ = fs::hard_link(&aside, &path);
+                    let _ = fs::remove_file(&aside);
+                    return Err(match moved {
+                        Some(ref info) => in_use(world_root, info, &path),
+                        None => format!("Could not lock {:?}", world_root),
+                    });
+                }
+                try!(fs::remove_file(&aside)
+                    .map_err(|e| format!("Could not remove stale lock {:?}: {}", aside, e)));
+            }
+            // Someone else removed it first
+            Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("Could not remove stale lock {:?}: {}", path, e)),
+        }
+    }
+
+    Err(format!("Could not lock {:?}", world_root))
+}
+
+fn in_use(world_root: &Path, info: &LockInfo, path: &Path) -> String {
+    format!("World {:?} is in use by process {} since {}. If that process is not running, \
+             delete {:?}",
+            world_root,
+            info.pid,
+            info.started,
+            path)
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Could not remove lock {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn read_info(path: &Path) -> Option<LockInfo> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => json::decode(&text).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Whether a process is running, if the platform lets us tell. Only Linux
+/// and others with `/proc` can; elsewhere a lock is never considered stale.
+fn is_running(pid: u32) -> Option<bool> {
+    if pid == process::id() {
+        return Some(true);
+    }
+
+    let proc_root = Path::new("/proc");
+    if proc_root.join("self").exists() {
+        Some(proc_root.join(pid.to_string()).exists())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn locking() {
+    use std::env;
+
+    let root = env::temp_dir().join("subsurface-lock-test");
+    let _ = fs::remove_dir_all(&root);
+
+    {
+        let _lock = SessionLock::acquire(&root).unwrap();
+        assert!(SessionLock::acquire(&root).is_err());
+    }
+    assert!(!root.join(LOCK_FILE).exists());
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+    // No process has this ID on Linux, whose IDs stay below 2^22
+    if is_running(u32::max_value()) == Some(false) {
+        let stale = LockInfo {
+            pid: u32::max_value(),
+            started: "yesterday".into(),
+        };
+        File::create(root.join(LOCK_FILE))
+            .unwrap()
+            .write_all(json::encode(&stale).unwrap().as_bytes())
+            .unwrap();
+        let lock = SessionLock::acquire(&root).unwrap();
+        assert_eq!(read_info(&root.join(LOCK_FILE)).unwrap().pid, process::id());
+        drop(lock);
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
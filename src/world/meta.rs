@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use rustc_serialize::json;
+use rand;
+
+use super::Access;
+use super::registry::Registry;
+use super::terrain::{self, ChunkGenerator};
+
+const META_FILE: &'static str = "world.json";
+
+/// Settings stored alongside a world's chunks so that it is regenerated the
+/// same way every time it is opened.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct WorldMeta {
+    pub generator: String,
+    pub seed: u32,
+}
+
+impl WorldMeta {
+    pub fn new(generator: String, seed: u32) -> WorldMeta {
+        WorldMeta {
+            generator: generator,
+            seed: seed,
+        }
+    }
+
+    /// Reads the metadata, or `None` if the world has none yet. Metadata
+    /// that exists but cannot be read or decoded is an error.
+    pub fn read(world_root: &Path) -> Result<Option<WorldMeta>, String> {
+        use std::io::{ErrorKind, Read};
+        use std::fs::File;
+
+        let path = world_root.join(META_FILE);
+        let mut text = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                try!(file.read_to_string(&mut text)
+                    .map_err(|e| format!("Could not read world metadata {:?}: {}", path, e)));
+                json::decode(&text)
+                    .map(Some)
+                    .map_err(|e| format!("Could not decode world metadata {:?}: {}", path, e))
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                debug!("World metadata was not found at {:?}", path);
+                Ok(None)
+            }
+            Err(e) => Err(format!("Could not open world metadata {:?}: {}", path, e)),
+        }
+    }
+
+    /// Builds the generator named in the metadata. A new world gets
+    /// `default_generator` and a random seed, but its metadata is only
+    /// written once that generator has been built, so a mistyped generator
+    /// never sticks to the world. Nothing is written with `Access::ReadOnly`.
+    pub fn open_generator(world_root: &Path,
+                          default_generator: &str,
+                          registry: &Registry,
+                          access: Access)
+                          -> Result<Box<ChunkGenerator>, String> {
+        match try!(WorldMeta::read(world_root)) {
+            Some(meta) => {
+                info!("Using generator {:?} from world metadata", meta.generator);
+                terrain::from_spec(&meta.generator, meta.seed, registry)
+            }
+            None => {
+                let meta = WorldMeta::new(default_generator.into(), rand::random::<u32>());
+                let chunk_gen = try!(terrain::from_spec(&meta.generator, meta.seed, registry));
+                if access == Access::ReadWrite {
+                    try!(meta.write(world_root));
+                }
+                Ok(chunk_gen)
+            }
+        }
+    }
+
+    pub fn write(&self, world_root: &Path) -> Result<(), String> {
+        use std::io::Write;
+        use std::fs::File;
+        use std::fs::DirBuilder;
+
+        let path = world_root.join(META_FILE);
+        debug!("Writing world metadata to {:?}", path);
+        let text = try!(json::encode(self).map_err(|e| e.to_string()));
+        DirBuilder::new()
+            .recursive(true)
+            .create(world_root)
+            .and_then(|_| File::create(&path))
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| format!("Could not write world metadata {:?}: {}", path, e))
+    }
+}
+
+#[test]
+fn bad_generator_is_not_saved() {
+    use std::env;
+    use std::fs;
+
+    let root = env::temp_dir().join("subsurface-meta-test");
+    let _ = fs::remove_dir_all(&root);
+    let registry = Registry::new();
+
+    let open = |generator, access| WorldMeta::open_generator(&root, generator, &registry, access);
+    assert!(open("no-such-preset.json", Access::ReadWrite).is_err());
+    assert!(WorldMeta::read(&root).unwrap().is_none());
+    assert!(open("flat", Access::ReadOnly).is_ok());
+    assert!(WorldMeta::read(&root).unwrap().is_none());
+
+    assert!(open("flat", Access::ReadWrite).is_ok());
+    assert_eq!(WorldMeta::read(&root).unwrap().unwrap().generator, "flat");
+
+    // Broken metadata is reported instead of treated as a new world
+    {
+        use std::io::Write;
+        fs::File::create(root.join(META_FILE)).unwrap().write_all(b"{ \"seed\"").unwrap();
+    }
+    assert!(WorldMeta::read(&root).is_err());
+    assert!(open("flat", Access::ReadWrite).is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -7,11 +7,20 @@ pub mod block;
 pub mod world;
 pub mod terrain;
 pub mod registry;
+pub mod meta;
+pub mod vox;
+pub mod prefab;
+pub mod edit;
+pub mod history;
+pub mod events;
+pub mod lock;
+pub mod snapshot;
 
 pub type WorldPoint = Point3<i32>;
 pub type LocalPoint = Point3<u8>;
 
-pub use self::world::World;
+pub use self::world::{World, RayHit, Access, find_chunk_origin};
+pub use self::meta::WorldMeta;
 
 pub type RegionIter = PointIter<i32>;
 pub type LocalIter = PointIter<u8>;
@@ -0,0 +1,235 @@
+//! Boxes of blocks saved by name so they can be pasted elsewhere, rotated or
+//! mirrored, and reused as generator structures.
+
+use std::path::Path;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+
+use prelude::*;
+
+const PREFAB_VERSION: u32 = 1;
+
+/// Blocks are stored x fastest, then y, then z, run-length encoded as
+/// `(count, palette index)`. Palette entry 0 is always air.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Prefab {
+    version: u32,
+    pub size: (u32, u32, u32),
+    palette: Vec<String>,
+    runs: Vec<(u32, u16)>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PasteOptions {
+    /// Clockwise quarter turns about the y axis, seen from above.
+    pub rotation: u8,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+    /// Leave existing blocks where the prefab has air.
+    pub skip_air: bool,
+}
+
+pub const AIR: &'static str = "air";
+
+impl Prefab {
+    /// Builds a prefab from block names in storage order, `None` for air.
+    pub fn from_names<'a, I>(size: (u32, u32, u32), names: I) -> Prefab
+        where I: Iterator<Item = Option<&'a String>>
+    {
+        let mut prefab = Prefab {
+            version: PREFAB_VERSION,
+            size: size,
+            palette: vec![AIR.to_string()],
+            runs: Vec::new(),
+        };
+
+        for name in names {
+            let index = match name {
+                Some(name) => {
+                    match prefab.palette.iter().position(|p| p == name) {
+                        Some(index) => index,
+                        None => {
+                            prefab.palette.push(name.clone());
+                            prefab.palette.len() - 1
+                        }
+                    }
+                }
+                None => 0,
+            } as u16;
+
+            let extends_run = match prefab.runs.last() {
+                Some(&(_, last)) => last == index,
+                None => false,
+            };
+            if extends_run {
+                prefab.runs.last_mut().unwrap().0 += 1;
+            } else {
+                prefab.runs.push((1, index));
+            }
+        }
+
+        prefab
+    }
+
+    pub fn read(path: &Path) -> Result<Prefab, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let mut bytes = Vec::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+        let prefab: Prefab = try!(decode(&bytes)
+            .map_err(|e| format!("Could not decode prefab {:?}: {}", path, e)));
+        if prefab.version != PREFAB_VERSION {
+            return Err(format!("Unsupported prefab version {}", prefab.version));
+        }
+        try!(prefab.check().map_err(|e| format!("Invalid prefab {:?}: {}", path, e)));
+        Ok(prefab)
+    }
+
+    /// Checks that the palette starts with air and that the runs fill the box
+    /// exactly and only use palette entries that exist, which `blocks` relies
+    /// on.
+    fn check(&self) -> Result<(), String> {
+        let (sx, sy, sz) = self.size;
+        if sx == 0 || sy == 0 || sz == 0 {
+            return Err(format!("size {:?} has an empty side", self.size));
+        }
+        if self.palette.first().map(|first| first.as_str()) != Some(AIR) {
+            return Err(format!("palette must start with \"{}\"", AIR));
+        }
+
+        let volume = sx as u64 * sy as u64 * sz as u64;
+        let mut total = 0;
+        for &(count, index) in &self.runs {
+            if index as usize >= self.palette.len() {
+                return Err(format!("palette index {} is out of range", index));
+            }
+            total += count as u64;
+        }
+        if total != volume {
+            return Err(format!("runs cover {} blocks but the box holds {}", total, volume));
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        use std::io::Write;
+        use std::fs::File;
+
+        let encoded = encode(self, SizeLimit::Infinite).unwrap();
+        try!(File::create(path)
+            .and_then(|mut f| f.write_all(&encoded))
+            .map_err(|e| format!("Could not write {:?}: {}", path, e)));
+        Ok(())
+    }
+
+    /// Size after `options` are applied; quarter turns swap x and z.
+    pub fn transformed_size(&self, options: PasteOptions) -> Vector3<i32> {
+        let (x, y, z) = (self.size.0 as i32, self.size.1 as i32, self.size.2 as i32);
+        if options.rotation % 2 == 1 {
+            vec3(z, y, x)
+        } else {
+            vec3(x, y, z)
+        }
+    }
+
+    /// Every block as an offset from the pasted box's minimum corner and a
+    /// block name, with `options` applied. Air is included unless
+    /// `options.skip_air` is set.
+    pub fn blocks(&self, options: PasteOptions) -> Vec<(Vector3<i32>, &String)> {
+        let (sx, sy, sz) = (self.size.0 as i32, self.size.1 as i32, self.size.2 as i32);
+        let mut blocks = Vec::new();
+        let mut index = 0;
+
+        for &(count, palette_index) in &self.runs {
+            for _ in 0..count {
+                let (x, y, z) = (index % sx, (index / sx) % sy, index / (sx * sy));
+                index += 1;
+
+                if palette_index == 0 && options.skip_air {
+                    continue;
+                }
+
+                let x = if options.mirror_x { sx - 1 - x } else { x };
+                let z = if options.mirror_z { sz - 1 - z } else { z };
+                let (mut x, mut z, mut width, mut depth) = (x, z, sx, sz);
+                for _ in 0..options.rotation % 4 {
+                    let turned = (depth - 1 - z, x);
+                    x = turned.0;
+                    z = turned.1;
+                    let swapped = (depth, width);
+                    width = swapped.0;
+                    depth = swapped.1;
+                }
+
+                blocks.push((vec3(x, y, z), &self.palette[palette_index as usize]));
+            }
+        }
+
+        blocks
+    }
+
+    /// Names of every block type used, other than air.
+    pub fn block_names(&self) -> &[String] {
+        &self.palette[1..]
+    }
+}
+
+#[test]
+fn transforms() {
+    let stone = "stone".to_string();
+    let dirt = "dirt".to_string();
+    // 2 x 1 x 3, stone along x = 0 and one dirt block at (1, 0, 2)
+    let names = vec![Some(&stone), None, Some(&stone), None, Some(&stone), Some(&dirt)];
+    let prefab = Prefab::from_names((2, 1, 3), names.into_iter());
+    assert_eq!(prefab.runs, vec![(1, 1), (1, 0), (1, 1), (1, 0), (1, 1), (1, 2)]);
+
+    let find_dirt = |options: PasteOptions| {
+        prefab.blocks(options).into_iter().find(|b| b.1 == &dirt).unwrap().0
+    };
+
+    let mut options = PasteOptions::default();
+    assert_eq!(find_dirt(options), vec3(1, 0, 2));
+    assert_eq!(prefab.blocks(options).len(), 6);
+
+    options.rotation = 1;
+    assert_eq!(prefab.transformed_size(options), vec3(3, 1, 2));
+    assert_eq!(find_dirt(options), vec3(0, 0, 1));
+
+    options.rotation = 2;
+    assert_eq!(find_dirt(options), vec3(0, 0, 0));
+
+    options.rotation = 0;
+    options.mirror_x = true;
+    assert_eq!(find_dirt(options), vec3(0, 0, 2));
+
+    options.mirror_x = false;
+    options.skip_air = true;
+    assert_eq!(prefab.blocks(options).len(), 4);
+}
+
+#[test]
+fn rejects_malformed() {
+    let stone = "stone".to_string();
+    let prefab = Prefab::from_names((2, 1, 1), vec![Some(&stone), None].into_iter());
+    assert!(prefab.check().is_ok());
+
+    let mut short = prefab.clone();
+    short.runs.pop();
+    assert!(short.check().is_err());
+
+    let mut flat = prefab.clone();
+    flat.size = (2, 0, 1);
+    assert!(flat.check().is_err());
+
+    let mut unknown = prefab.clone();
+    unknown.runs[0].1 = 9;
+    assert!(unknown.check().is_err());
+
+    let mut no_air = prefab.clone();
+    no_air.palette[0] = "dirt".into();
+    assert!(no_air.check().is_err());
+}
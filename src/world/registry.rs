@@ -5,6 +5,7 @@ use world::block::{BlockID, Face};
 
 pub struct Registry {
     ids_by_name: HashMap<String, BlockID>,
+    names_by_id: HashMap<BlockID, String>,
     uvs_by_id: HashMap<BlockID, BlockUV>,
 }
 
@@ -27,6 +28,7 @@ impl Registry {
     pub fn new() -> Registry {
         let mut ret = Registry {
             ids_by_name: HashMap::new(),
+            names_by_id: HashMap::new(),
             uvs_by_id: HashMap::new(),
         };
 
@@ -42,6 +44,17 @@ impl Registry {
                                      point2(3, 0),
                                      point2(3, 0)],
                            });
+        ret.register_block("log".into(),
+                           BlockID(4),
+                           BlockUV {
+                               uvs: [point2(1, 1),
+                                     point2(1, 1),
+                                     point2(0, 1),
+                                     point2(0, 1),
+                                     point2(0, 1),
+                                     point2(0, 1)],
+                           });
+        ret.register_block("leaves".into(), BlockID(5), BlockUV::one_face(point2(2, 1)));
 
         ret
     }
@@ -53,6 +66,17 @@ impl Registry {
         }
     }
 
+    pub fn lookup_name(&self, id: BlockID) -> Option<&String> {
+        self.names_by_id.get(&id)
+    }
+
+    /// Every registered block, in ID order.
+    pub fn block_ids(&self) -> Vec<BlockID> {
+        let mut ids: Vec<BlockID> = self.names_by_id.keys().cloned().collect();
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
     pub fn lookup_texture(&self, id: BlockID) -> Option<BlockUV> {
         match self.uvs_by_id.get(&id) {
             Some(uv) => Some(*uv),
@@ -61,6 +85,7 @@ impl Registry {
     }
 
     pub fn register_block(&mut self, name: String, id: BlockID, uv: BlockUV) {
+        self.names_by_id.insert(id, name.clone());
         self.ids_by_name.insert(name, id);
         self.uvs_by_id.insert(id, uv);
     }
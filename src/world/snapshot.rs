@@ -0,0 +1,311 @@
+//! Archives of a whole world directory, kept in its `snapshots` directory.
+//! A snapshot lists every saved chunk with a hash of its contents, but only
+//! stores the chunks that changed since the snapshot before it; the rest are
+//! found by following the chain of `base` snapshots back to a full one.
+//!
+//! Nothing here takes the world's lock. Callers either hold it already or
+//! take it themselves.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+use chrono;
+
+use prelude::*;
+use super::WorldPoint;
+use super::chunk::Chunk;
+use super::meta::WorldMeta;
+
+pub const SNAPSHOT_DIR: &'static str = "snapshots";
+const EXTENSION: &'static str = "snapshot";
+/// Bumped whenever `Archive` changes shape.
+const ARCHIVE_VERSION: u32 = 1;
+
+type Position = (i32, i32, i32);
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Archive {
+    version: u32,
+    created: String,
+    /// The snapshot that chunks missing from `chunks` are stored in, if any.
+    base: Option<String>,
+    meta: Option<WorldMeta>,
+    /// Every chunk saved when the snapshot was taken, with its hash.
+    manifest: Vec<(Position, u64)>,
+    /// Chunks encoded as by `Chunk::to_bytes`.
+    chunks: Vec<(Position, Vec<u8>)>,
+}
+
+/// A summary of one snapshot, for listing.
+#[derive(Clone, Debug)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created: String,
+    pub base: Option<String>,
+    pub chunks_stored: usize,
+    pub chunks_total: usize,
+    /// Size of the archive file.
+    pub bytes: u64,
+}
+
+impl SnapshotInfo {
+    fn new(name: &str, archive: &Archive, bytes: u64) -> SnapshotInfo {
+        SnapshotInfo {
+            name: name.into(),
+            created: archive.created.clone(),
+            base: archive.base.clone(),
+            chunks_stored: archive.chunks.len(),
+            chunks_total: archive.manifest.len(),
+            bytes: bytes,
+        }
+    }
+}
+
+impl fmt::Display for SnapshotInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "{}: {} of {} chunks, {} KiB",
+                    self.name,
+                    self.chunks_stored,
+                    self.chunks_total,
+                    self.bytes / 1024));
+        match self.base {
+            Some(ref base) => write!(f, ", changes since {}", base),
+            None => write!(f, ", full"),
+        }
+    }
+}
+
+fn snapshot_path(world_root: &Path, name: &str) -> PathBuf {
+    world_root.join(SNAPSHOT_DIR).join(format!("{}.{}", name, EXTENSION))
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is guaranteed
+/// to give the same answer in every build.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn read_archive(world_root: &Path, name: &str) -> Result<(Archive, u64), String> {
+    let path = snapshot_path(world_root, name);
+    let mut bytes = Vec::new();
+    try!(File::open(&path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("Could not read snapshot {:?}: {}", path, e)));
+    let archive: Archive = try!(decode(&bytes)
+        .map_err(|e| format!("Could not decode snapshot {:?}: {}", path, e)));
+    if archive.version != ARCHIVE_VERSION {
+        return Err(format!("Snapshot {:?} has version {} but only {} is supported",
+                           path,
+                           archive.version,
+                           ARCHIVE_VERSION));
+    }
+    Ok((archive, bytes.len() as u64))
+}
+
+/// Names of the snapshots of a world, oldest first.
+fn names(world_root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(world_root.join(SNAPSHOT_DIR)) {
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(EXTENSION))
+                .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()))
+                .collect()
+        }
+        Err(_) => Vec::new(),
+    };
+    names.sort_by_key(|name| taken_order(name));
+    names
+}
+
+/// Sorts names in the order they were taken. Names are timestamps, with a
+/// numeric suffix from 2 up when several are taken in the same second.
+fn taken_order(name: &str) -> (String, u32) {
+    let parts: Vec<&str> = name.splitn(3, '-').collect();
+    match parts.get(2).and_then(|suffix| suffix.parse::<u32>().ok()) {
+        Some(suffix) => (format!("{}-{}", parts[0], parts[1]), suffix),
+        None => (name.to_string(), 0),
+    }
+}
+
+/// Every snapshot of the world at `world_root`, oldest first.
+pub fn list(world_root: &Path) -> Result<Vec<SnapshotInfo>, String> {
+    let mut infos = Vec::new();
+    for name in names(world_root) {
+        let (archive, bytes) = try!(read_archive(world_root, &name));
+        infos.push(SnapshotInfo::new(&name, &archive, bytes));
+    }
+    Ok(infos)
+}
+
+/// Archives the saved chunks and metadata of the world at `world_root`.
+/// Unless `full` is set, only chunks that differ from the latest snapshot
+/// are stored. Chunks still in memory must be saved first.
+pub fn create(world_root: &Path, full: bool) -> Result<SnapshotInfo, String> {
+    let latest = names(world_root).pop();
+    let (base, known) = match latest {
+        Some(ref name) if !full => {
+            let (archive, _) = try!(read_archive(world_root, name));
+            let known: HashMap<Position, u64> = archive.manifest.into_iter().collect();
+            (Some(name.clone()), known)
+        }
+        _ => (None, HashMap::new()),
+    };
+
+    let mut origins = Chunk::list(world_root);
+    origins.sort_by_key(|o| (o.x, o.y, o.z));
+    let mut manifest = Vec::new();
+    let mut chunks = Vec::new();
+    for origin in origins {
+        let position = (origin.x, origin.y, origin.z);
+        let bytes = try!(Chunk::read_bytes(origin, world_root));
+        let chunk_hash = hash(&bytes);
+        manifest.push((position, chunk_hash));
+        if known.get(&position) != Some(&chunk_hash) {
+            chunks.push((position, bytes));
+        }
+    }
+
+    let now = chrono::Local::now();
+    let archive = Archive {
+        version: ARCHIVE_VERSION,
+        created: now.to_rfc3339(),
+        base: base,
+        meta: try!(WorldMeta::read(world_root)),
+        manifest: manifest,
+        chunks: chunks,
+    };
+
+    let stamp = now.format("%Y%m%d-%H%M%S").to_string();
+    let mut name = stamp.clone();
+    let mut suffix = 2;
+    while snapshot_path(world_root, &name).exists() {
+        name = format!("{}-{}", stamp, suffix);
+        suffix += 1;
+    }
+
+    let path = snapshot_path(world_root, &name);
+    let encoded = encode(&archive, SizeLimit::Infinite).unwrap();
+    try!(fs::create_dir_all(world_root.join(SNAPSHOT_DIR))
+        .and_then(|_| File::create(&path))
+        .and_then(|mut file| file.write_all(&encoded))
+        .map_err(|e| format!("Could not write snapshot {:?}: {}", path, e)));
+    info!("Wrote snapshot {} with {} of {} chunks",
+          name,
+          archive.chunks.len(),
+          archive.manifest.len());
+    Ok(SnapshotInfo::new(&name, &archive, encoded.len() as u64))
+}
+
+/// Puts the world at `world_root` back as it was when snapshot `name` was
+/// taken: chunks saved since are deleted and the metadata is replaced. An
+/// incremental snapshot of the current state is taken first, so a restore
+/// can itself be undone. Returns how many chunks were written.
+pub fn restore(world_root: &Path, name: &str) -> Result<usize, String> {
+    let (target, _) = try!(read_archive(world_root, name));
+    let meta = target.meta.clone();
+
+    // Gather every chunk in the manifest, walking back through the bases
+    // for those this snapshot did not store
+    let wanted: HashMap<Position, u64> = target.manifest.iter().cloned().collect();
+    let mut found: HashMap<Position, Vec<u8>> = HashMap::new();
+    let mut archive = target;
+    let mut archive_name = name.to_string();
+    loop {
+        for (position, bytes) in archive.chunks {
+            if !found.contains_key(&position) && wanted.get(&position) == Some(&hash(&bytes)) {
+                found.insert(position, bytes);
+            }
+        }
+        if found.len() == wanted.len() {
+            break;
+        }
+        let base = try!(archive.base.ok_or(format!("Snapshot {} is missing {} chunks",
+                                                   name,
+                                                   wanted.len() - found.len())));
+        archive = try!(read_archive(world_root, &base)
+            .map(|(archive, _)| archive)
+            .map_err(|e| format!("Snapshot {} needs {}: {}", archive_name, base, e)));
+        archive_name = base;
+    }
+
+    let backup = try!(create(world_root, false));
+    info!("Saved the current state as snapshot {} before restoring", backup.name);
+
+    for origin in Chunk::list(world_root) {
+        if !wanted.contains_key(&(origin.x, origin.y, origin.z)) {
+            try!(Chunk::remove(origin, world_root));
+        }
+    }
+    for (&position, bytes) in &found {
+        let origin: WorldPoint = point3(position.0, position.1, position.2);
+        try!(Chunk::write_bytes(origin, world_root, bytes));
+    }
+    if let Some(meta) = meta {
+        try!(meta.write(world_root));
+    }
+
+    info!("Restored snapshot {} ({} chunks)", name, found.len());
+    Ok(found.len())
+}
+
+#[test]
+fn incremental_snapshots() {
+    use std::env;
+    use super::block::{Block, BlockID};
+
+    let root = env::temp_dir().join("subsurface-snapshot-test");
+    let _ = fs::remove_dir_all(&root);
+
+    fn save(root: &Path, origin: WorldPoint, id: BlockID) {
+        let mut chunk = Chunk::new(origin);
+        chunk.set_block_immediate(origin + vec3(1, 1, 1), Block::from_id_only(id));
+        chunk.write(root);
+    }
+
+    save(&root, point3(0, 0, 0), BlockID(2));
+    save(&root, point3(16, 0, 0), BlockID(2));
+    WorldMeta::new("flat".into(), 7).write(&root).unwrap();
+    let full = create(&root, false).unwrap();
+    assert_eq!((full.chunks_stored, full.chunks_total), (2, 2));
+    assert_eq!(full.base, None);
+
+    save(&root, point3(16, 0, 0), BlockID(3));
+    let partial = create(&root, false).unwrap();
+    assert_eq!((partial.chunks_stored, partial.chunks_total), (1, 2));
+    assert_eq!(partial.base, Some(full.name.clone()));
+
+    // A chunk saved after the snapshot goes away on restore
+    save(&root, point3(0, 16, 0), BlockID(4));
+    save(&root, point3(0, 0, 0), BlockID(4));
+    assert_eq!(restore(&root, &partial.name).unwrap(), 2);
+    let block_at = |origin: WorldPoint| {
+        Chunk::read(origin, &root).unwrap().get_block(origin + vec3(1, 1, 1)).id
+    };
+    assert_eq!(block_at(point3(0, 0, 0)), BlockID(2));
+    assert_eq!(block_at(point3(16, 0, 0)), BlockID(3));
+    assert!(Chunk::read(point3(0, 16, 0), &root).is_none());
+
+    // The restore kept a snapshot of what it replaced
+    let infos = list(&root).unwrap();
+    assert_eq!(infos.len(), 3);
+    assert_eq!(infos[2].chunks_total, 3);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn names_in_order_taken() {
+    let mut names = vec!["20240101-120000-10", "20240101-120000-2", "20240101-120001",
+                         "20240101-120000"];
+    names.sort_by_key(|name| taken_order(name));
+    assert_eq!(names,
+               vec!["20240101-120000", "20240101-120000-2", "20240101-120000-10",
+                    "20240101-120001"]);
+}
@@ -0,0 +1,289 @@
+use prelude::*;
+use world::block::{Block, BlockID};
+use world::chunk::{Chunk, CHUNK_SIZE};
+use world::WorldPoint;
+use world::registry::Registry;
+use world::prefab::{Prefab, PasteOptions};
+use super::pipeline::{Stage, GenContext};
+
+/// Something placed on top of the terrain after the base pass, such as a
+/// tree. Features may extend into neighbouring chunks; the decorator asks for
+/// every feature that touches a chunk and keeps the blocks that land inside it.
+pub trait Feature {
+    /// Furthest horizontal distance from the anchor a block can be placed.
+    fn reach(&self) -> i32;
+
+    /// Blocks making up the feature anchored on top of the surface at
+    /// `anchor`. `roll` is a per-anchor random value so features can vary
+    /// without storing any state.
+    fn blocks(&self,
+              anchor: WorldPoint,
+              roll: u32,
+              registry: &Registry)
+              -> Vec<(WorldPoint, BlockID)>;
+}
+
+pub struct Tree {
+    trunk: String,
+    leaves: String,
+    min_height: i32,
+    max_height: i32,
+}
+
+impl Tree {
+    pub fn new(trunk: String, leaves: String, min_height: i32, max_height: i32) -> Tree {
+        assert!(min_height <= max_height);
+        Tree {
+            trunk: trunk,
+            leaves: leaves,
+            min_height: min_height,
+            max_height: max_height,
+        }
+    }
+}
+
+impl Feature for Tree {
+    fn reach(&self) -> i32 {
+        2
+    }
+
+    fn blocks(&self,
+              anchor: WorldPoint,
+              roll: u32,
+              registry: &Registry)
+              -> Vec<(WorldPoint, BlockID)> {
+        let trunk_id = registry.lookup_id(&self.trunk).expect("Could not find block ID");
+        let leaves_id = registry.lookup_id(&self.leaves).expect("Could not find block ID");
+        let height = self.min_height +
+                     (roll % (self.max_height - self.min_height + 1) as u32) as i32;
+
+        // Trunk first, so the canopy never replaces it
+        let mut blocks: Vec<(WorldPoint, BlockID)> =
+            (0..height).map(|y| (anchor + vec3(0, y, 0), trunk_id)).collect();
+
+        let top = anchor.y + height;
+        for y in (top - 3)..(top + 1) {
+            let radius = if y >= top - 1 { 1 } else { 2 };
+            for x in -radius..(radius + 1) {
+                for z in -radius..(radius + 1) {
+                    // Clip the corners so the canopy is rounder
+                    if radius > 1 && x.abs() == radius && z.abs() == radius {
+                        continue;
+                    }
+                    blocks.push((point3(anchor.x + x, y, anchor.z + z), leaves_id));
+                }
+            }
+        }
+
+        blocks
+    }
+}
+
+pub struct Boulder {
+    block: String,
+    radius: i32,
+}
+
+impl Boulder {
+    pub fn new(block: String, radius: i32) -> Boulder {
+        assert!(radius >= 1);
+        Boulder {
+            block: block,
+            radius: radius,
+        }
+    }
+}
+
+impl Feature for Boulder {
+    fn reach(&self) -> i32 {
+        self.radius
+    }
+
+    fn blocks(&self,
+              anchor: WorldPoint,
+              roll: u32,
+              registry: &Registry)
+              -> Vec<(WorldPoint, BlockID)> {
+        let id = registry.lookup_id(&self.block).expect("Could not find block ID");
+        let radius = 1 + (roll % self.radius as u32) as i32;
+
+        let mut blocks = Vec::new();
+        for x in -radius..(radius + 1) {
+            for y in -radius..(radius + 1) {
+                for z in -radius..(radius + 1) {
+                    if x * x + y * y + z * z <= radius * radius {
+                        blocks.push((anchor + vec3(x, y, z), id));
+                    }
+                }
+            }
+        }
+
+        blocks
+    }
+}
+
+/// A saved structure stood on the surface, centred on its anchor and turned
+/// a random number of quarter turns.
+pub struct PrefabFeature {
+    /// Blocks offset from the anchor, for each number of quarter turns.
+    turns: Vec<Vec<(Vector3<i32>, BlockID)>>,
+    reach: i32,
+}
+
+impl PrefabFeature {
+    /// Looks up the prefab's blocks once, so an unknown block is reported
+    /// when the preset loads rather than while generating.
+    pub fn new(prefab: &Prefab, registry: &Registry) -> Result<PrefabFeature, String> {
+        let mut turns = Vec::new();
+        for rotation in 0..4 {
+            let options = PasteOptions {
+                rotation: rotation,
+                skip_air: true,
+                ..PasteOptions::default()
+            };
+            let size = prefab.transformed_size(options);
+            let centre = vec3(size.x / 2, 0, size.z / 2);
+
+            let mut blocks = Vec::new();
+            for (offset, name) in prefab.blocks(options) {
+                let id = try!(registry.lookup_id(name)
+                    .ok_or(format!("Unknown block \"{}\" in prefab", name)));
+                blocks.push((offset - centre, id));
+            }
+            turns.push(blocks);
+        }
+
+        Ok(PrefabFeature {
+            turns: turns,
+            reach: prefab.size.0.max(prefab.size.2) as i32,
+        })
+    }
+}
+
+impl Feature for PrefabFeature {
+    fn reach(&self) -> i32 {
+        self.reach
+    }
+
+    fn blocks(&self, anchor: WorldPoint, roll: u32, _: &Registry) -> Vec<(WorldPoint, BlockID)> {
+        self.turns[(roll % 4) as usize]
+            .iter()
+            .map(|&(offset, id)| (anchor + offset, id))
+            .collect()
+    }
+}
+
+/// Scatters features over the surface. Placement is a pure function of the
+/// seed and column, so a chunk can find every feature that reaches into it
+/// without its neighbours being generated first.
+pub struct Decorator {
+    features: Vec<(Box<Feature>, u32)>,
+}
+
+impl Decorator {
+    pub fn new() -> Decorator {
+        Decorator { features: Vec::new() }
+    }
+
+    /// Adds a feature placed on roughly `per_thousand` out of every thousand
+    /// columns.
+    pub fn with_feature(mut self, feature: Box<Feature>, per_thousand: u32) -> Decorator {
+        self.features.push((feature, per_thousand));
+        self
+    }
+}
+
+impl Stage for Decorator {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let origin = chunk.origin;
+
+        for (index, &(ref feature, per_thousand)) in self.features.iter().enumerate() {
+            let reach = feature.reach();
+            for x in (origin.x - reach)..(origin.x + CHUNK_SIZE + reach) {
+                for z in (origin.z - reach)..(origin.z + CHUNK_SIZE + reach) {
+                    let roll = column_hash(context.seed, x, z, index as u32);
+                    if roll % 1000 >= per_thousand {
+                        continue;
+                    }
+
+                    let anchor = point3(x, context.heightmap.height_at(x, z) + 1, z);
+                    for (loc, id) in feature.blocks(anchor, roll / 1000, context.registry) {
+                        if chunk.is_loc_local(loc) && chunk.get_block(loc).is_empty() {
+                            chunk.set_block_immediate(loc, Block::from_id_only(id));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Cheap integer hash of a column, used in place of a random number
+/// generator so that placement is repeatable.
+pub fn column_hash(seed: u32, x: i32, z: i32, salt: u32) -> u32 {
+    let mut h = seed ^ salt.wrapping_mul(0x9e3779b9);
+    h ^= (x as u32).wrapping_mul(0x85ebca6b);
+    h = h.rotate_left(13);
+    h ^= (z as u32).wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+#[test]
+fn column_hash_is_stable() {
+    assert_eq!(column_hash(1, 10, -4, 0), column_hash(1, 10, -4, 0));
+    assert!(column_hash(1, 10, -4, 0) != column_hash(1, -4, 10, 0));
+    assert!(column_hash(1, 10, -4, 0) != column_hash(1, 10, -4, 1));
+}
+
+#[test]
+fn canopies_cross_chunk_borders() {
+    use std::collections::HashMap;
+    use super::ChunkGenerator;
+    use super::pipeline::Pipeline;
+    use super::stages::ConstantHeight;
+
+    let registry = Registry::new();
+    let new_tree = || Box::new(Tree::new("log".into(), "leaves".into(), 4, 6));
+    let pipeline = Pipeline::new(3, Box::new(ConstantHeight::new(3)))
+        .with_stage(Box::new(Decorator::new().with_feature(new_tree(), 200)));
+    let chunks = [pipeline.generate_chunk(point3(0, 0, 0), &registry),
+                  pipeline.generate_chunk(point3(CHUNK_SIZE, 0, 0), &registry)];
+
+    // The same trees placed once over both chunks, first block placed wins
+    let mut combined: HashMap<(i32, i32, i32), BlockID> = HashMap::new();
+    let tree = new_tree();
+    let reach = tree.reach();
+    for x in -reach..(2 * CHUNK_SIZE + reach) {
+        for z in -reach..(CHUNK_SIZE + reach) {
+            let roll = column_hash(3, x, z, 0);
+            if roll % 1000 >= 200 {
+                continue;
+            }
+            for (loc, id) in tree.blocks(point3(x, 4, z), roll / 1000, &registry) {
+                combined.entry((loc.x, loc.y, loc.z)).or_insert(id);
+            }
+        }
+    }
+
+    let leaves = registry.lookup_id(&"leaves".into()).unwrap();
+    let mut leaves_near_border = [0, 0];
+    for (i, chunk) in chunks.iter().enumerate() {
+        for x in chunk.origin.x..(chunk.origin.x + CHUNK_SIZE) {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let expected = combined.get(&(x, y, z)).cloned().unwrap_or(BlockID(0));
+                    let id = chunk.get_block(point3(x, y, z)).id;
+                    assert_eq!(id, expected, "block at {:?}", (x, y, z));
+                    if id == leaves && (x - CHUNK_SIZE).abs() <= reach {
+                        leaves_near_border[i] += 1;
+                    }
+                }
+            }
+        }
+    }
+    assert!(leaves_near_border[0] > 0 && leaves_near_border[1] > 0);
+}
@@ -1,37 +1,119 @@
+use prelude::*;
 use world::block::Block;
-use world::chunk::{Chunk, CHUNK_EXTENTS_LESS_ONE};
+use world::chunk::{Chunk, CHUNK_SIZE};
 use world::WorldPoint;
 use world::registry::Registry;
-use world::RegionIter;
 use super::ChunkGenerator;
+use super::pipeline::{Pipeline, Stage, GenContext};
+use super::stages::ConstantHeight;
 
+/// Horizontal bands of blocks stacked upwards from `base`, bottom layer first.
+struct Layers {
+    base: i32,
+    layers: Vec<(i32, String)>,
+}
+
+impl Stage for Layers {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let origin = chunk.origin;
+        let mut bottom = self.base;
+
+        for &(thickness, ref block) in &self.layers {
+            let id = context.registry.lookup_id(block).expect("Could not find block ID");
+            let top = bottom + thickness;
+
+            for y in clamp(bottom, origin.y, origin.y + CHUNK_SIZE)..
+                     clamp(top, origin.y, origin.y + CHUNK_SIZE) {
+                for x in origin.x..(origin.x + CHUNK_SIZE) {
+                    for z in origin.z..(origin.z + CHUNK_SIZE) {
+                        chunk.set_block_immediate(point3(x, y, z), Block::from_id_only(id));
+                    }
+                }
+            }
+
+            bottom = top;
+        }
+    }
+}
+
+/// Superflat terrain made of stacked layers, expressed as a pipeline preset.
 pub struct FlatGenerator {
-    high: i32,
-    low: i32,
-    block: String,
+    pipeline: Pipeline,
 }
 
 impl FlatGenerator {
+    /// A single block type between `low` and `high`.
     pub fn new(high: i32, low: i32, block: String) -> FlatGenerator {
-        FlatGenerator {
-            high: high,
-            low: low,
-            block: block,
+        FlatGenerator::from_layers(low, vec![(high - low + 1, block)])
+    }
+
+    pub fn from_layers(base: i32, layers: Vec<(i32, String)>) -> FlatGenerator {
+        let thickness: i32 = layers.iter().map(|l| l.0).sum();
+        let pipeline = Pipeline::new(0, Box::new(ConstantHeight::new(base + thickness - 1)))
+            .with_stage(Box::new(Layers {
+                base: base,
+                layers: layers,
+            }));
+
+        FlatGenerator { pipeline: pipeline }
+    }
+
+    /// Parses a layer spec such as `"1*stone,3*dirt,1*grass"`, listed from
+    /// the bottom up and starting at y = 0. A layer without a count is one
+    /// block thick. Block names are checked against the registry.
+    pub fn from_spec(spec: &str, registry: &Registry) -> Result<FlatGenerator, String> {
+        let layers = try!(parse_layers(spec));
+        for &(_, ref block) in &layers {
+            if registry.lookup_id(block).is_none() {
+                return Err(format!("Unknown block \"{}\" in layer spec", block));
+            }
         }
+
+        Ok(FlatGenerator::from_layers(0, layers))
     }
 }
 
-impl ChunkGenerator for FlatGenerator {
-    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
-        let mut chunk = Chunk::new(origin);
-        let id = registry.lookup_id(&self.block).expect("Could not find block ID");
-
-        for loc in RegionIter::new(origin, origin + CHUNK_EXTENTS_LESS_ONE) {
-            if loc.y <= self.high && loc.y >= self.low {
-                chunk.set_block_immediate(loc, Block::from_id_only(id));
+fn parse_layers(spec: &str) -> Result<Vec<(i32, String)>, String> {
+    let mut layers = Vec::new();
+    for layer in spec.split(',').map(|l| l.trim()) {
+        let mut parts = layer.splitn(2, '*');
+        let first = parts.next().unwrap();
+        let (count, block) = match parts.next() {
+            Some(block) => {
+                let count = try!(first.trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid layer count in \"{}\"", layer)));
+                (count, block.trim())
             }
+            None => (1, first),
+        };
+
+        if count <= 0 || block.is_empty() {
+            return Err(format!("Invalid layer \"{}\"", layer));
         }
+        layers.push((count, block.to_string()));
+    }
 
-        chunk
+    Ok(layers)
+}
+
+impl ChunkGenerator for FlatGenerator {
+    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
+        self.pipeline.generate_chunk(origin, registry)
     }
+
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.pipeline.surface_height(x, z)
+    }
+}
+
+#[test]
+fn layer_specs() {
+    assert_eq!(parse_layers("1*stone,3*dirt,1*grass").unwrap(),
+               vec![(1, "stone".to_string()), (3, "dirt".to_string()), (1, "grass".to_string())]);
+    assert_eq!(parse_layers("stone, 2*dirt").unwrap(),
+               vec![(1, "stone".to_string()), (2, "dirt".to_string())]);
+    assert!(parse_layers("0*stone").is_err());
+    assert!(parse_layers("x*stone").is_err());
+    assert!(parse_layers("3*").is_err());
 }
@@ -0,0 +1,286 @@
+use std::path::Path;
+
+use image;
+use image::{GrayImage, RgbImage};
+
+use prelude::*;
+use world::block::Block;
+use world::chunk::{Chunk, CHUNK_SIZE};
+use world::WorldPoint;
+use world::registry::Registry;
+use super::ChunkGenerator;
+use super::pipeline::{Pipeline, Heightmap, Stage, GenContext};
+use super::stages::{Fill, Surface};
+
+/// Maps world columns onto image pixels. One pixel covers `horizontal_scale`
+/// blocks and pixel (0, 0) sits at `offset.x, offset.z`.
+#[derive(Clone, Copy)]
+pub struct ImagePlacement {
+    pub horizontal_scale: f32,
+    pub vertical_scale: f32,
+    pub offset: Vector3<i32>,
+}
+
+impl ImagePlacement {
+    fn check(&self) -> Result<(), String> {
+        if !(self.horizontal_scale > 0.0) {
+            return Err(format!("Horizontal scale must be above 0, but got {}",
+                               self.horizontal_scale));
+        }
+        Ok(())
+    }
+
+    fn pixel_coords(&self, x: i32, z: i32) -> (f32, f32) {
+        ((x - self.offset.x) as f32 / self.horizontal_scale,
+         (z - self.offset.z) as f32 / self.horizontal_scale)
+    }
+}
+
+/// Terrain height read from a grayscale image. Black is `offset.y`, each
+/// brightness step adds `vertical_scale` blocks. Columns outside the image
+/// sit at `offset.y`.
+pub struct ImageHeightmap {
+    heights: GrayImage,
+    placement: ImagePlacement,
+}
+
+impl ImageHeightmap {
+    pub fn from_path(path: &Path, placement: ImagePlacement) -> Result<ImageHeightmap, String> {
+        try!(placement.check());
+        let heights = try!(image::open(path)
+                .map_err(|e| format!("Could not load heightmap {:?}: {}", path, e)))
+            .to_luma();
+
+        Ok(ImageHeightmap {
+            heights: heights,
+            placement: placement,
+        })
+    }
+
+    fn sample(&self, px: i64, pz: i64) -> f32 {
+        let (width, height) = self.heights.dimensions();
+        if px < 0 || pz < 0 || px >= width as i64 || pz >= height as i64 {
+            0.0
+        } else {
+            self.heights.get_pixel(px as u32, pz as u32).data[0] as f32
+        }
+    }
+}
+
+impl Heightmap for ImageHeightmap {
+    fn height_at(&self, x: i32, z: i32) -> i32 {
+        let (px, pz) = self.placement.pixel_coords(x, z);
+        let (x0, z0) = (px.floor(), pz.floor());
+        let (fx, fz) = (px - x0, pz - z0);
+        let (x0, z0) = (x0 as i64, z0 as i64);
+
+        // Bilinear filtering keeps upscaled heightmaps from turning into steps
+        let top = self.sample(x0, z0) * (1.0 - fx) + self.sample(x0 + 1, z0) * fx;
+        let bottom = self.sample(x0, z0 + 1) * (1.0 - fx) + self.sample(x0 + 1, z0 + 1) * fx;
+        let value = top * (1.0 - fz) + bottom * fz;
+
+        self.placement.offset.y + (value * self.placement.vertical_scale).round() as i32
+    }
+}
+
+/// Paints the surface block of each column from a colour image, using the
+/// closest colour in `colors`.
+pub struct ColorSurface {
+    colors: RgbImage,
+    placement: ImagePlacement,
+    mapping: Vec<([u8; 3], String)>,
+}
+
+impl ColorSurface {
+    /// Block names in `mapping` are checked against `registry`.
+    pub fn from_path(path: &Path,
+                     placement: ImagePlacement,
+                     mapping: Vec<([u8; 3], String)>,
+                     registry: &Registry)
+                     -> Result<ColorSurface, String> {
+        try!(placement.check());
+        if mapping.is_empty() {
+            return Err("Colour map needs at least one colour".into());
+        }
+        for &(_, ref block) in &mapping {
+            if registry.lookup_id(block).is_none() {
+                return Err(format!("Unknown block \"{}\" in colour map", block));
+            }
+        }
+
+        let colors = try!(image::open(path)
+                .map_err(|e| format!("Could not load colour map {:?}: {}", path, e)))
+            .to_rgb();
+
+        Ok(ColorSurface {
+            colors: colors,
+            placement: placement,
+            mapping: mapping,
+        })
+    }
+
+    fn block_for(&self, x: i32, z: i32) -> Option<&String> {
+        let (px, pz) = self.placement.pixel_coords(x, z);
+        let (width, height) = self.colors.dimensions();
+        if px < 0.0 || pz < 0.0 || px >= width as f32 || pz >= height as f32 {
+            return None;
+        }
+
+        let pixel = self.colors.get_pixel(px as u32, pz as u32).data;
+        self.mapping
+            .iter()
+            .min_by_key(|&&(color, _)| {
+                (0..3)
+                    .map(|i| (color[i] as i32 - pixel[i] as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .map(|&(_, ref block)| block)
+    }
+}
+
+impl Stage for ColorSurface {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let origin = chunk.origin;
+
+        for x in origin.x..(origin.x + CHUNK_SIZE) {
+            for z in origin.z..(origin.z + CHUNK_SIZE) {
+                let height = context.heightmap.height_at(x, z);
+                let loc = point3(x, height, z);
+                if !chunk.is_loc_local(loc) || chunk.get_block(loc).is_empty() {
+                    continue;
+                }
+
+                if let Some(block) = self.block_for(x, z) {
+                    let id = context.registry.lookup_id(block).expect("Could not find block ID");
+                    chunk.set_block_immediate(loc, Block::from_id_only(id));
+                }
+            }
+        }
+    }
+}
+
+/// Parses a hex colour such as `"7f7f7f"` or `"#7f7f7f"`.
+pub fn parse_color(hex: &str) -> Result<[u8; 3], String> {
+    let hex = hex.trim_left_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("Invalid colour \"{}\"", hex));
+    }
+
+    let mut color = [0; 3];
+    for i in 0..3 {
+        color[i] = try!(u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid colour \"{}\"", hex)));
+    }
+    Ok(color)
+}
+
+/// Terrain from a heightmap image, with grass and dirt on stone, expressed
+/// as a pipeline preset. A colour image can override the surface block.
+pub struct HeightmapGenerator {
+    pipeline: Pipeline,
+}
+
+impl HeightmapGenerator {
+    pub fn new(path: &Path,
+               placement: ImagePlacement,
+               registry: &Registry)
+               -> Result<HeightmapGenerator, String> {
+        HeightmapGenerator::with_colors(path, placement, None, registry)
+    }
+
+    pub fn with_colors(path: &Path,
+                       placement: ImagePlacement,
+                       colors: Option<(&Path, Vec<([u8; 3], String)>)>,
+                       registry: &Registry)
+                       -> Result<HeightmapGenerator, String> {
+        let heightmap = try!(ImageHeightmap::from_path(path, placement));
+        let mut pipeline = Pipeline::new(0, Box::new(heightmap))
+            .with_stage(Box::new(Fill::new("stone".into(), None)))
+            .with_stage(Box::new(Surface::new("grass".into(), "dirt".into(), 3)));
+
+        if let Some((color_path, mapping)) = colors {
+            let surface = try!(ColorSurface::from_path(color_path, placement, mapping, registry));
+            pipeline = pipeline.with_stage(Box::new(surface));
+        }
+
+        Ok(HeightmapGenerator { pipeline: pipeline })
+    }
+}
+
+impl ChunkGenerator for HeightmapGenerator {
+    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
+        self.pipeline.generate_chunk(origin, registry)
+    }
+
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.pipeline.surface_height(x, z)
+    }
+}
+
+#[test]
+fn colors() {
+    assert_eq!(parse_color("#7f7f7f").unwrap(), [0x7f, 0x7f, 0x7f]);
+    assert_eq!(parse_color("00ff10").unwrap(), [0, 0xff, 0x10]);
+    assert!(parse_color("fff").is_err());
+    assert!(parse_color("gg0000").is_err());
+}
+
+#[test]
+fn image_heights() {
+    let heights = GrayImage::from_raw(2, 2, vec![0, 100, 200, 50]).unwrap();
+    let mut heightmap = ImageHeightmap {
+        heights: heights,
+        placement: ImagePlacement {
+            horizontal_scale: 1.0,
+            vertical_scale: 0.5,
+            offset: vec3(0, 10, 0),
+        },
+    };
+    assert_eq!(heightmap.height_at(0, 0), 10);
+    assert_eq!(heightmap.height_at(1, 0), 60);
+    assert_eq!(heightmap.height_at(0, 1), 110);
+    assert_eq!(heightmap.height_at(1, 1), 35);
+
+    // Outside the image the terrain sits at the offset
+    assert_eq!(heightmap.height_at(-3, 0), 10);
+    assert_eq!(heightmap.height_at(0, 7), 10);
+
+    // Each pixel covers two blocks, blended in between
+    heightmap.placement.horizontal_scale = 2.0;
+    assert_eq!(heightmap.height_at(1, 0), 35);
+    assert_eq!(heightmap.height_at(2, 0), 60);
+}
+
+#[test]
+fn unknown_colour_blocks() {
+    let placement = ImagePlacement {
+        horizontal_scale: 1.0,
+        vertical_scale: 1.0,
+        offset: vec3(0, 0, 0),
+    };
+    let mapping = vec![([0, 0, 0], "stone".to_string()), ([255, 0, 0], "lava".to_string())];
+    let error = ColorSurface::from_path(Path::new("/nonexistent.png"),
+                                        placement,
+                                        mapping,
+                                        &Registry::new())
+        .err()
+        .unwrap();
+    assert!(error.contains("lava"));
+}
+
+#[test]
+fn bad_horizontal_scales() {
+    for &scale in &[0.0, -2.0] {
+        let placement = ImagePlacement {
+            horizontal_scale: scale,
+            vertical_scale: 1.0,
+            offset: vec3(0, 0, 0),
+        };
+        let error = HeightmapGenerator::new(Path::new("/nonexistent.png"),
+                                            placement,
+                                            &Registry::new())
+            .err()
+            .unwrap();
+        assert!(error.contains("Horizontal scale"));
+    }
+}
@@ -1,13 +1,103 @@
+use std::path::Path;
+
+use prelude::*;
+
 use super::WorldPoint;
 use super::chunk::Chunk;
 use world::registry::Registry;
 
 mod flat;
 mod simplex;
+mod decoration;
+mod pipeline;
+mod stages;
+mod heightmap;
+mod script;
 
 pub use self::flat::FlatGenerator;
-pub use self::simplex::SimplexGenerator;
+pub use self::simplex::{SimplexGenerator, SimplexHeight, Carver};
+pub use self::decoration::{Decorator, Feature, Tree, Boulder, PrefabFeature};
+pub use self::pipeline::{Pipeline, Heightmap, Stage, GenContext};
+pub use self::stages::{ConstantHeight, Fill, Surface, Ores};
+pub use self::heightmap::{HeightmapGenerator, ImageHeightmap, ColorSurface, ImagePlacement};
+pub use self::script::ScriptGenerator;
 
 pub trait ChunkGenerator {
     fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk;
+
+    /// Height of the topmost terrain block in a column, if the generator can
+    /// answer without generating the chunk. Decorations use this to anchor
+    /// features in neighbouring columns.
+    fn surface_height(&self, _x: i32, _z: i32) -> Option<i32> {
+        None
+    }
+}
+
+/// Builds a generator from a spec string as given on the command line or in
+/// world metadata: `flat`, `flat:<layers>` (see `FlatGenerator::from_spec`;
+/// the prefix may be left out when the layers contain a `*`),
+/// `simplex`, `heightmap:<image>[,<horizontal scale>[,<vertical scale>]]`
+/// (commas in the image path are fine, but a path ending in `,<number>`
+/// needs both scales after it),
+/// `script:<Rhai file>` (see `ScriptGenerator`), or a path to a pipeline
+/// preset file.
+pub fn from_spec(spec: &str,
+                 seed: u32,
+                 registry: &Registry)
+                 -> Result<Box<ChunkGenerator>, String> {
+    if spec == "flat" {
+        Ok(Box::new(FlatGenerator::new(50, 1, "stone".into())))
+    } else if spec.starts_with("flat:") {
+        Ok(Box::new(try!(FlatGenerator::from_spec(&spec["flat:".len()..], registry))))
+    } else if spec.starts_with("heightmap:") {
+        let (path, scales) = split_scales(&spec["heightmap:".len()..]);
+        let placement = ImagePlacement {
+            horizontal_scale: scales.get(0).cloned().unwrap_or(1.0),
+            vertical_scale: scales.get(1).cloned().unwrap_or(0.25),
+            offset: vec3(0, 0, 0),
+        };
+        Ok(Box::new(try!(HeightmapGenerator::new(Path::new(&path), placement, registry))))
+    } else if spec.starts_with("script:") {
+        Ok(Box::new(try!(ScriptGenerator::from_path(Path::new(&spec["script:".len()..]), seed))))
+    } else if spec == "simplex" {
+        Ok(Box::new(SimplexGenerator::with_seed(50, 1, seed)))
+    } else if spec.contains('*') {
+        Ok(Box::new(try!(FlatGenerator::from_spec(spec, registry))))
+    } else {
+        Ok(Box::new(try!(Pipeline::from_preset_file(Path::new(spec), seed, registry))))
+    }
+}
+
+/// Splits up to two trailing numbers off `text`, leaving the rest, commas
+/// and all, as the path.
+fn split_scales(text: &str) -> (String, Vec<f32>) {
+    let mut parts: Vec<&str> = text.split(',').collect();
+    let mut scales = Vec::new();
+    while parts.len() > 1 && scales.len() < 2 {
+        match parts[parts.len() - 1].trim().parse::<f32>() {
+            Ok(scale) => scales.insert(0, scale),
+            Err(_) => break,
+        }
+        parts.pop();
+    }
+    (parts.join(","), scales)
+}
+
+#[test]
+fn heightmap_specs() {
+    assert_eq!(split_scales("hills.png"), ("hills.png".to_string(), vec![]));
+    assert_eq!(split_scales("hills.png,2"), ("hills.png".to_string(), vec![2.0]));
+    assert_eq!(split_scales("maps/a,b.png,2, 0.5"),
+               ("maps/a,b.png".to_string(), vec![2.0, 0.5]));
+    assert_eq!(split_scales("x,3,1,0.25"), ("x,3".to_string(), vec![1.0, 0.25]));
+}
+
+#[test]
+fn bare_layer_specs() {
+    let registry = Registry::new();
+    for spec in &["flat:1*stone,3*dirt", "1*stone,3*dirt"] {
+        let chunk_gen = from_spec(spec, 0, &registry).unwrap();
+        assert_eq!(chunk_gen.surface_height(0, 0), Some(3));
+    }
+    assert!(from_spec("2*lava", 0, &registry).is_err());
 }
@@ -0,0 +1,368 @@
+use std::path::Path;
+
+use prelude::*;
+
+use rustc_serialize::json::Json;
+
+use world::chunk::Chunk;
+use world::WorldPoint;
+use world::registry::Registry;
+use world::prefab::Prefab;
+use super::ChunkGenerator;
+use super::stages::{ConstantHeight, Fill, Surface, Ores};
+use super::simplex::{SimplexHeight, Carver};
+use super::decoration::{Decorator, Tree, Boulder, Feature, PrefabFeature};
+use super::heightmap::{ImageHeightmap, ColorSurface, ImagePlacement, parse_color};
+
+/// The density pass of a pipeline: how high the terrain reaches in a column.
+pub trait Heightmap {
+    fn height_at(&self, x: i32, z: i32) -> i32;
+}
+
+/// One step of terrain generation, run in order on every chunk.
+pub trait Stage {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext);
+}
+
+pub struct GenContext<'a> {
+    pub heightmap: &'a Heightmap,
+    pub registry: &'a Registry,
+    pub seed: u32,
+}
+
+/// A generator assembled from a heightmap and a list of stages, such as
+/// filling, surface painting, carving, ores and decorations.
+pub struct Pipeline {
+    seed: u32,
+    heightmap: Box<Heightmap>,
+    stages: Vec<Box<Stage>>,
+}
+
+impl Pipeline {
+    pub fn new(seed: u32, heightmap: Box<Heightmap>) -> Pipeline {
+        Pipeline {
+            seed: seed,
+            heightmap: heightmap,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn with_stage(mut self, stage: Box<Stage>) -> Pipeline {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Builds a pipeline from a preset document of the form
+    ///
+    /// ```json
+    /// { "seed": 42,
+    ///   "heightmap": { "type": "simplex", "low": 1, "high": 50 },
+    ///   "stages": [ { "type": "fill", "block": "stone" },
+    ///               { "type": "surface", "top": "grass", "filler": "dirt" } ] }
+    /// ```
+    ///
+    /// `default_seed` is used when the preset does not pin one. Block names
+    /// are checked against `registry`.
+    pub fn from_json(preset: &Json,
+                     default_seed: u32,
+                     registry: &Registry)
+                     -> Result<Pipeline, String> {
+        let seed = match preset.find("seed") {
+            Some(seed) => try!(seed.as_u64().ok_or("\"seed\" must be a number")) as u32,
+            None => default_seed,
+        };
+
+        let heightmap = match preset.find("heightmap") {
+            Some(config) => try!(build_heightmap(config, seed)),
+            None => return Err("Preset is missing \"heightmap\"".into()),
+        };
+
+        let mut pipeline = Pipeline::new(seed, heightmap);
+        if let Some(stages) = preset.find("stages") {
+            let stages = try!(stages.as_array().ok_or("\"stages\" must be a list"));
+            for config in stages {
+                pipeline.stages.push(try!(build_stage(config, seed, registry)));
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    pub fn from_preset_file(path: &Path,
+                            default_seed: u32,
+                            registry: &Registry)
+                            -> Result<Pipeline, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let mut text = String::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read preset {:?}: {}", path, e)));
+        let preset = try!(Json::from_str(&text)
+            .map_err(|e| format!("Could not parse preset {:?}: {}", path, e)));
+        Pipeline::from_json(&preset, default_seed, registry)
+    }
+}
+
+impl ChunkGenerator for Pipeline {
+    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
+        let mut chunk = Chunk::new(origin);
+        let context = GenContext {
+            heightmap: &*self.heightmap,
+            registry: registry,
+            seed: self.seed,
+        };
+
+        for stage in &self.stages {
+            stage.apply(&mut chunk, &context);
+        }
+
+        chunk
+    }
+
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        Some(self.heightmap.height_at(x, z))
+    }
+}
+
+fn build_heightmap(config: &Json, seed: u32) -> Result<Box<Heightmap>, String> {
+    match try!(get_str(config, "type")) {
+        "constant" => Ok(Box::new(ConstantHeight::new(try!(get_i32(config, "height"))))),
+        "simplex" => {
+            Ok(Box::new(SimplexHeight::new(seed,
+                                           try!(get_i32(config, "high")),
+                                           try!(get_i32(config, "low")),
+                                           try!(get_f32_or(config, "scale", 0.07)))))
+        }
+        "image" => {
+            Ok(Box::new(try!(ImageHeightmap::from_path(Path::new(try!(get_str(config, "path"))),
+                                                       try!(get_placement(config))))))
+        }
+        other => Err(format!("Unknown heightmap type \"{}\"", other)),
+    }
+}
+
+fn build_stage(config: &Json, seed: u32, registry: &Registry) -> Result<Box<Stage>, String> {
+    match try!(get_str(config, "type")) {
+        "fill" => {
+            Ok(Box::new(Fill::new(try!(get_block(config, "block", registry)).into(),
+                                  try!(get_opt_i32(config, "low")))))
+        }
+        "surface" => {
+            Ok(Box::new(Surface::new(try!(get_block(config, "top", registry)).into(),
+                                     try!(get_block(config, "filler", registry)).into(),
+                                     try!(get_i32_or(config, "depth", 3)))))
+        }
+        "color_surface" => {
+            let colors = try!(config.find("colors")
+                .and_then(|c| c.as_object())
+                .ok_or("Colour surface stage needs a \"colors\" object"));
+            let mut mapping = Vec::new();
+            for (block, color) in colors {
+                let color = try!(color.as_string()
+                    .ok_or(format!("Colour for \"{}\" must be a string", block)));
+                mapping.push((try!(parse_color(color)), block.clone()));
+            }
+
+            Ok(Box::new(try!(ColorSurface::from_path(Path::new(try!(get_str(config, "path"))),
+                                                     try!(get_placement(config)),
+                                                     mapping,
+                                                     registry))))
+        }
+        "carver" => {
+            Ok(Box::new(Carver::new(seed,
+                                    try!(get_f32_or(config, "scale", 0.15)),
+                                    try!(get_f32_or(config, "threshold", 0.25)))))
+        }
+        "ore" => {
+            Ok(Box::new(Ores::new(try!(get_block(config, "block", registry)).into(),
+                                  try!(get_block(config, "host", registry)).into(),
+                                  try!(per_thousand(try!(get_i32_or(config,
+                                                                    "per_thousand",
+                                                                    2)),
+                                                    "ore stage")),
+                                  try!(get_i32_or(config, "size", 1)))))
+        }
+        "decoration" => {
+            let mut decorator = Decorator::new();
+            let features = try!(config.find("features")
+                .and_then(|f| f.as_array())
+                .ok_or("Decoration stage needs a \"features\" list"));
+            for feature in features {
+                let chance = try!(per_thousand(try!(get_i32(feature, "per_thousand")),
+                                               "decoration stage"));
+                decorator = decorator.with_feature(try!(build_feature(feature, registry)),
+                                                   chance);
+            }
+            Ok(Box::new(decorator))
+        }
+        other => Err(format!("Unknown stage type \"{}\"", other)),
+    }
+}
+
+fn build_feature(config: &Json, registry: &Registry) -> Result<Box<Feature>, String> {
+    match try!(get_str(config, "type")) {
+        "tree" => {
+            let min_height = try!(get_i32_or(config, "min_height", 4));
+            let max_height = try!(get_i32_or(config, "max_height", 6));
+            if min_height < 0 || min_height > max_height {
+                return Err(format!("Tree heights must satisfy 0 <= min_height <= max_height, \
+                                    but got {} and {}",
+                                   min_height,
+                                   max_height));
+            }
+            Ok(Box::new(Tree::new(try!(get_block(config, "trunk", registry)).into(),
+                                  try!(get_block(config, "leaves", registry)).into(),
+                                  min_height,
+                                  max_height)))
+        }
+        "boulder" => {
+            let radius = try!(get_i32_or(config, "radius", 2));
+            if radius < 1 {
+                return Err(format!("Boulder radius must be at least 1, but got {}", radius));
+            }
+            Ok(Box::new(Boulder::new(try!(get_block(config, "block", registry)).into(), radius)))
+        }
+        "prefab" => {
+            let prefab = try!(Prefab::read(Path::new(try!(get_str(config, "path")))));
+            Ok(Box::new(try!(PrefabFeature::new(&prefab, registry))))
+        }
+        other => Err(format!("Unknown feature type \"{}\"", other)),
+    }
+}
+
+/// Checks that a `per_thousand` chance read for `stage` lies in 0..=1000.
+fn per_thousand(value: i32, stage: &str) -> Result<u32, String> {
+    if value < 0 || value > 1000 {
+        return Err(format!("\"per_thousand\" for the {} must be between 0 and 1000, but got {}",
+                           stage,
+                           value));
+    }
+    Ok(value as u32)
+}
+
+/// Reads `horizontal_scale`, `vertical_scale` and `offset: [x, y, z]` for
+/// image based stages.
+fn get_placement(config: &Json) -> Result<ImagePlacement, String> {
+    let offset = match config.find("offset") {
+        Some(offset) => {
+            let values: Vec<i32> = try!(offset.as_array()
+                    .ok_or("\"offset\" must be a list"))
+                .iter()
+                .filter_map(|v| v.as_i64())
+                .map(|v| v as i32)
+                .collect();
+            if values.len() != 3 {
+                return Err("\"offset\" must have three integers".into());
+            }
+            vec3(values[0], values[1], values[2])
+        }
+        None => vec3(0, 0, 0),
+    };
+
+    Ok(ImagePlacement {
+        horizontal_scale: try!(get_f32_or(config, "horizontal_scale", 1.0)),
+        vertical_scale: try!(get_f32_or(config, "vertical_scale", 0.25)),
+        offset: offset,
+    })
+}
+
+fn get_str<'a>(config: &'a Json, key: &str) -> Result<&'a str, String> {
+    config.find(key)
+        .and_then(|v| v.as_string())
+        .ok_or(format!("Expected string \"{}\"", key))
+}
+
+/// Like `get_str`, for a block name that must be in `registry`.
+fn get_block<'a>(config: &'a Json, key: &str, registry: &Registry) -> Result<&'a str, String> {
+    let name = try!(get_str(config, key));
+    match registry.lookup_id(&name.to_string()) {
+        Some(_) => Ok(name),
+        None => Err(format!("Unknown block \"{}\" for \"{}\"", name, key)),
+    }
+}
+
+fn get_i32(config: &Json, key: &str) -> Result<i32, String> {
+    config.find(key)
+        .and_then(|v| v.as_i64())
+        .map(|v| v as i32)
+        .ok_or(format!("Expected integer \"{}\"", key))
+}
+
+fn get_opt_i32(config: &Json, key: &str) -> Result<Option<i32>, String> {
+    match config.find(key) {
+        Some(_) => get_i32(config, key).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn get_i32_or(config: &Json, key: &str, default: i32) -> Result<i32, String> {
+    get_opt_i32(config, key).map(|v| v.unwrap_or(default))
+}
+
+fn get_f32_or(config: &Json, key: &str, default: f32) -> Result<f32, String> {
+    match config.find(key) {
+        Some(v) => {
+            v.as_f64()
+                .map(|v| v as f32)
+                .ok_or(format!("Expected number \"{}\"", key))
+        }
+        None => Ok(default),
+    }
+}
+
+#[test]
+fn parses_preset() {
+    let preset = Json::from_str(r#"{ "seed": 7,
+                                      "heightmap": { "type": "constant", "height": 10 },
+                                      "stages": [ { "type": "fill", "block": "stone" },
+                                                  { "type": "surface",
+                                                    "top": "grass",
+                                                    "filler": "dirt" } ] }"#)
+        .unwrap();
+    let registry = Registry::new();
+    let pipeline = Pipeline::from_json(&preset, 0, &registry).unwrap();
+    assert_eq!(pipeline.seed(), 7);
+    assert_eq!(pipeline.stages.len(), 2);
+    assert_eq!(pipeline.surface_height(3, 4), Some(10));
+
+    let bad = Json::from_str(r#"{ "heightmap": { "type": "lava" } }"#).unwrap();
+    assert!(Pipeline::from_json(&bad, 0, &registry).is_err());
+
+    // Mistakes that would otherwise only show up while generating
+    let with_feature = |feature: &str| {
+        let preset = format!(r#"{{ "heightmap": {{ "type": "constant", "height": 10 }},
+                                   "stages": [ {{ "type": "decoration",
+                                                  "features": [ {{ "per_thousand": 5,
+                                                                   {} }} ] }} ] }}"#,
+                             feature);
+        Pipeline::from_json(&Json::from_str(&preset).unwrap(), 0, &registry)
+    };
+    assert!(with_feature(r#""type": "boulder", "block": "stone", "radius": 1"#).is_ok());
+    assert!(with_feature(r#""type": "boulder", "block": "stone", "radius": 0"#).is_err());
+    assert!(with_feature(r#""type": "tree", "trunk": "logg", "leaves": "leaves""#).is_err());
+    assert!(with_feature(r#""type": "tree", "trunk": "log", "leaves": "leaves",
+                            "min_height": 6, "max_height": 4"#)
+        .is_err());
+
+    // Chances outside 0..=1000 name the stage they came from
+    let with_stage = |stage: &str| {
+        let preset = format!(r#"{{ "heightmap": {{ "type": "constant", "height": 10 }},
+                                   "stages": [ {{ {} }} ] }}"#,
+                             stage);
+        Pipeline::from_json(&Json::from_str(&preset).unwrap(), 0, &registry).err()
+    };
+    let ore = r#""type": "ore", "block": "dirt", "host": "stone", "per_thousand": "#;
+    assert!(with_stage(&format!("{}1000", ore)).is_none());
+    assert!(with_stage(&format!("{}1001", ore)).unwrap().contains("ore stage"));
+    assert!(with_stage(&format!("{}-5", ore)).unwrap().contains("ore stage"));
+    let decoration = r#""type": "decoration",
+                        "features": [ { "type": "boulder", "block": "stone",
+                                        "per_thousand": -1 } ]"#;
+    assert!(with_stage(decoration).unwrap().contains("decoration stage"));
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use noise;
+use rhai::{Engine, Scope, AST, INT, FLOAT};
+
+use prelude::*;
+use world::block::{Block, BlockID};
+use world::chunk::Chunk;
+use world::WorldPoint;
+use world::registry::Registry;
+use world::prefab::AIR;
+use super::ChunkGenerator;
+
+/// The most operations one call to `block_at` may take, so a script that
+/// loops forever fails rather than hanging chunk generation.
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+/// Terrain from a Rhai script defining `fn block_at(x, y, z)`, which returns
+/// the name of the block at a position, or `"air"` or `""` for nothing.
+/// Scripts can call `seed()`, `noise(x, z)` and `noise3(x, y, z)`, the
+/// latter two giving seeded simplex noise between -1 and 1.
+///
+/// The script is called once per block, so this is for trying out ideas
+/// rather than for worlds of any size.
+pub struct ScriptGenerator {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptGenerator {
+    pub fn from_path(path: &Path, seed: u32) -> Result<ScriptGenerator, String> {
+        let mut source = String::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|e| format!("Could not read script {:?}: {}", path, e)));
+        ScriptGenerator::from_source(&source, seed)
+            .map_err(|e| format!("In {:?}: {}", path, e))
+    }
+
+    pub fn from_source(source: &str, seed: u32) -> Result<ScriptGenerator, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        let noise_seed = noise::Seed::new(seed);
+        engine.register_fn("seed", move || seed as INT);
+        engine.register_fn("noise", move |x: FLOAT, z: FLOAT| {
+            noise::open_simplex2(&noise_seed, &[x as f32, z as f32]) as FLOAT
+        });
+        let noise_seed = noise::Seed::new(seed);
+        engine.register_fn("noise3", move |x: FLOAT, y: FLOAT, z: FLOAT| {
+            noise::open_simplex3(&noise_seed, &[x as f32, y as f32, z as f32]) as FLOAT
+        });
+
+        let ast = try!(engine.compile(source).map_err(|e| format!("Script error: {}", e)));
+        if !ast.iter_functions().any(|f| f.name == "block_at" && f.params.len() == 3) {
+            return Err("Script does not define block_at(x, y, z)".into());
+        }
+
+        Ok(ScriptGenerator {
+            engine: engine,
+            ast: ast,
+        })
+    }
+}
+
+impl ChunkGenerator for ScriptGenerator {
+    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
+        let mut chunk = Chunk::new(origin);
+        let mut scope = Scope::new();
+        let mut ids: HashMap<String, Option<BlockID>> = HashMap::new();
+
+        for loc in chunk.iter() {
+            let world_loc = origin + vec3(loc.x as i32, loc.y as i32, loc.z as i32);
+            let result = self.engine.call_fn::<String>(&mut scope,
+                                                       &self.ast,
+                                                       "block_at",
+                                                       (world_loc.x as INT,
+                                                        world_loc.y as INT,
+                                                        world_loc.z as INT));
+            let name = match result {
+                Ok(name) => name,
+                Err(e) => {
+                    // One error per chunk is enough to go on
+                    warn!("Script failed at {:?}, leaving the rest of the chunk empty: {}",
+                          world_loc,
+                          e);
+                    break;
+                }
+            };
+            if name.is_empty() || name == AIR {
+                continue;
+            }
+
+            let id = *ids.entry(name.clone()).or_insert_with(|| {
+                let id = registry.lookup_id(&name);
+                if id.is_none() {
+                    warn!("Script asked for unknown block \"{}\"", name);
+                }
+                id
+            });
+            if let Some(id) = id {
+                chunk.set_block_immediate(world_loc, Block::from_id_only(id));
+            }
+        }
+
+        chunk
+    }
+}
+
+#[test]
+fn script_terrain() {
+    let source = "fn block_at(x, y, z) {\n\
+                      if y < 2 { \"stone\" } else if y == 2 && x == z { \"dirt\" } else { \"\" }\n\
+                  }";
+    let chunk_gen = ScriptGenerator::from_source(source, 7).unwrap();
+    let registry = Registry::new();
+    let chunk = chunk_gen.generate_chunk(point3(0, 0, 0), &registry);
+
+    assert_eq!(chunk.get_block(point3(5, 1, 9)).id, registry.lookup_id(&"stone".into()).unwrap());
+    assert_eq!(chunk.get_block(point3(3, 2, 3)).id, registry.lookup_id(&"dirt".into()).unwrap());
+    assert!(chunk.get_block(point3(3, 2, 4)).is_empty());
+
+    // A script that never returns gives up rather than hanging
+    let endless = ScriptGenerator::from_source("fn block_at(x, y, z) { loop { } }", 7).unwrap();
+    let empty = endless.generate_chunk(point3(0, 0, 0), &registry);
+    assert!(empty.get_block(point3(0, 0, 0)).is_empty());
+
+    assert!(ScriptGenerator::from_source("fn height(x, z) { 4 }", 7).is_err());
+    assert!(ScriptGenerator::from_source("fn block_at(x, y, z) {", 7).is_err());
+}
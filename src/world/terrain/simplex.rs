@@ -1,71 +1,114 @@
 extern crate rand;
 
 use prelude::*;
-use world::block::Block;
-use world::chunk::{Chunk, CHUNK_SIZE};
+use world::block::{Block, BlockID};
+use world::chunk::Chunk;
 use world::WorldPoint;
 use world::registry::Registry;
 use super::ChunkGenerator;
+use super::pipeline::{Pipeline, Heightmap, Stage, GenContext};
+use super::stages::{Fill, Surface};
+use super::decoration::{Decorator, Tree, Boulder};
 use noise;
 
-pub struct SimplexGenerator {
+/// Rolling hills sampled from 2D simplex noise between `low` and `high`.
+pub struct SimplexHeight {
     high: i32,
     low: i32,
+    scale: f32,
 
     seed: noise::Seed,
 }
 
-impl SimplexGenerator {
-    pub fn new(high: i32, low: i32) -> SimplexGenerator {
-        SimplexGenerator {
+impl SimplexHeight {
+    pub fn new(seed: u32, high: i32, low: i32, scale: f32) -> SimplexHeight {
+        SimplexHeight {
             high: high,
             low: low,
+            scale: scale,
 
-            seed: noise::Seed::new(rand::random::<u32>()),
+            seed: noise::Seed::new(seed),
         }
     }
 }
 
-const FLAT_SCALING_FACTOR: f32 = 0.07;
-const HOLE_SCALING_FACTOR: f32 = 0.15;
+impl Heightmap for SimplexHeight {
+    fn height_at(&self, x: i32, z: i32) -> i32 {
+        let sample = noise::open_simplex2(&self.seed,
+                                          &[x as f32 * self.scale, z as f32 * self.scale]);
+        let sample_0_1 = (sample + 1.0) / 2.0;
+        ((self.high - self.low) as f32 * sample_0_1) as i32 + self.low
+    }
+}
 
-impl ChunkGenerator for SimplexGenerator {
-    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
-        let mut chunk = Chunk::new(origin);
-        let grass_id = registry.lookup_id(&"grass".into()).expect("Could not find block ID");
-        let dirt_id = registry.lookup_id(&"dirt".into()).expect("Could not find block ID");
-        let stone_id = registry.lookup_id(&"stone".into()).expect("Could not find block ID");
-
-
-        for x in origin.x..(origin.x + CHUNK_SIZE) {
-            for z in origin.z..(origin.z + CHUNK_SIZE) {
-                let sample = noise::open_simplex2(&self.seed,
-                                                  &[x as f32 * FLAT_SCALING_FACTOR,
-                                                    z as f32 * FLAT_SCALING_FACTOR]);
-                let sample_0_1 = (sample + 1.0) / 2.0;
-                let sampled_height = ((self.high - self.low) as f32 * sample_0_1) as i32 + self.low;
-
-                for y in origin.y..(origin.y + CHUNK_SIZE) {
-                    if y <= sampled_height &&
-                       noise::open_simplex3(&self.seed,
-                                            &[x as f32 * HOLE_SCALING_FACTOR,
-                                              y as f32 * HOLE_SCALING_FACTOR,
-                                              z as f32 * HOLE_SCALING_FACTOR]) <
-                       0.25 {
-                        let id = if y == sampled_height {
-                            grass_id
-                        } else if y <= sampled_height - 1 && y >= sampled_height - 3 {
-                            dirt_id
-                        } else {
-                            stone_id
-                        };
-
-                        chunk.set_block_immediate(point3(x, y, z), Block::from_id_only(id));
-                    }
-                }
+/// Cuts caves out of the terrain wherever 3D simplex noise rises above
+/// `threshold`.
+pub struct Carver {
+    scale: f32,
+    threshold: f32,
+
+    seed: noise::Seed,
+}
+
+impl Carver {
+    pub fn new(seed: u32, scale: f32, threshold: f32) -> Carver {
+        Carver {
+            scale: scale,
+            threshold: threshold,
+
+            seed: noise::Seed::new(seed),
+        }
+    }
+}
+
+impl Stage for Carver {
+    fn apply(&self, chunk: &mut Chunk, _context: &GenContext) {
+        for loc in chunk.iter() {
+            if chunk.get_block_local(loc).is_empty() {
+                continue;
+            }
+
+            let world_loc = chunk.origin + vec3(loc.x as i32, loc.y as i32, loc.z as i32);
+            if noise::open_simplex3(&self.seed,
+                                    &[world_loc.x as f32 * self.scale,
+                                      world_loc.y as f32 * self.scale,
+                                      world_loc.z as f32 * self.scale]) >=
+               self.threshold {
+                chunk.set_block_immediate(world_loc, Block::from_id_only(BlockID(0)));
             }
         }
+    }
+}
+
+/// The default hills-and-caves terrain, expressed as a pipeline preset.
+pub struct SimplexGenerator {
+    pipeline: Pipeline,
+}
+
+impl SimplexGenerator {
+    pub fn new(high: i32, low: i32) -> SimplexGenerator {
+        SimplexGenerator::with_seed(high, low, rand::random::<u32>())
+    }
+
+    pub fn with_seed(high: i32, low: i32, seed: u32) -> SimplexGenerator {
+        let pipeline = Pipeline::new(seed, Box::new(SimplexHeight::new(seed, high, low, 0.07)))
+            .with_stage(Box::new(Fill::new("stone".into(), None)))
+            .with_stage(Box::new(Surface::new("grass".into(), "dirt".into(), 3)))
+            .with_stage(Box::new(Carver::new(seed, 0.15, 0.25)))
+            .with_stage(Box::new(Decorator::new()
+                .with_feature(Box::new(Tree::new("log".into(), "leaves".into(), 4, 6)), 12)
+                .with_feature(Box::new(Boulder::new("stone".into(), 2)), 2)));
+
+        SimplexGenerator { pipeline: pipeline }
+    }
+}
+
+impl ChunkGenerator for SimplexGenerator {
+    fn generate_chunk(&self, origin: WorldPoint, registry: &Registry) -> Chunk {
+        self.pipeline.generate_chunk(origin, registry)
+    }
 
-        chunk
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.pipeline.surface_height(x, z)
     }
 }
@@ -0,0 +1,154 @@
+use prelude::*;
+use world::block::Block;
+use world::chunk::{Chunk, CHUNK_SIZE, CHUNK_EXTENTS_LESS_ONE};
+use world::RegionIter;
+use super::pipeline::{Heightmap, Stage, GenContext};
+use super::decoration::column_hash;
+
+pub struct ConstantHeight {
+    height: i32,
+}
+
+impl ConstantHeight {
+    pub fn new(height: i32) -> ConstantHeight {
+        ConstantHeight { height: height }
+    }
+}
+
+impl Heightmap for ConstantHeight {
+    fn height_at(&self, _x: i32, _z: i32) -> i32 {
+        self.height
+    }
+}
+
+/// Fills every column with `block` from `low` (or the bottom of the chunk)
+/// up to the heightmap.
+pub struct Fill {
+    block: String,
+    low: Option<i32>,
+}
+
+impl Fill {
+    pub fn new(block: String, low: Option<i32>) -> Fill {
+        Fill {
+            block: block,
+            low: low,
+        }
+    }
+}
+
+impl Stage for Fill {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let id = context.registry.lookup_id(&self.block).expect("Could not find block ID");
+        let origin = chunk.origin;
+
+        for x in origin.x..(origin.x + CHUNK_SIZE) {
+            for z in origin.z..(origin.z + CHUNK_SIZE) {
+                let height = context.heightmap.height_at(x, z);
+                for y in origin.y..(origin.y + CHUNK_SIZE) {
+                    if y <= height && self.low.map_or(true, |low| y >= low) {
+                        chunk.set_block_immediate(point3(x, y, z), Block::from_id_only(id));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Repaints the top of each filled column: `top` on the surface block and
+/// `filler` for `depth` blocks beneath it.
+pub struct Surface {
+    top: String,
+    filler: String,
+    depth: i32,
+}
+
+impl Surface {
+    pub fn new(top: String, filler: String, depth: i32) -> Surface {
+        Surface {
+            top: top,
+            filler: filler,
+            depth: depth,
+        }
+    }
+}
+
+impl Stage for Surface {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let top_id = context.registry.lookup_id(&self.top).expect("Could not find block ID");
+        let filler_id = context.registry
+            .lookup_id(&self.filler)
+            .expect("Could not find block ID");
+        let origin = chunk.origin;
+
+        for x in origin.x..(origin.x + CHUNK_SIZE) {
+            for z in origin.z..(origin.z + CHUNK_SIZE) {
+                let height = context.heightmap.height_at(x, z);
+                for y in origin.y..(origin.y + CHUNK_SIZE) {
+                    let loc = point3(x, y, z);
+                    if chunk.get_block(loc).is_empty() {
+                        continue;
+                    }
+
+                    if y == height {
+                        chunk.set_block_immediate(loc, Block::from_id_only(top_id));
+                    } else if y < height && y >= height - self.depth {
+                        chunk.set_block_immediate(loc, Block::from_id_only(filler_id));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Scatters small blobs of `block` through `host`, for ores and pockets of
+/// dirt or gravel.
+pub struct Ores {
+    block: String,
+    host: String,
+    per_thousand: u32,
+    size: i32,
+}
+
+impl Ores {
+    pub fn new(block: String, host: String, per_thousand: u32, size: i32) -> Ores {
+        Ores {
+            block: block,
+            host: host,
+            per_thousand: per_thousand,
+            size: size,
+        }
+    }
+}
+
+impl Stage for Ores {
+    fn apply(&self, chunk: &mut Chunk, context: &GenContext) {
+        let id = context.registry.lookup_id(&self.block).expect("Could not find block ID");
+        let host_id = context.registry.lookup_id(&self.host).expect("Could not find block ID");
+        let origin = chunk.origin;
+        let margin = vec3(self.size, self.size, self.size);
+
+        // Blob centres just outside the chunk still reach into it
+        for centre in RegionIter::new(origin - margin, origin + CHUNK_EXTENTS_LESS_ONE + margin) {
+            let roll = column_hash(column_hash(context.seed, centre.x, centre.z, 0x0e),
+                                   centre.y,
+                                   0,
+                                   0x0e);
+            if roll % 1000 >= self.per_thousand {
+                continue;
+            }
+
+            for loc in RegionIter::new(centre - margin, centre + margin) {
+                let offset = loc - centre;
+                if offset.x * offset.x + offset.y * offset.y + offset.z * offset.z >
+                   self.size * self.size {
+                    continue;
+                }
+
+                if chunk.is_loc_local(loc) && chunk.get_block(loc).id == host_id {
+                    chunk.set_block_immediate(loc, Block::from_id_only(id));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Reading and writing MagicaVoxel `.vox` files.
+//!
+//! MagicaVoxel is z-up. Vox `x` maps to world `x`, vox `z` to world `y` and
+//! vox `y` to world `-z`, so that models keep their handedness.
+
+use std::collections::hash_map::HashMap;
+use std::path::Path;
+
+use prelude::*;
+use super::WorldPoint;
+
+/// Largest model MagicaVoxel will load along any axis.
+pub const MAX_MODEL_SIZE: u32 = 256;
+
+pub struct VoxModel {
+    pub size: [u32; 3],
+    /// Position of the model's minimum corner, in vox coordinates.
+    pub corner: [i32; 3],
+    /// `(x, y, z, palette index)`, with palette indices starting at 1.
+    pub voxels: Vec<[u8; 4]>,
+}
+
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    /// RGBA colours; `palette[i - 1]` is the colour of palette index `i`.
+    pub palette: Vec<[u8; 4]>,
+}
+
+/// Palette indices mapped to registry block names, read from lines such as
+/// `12 = stone` or `3 = grass 5a9e3a`, where the optional colour is used for
+/// the palette when exporting.
+pub struct VoxMapping {
+    pub blocks: HashMap<u8, String>,
+    pub colors: HashMap<u8, [u8; 4]>,
+}
+
+impl VoxMapping {
+    pub fn from_path(path: &Path) -> Result<VoxMapping, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let mut text = String::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read mapping {:?}: {}", path, e)));
+        VoxMapping::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<VoxMapping, String> {
+        let mut mapping = VoxMapping {
+            blocks: HashMap::new(),
+            colors: HashMap::new(),
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = || format!("Invalid mapping on line {}: \"{}\"", number + 1, line);
+            let mut sides = line.splitn(2, '=');
+            let index = try!(sides.next()
+                .unwrap()
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| error()));
+            let mut rest = try!(sides.next().ok_or_else(&error)).split_whitespace();
+            let block = try!(rest.next().ok_or_else(&error));
+            if index == 0 {
+                return Err(error());
+            }
+
+            mapping.blocks.insert(index, block.to_string());
+            if let Some(hex) = rest.next() {
+                let color = try!(parse_hex(hex).ok_or_else(&error));
+                mapping.colors.insert(index, [color[0], color[1], color[2], 255]);
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim_left_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let mut color = [0; 3];
+    for i in 0..3 {
+        match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(v) => color[i] = v,
+            Err(_) => return None,
+        }
+    }
+    Some(color)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.pos + count > self.bytes.len() {
+            return Err("Unexpected end of .vox data".into());
+        }
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = try!(self.take(4));
+        Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        self.u32().map(|v| v as i32)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = try!(self.u32()) as usize;
+        Ok(String::from_utf8_lossy(try!(self.take(len))).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, String> {
+        let mut dict = HashMap::new();
+        for _ in 0..try!(self.u32()) {
+            let key = try!(self.string());
+            dict.insert(key, try!(self.string()));
+        }
+        Ok(dict)
+    }
+}
+
+/// Where the voxels of a file land in the world. The file's bounds are
+/// worked out once here rather than for every voxel.
+pub struct VoxPlacement {
+    origin: WorldPoint,
+    low: [i32; 3],
+    high: [i32; 3],
+}
+
+impl VoxPlacement {
+    /// World position of `voxel` from `model`.
+    pub fn voxel_to_world(&self, model: &VoxModel, voxel: [u8; 4]) -> WorldPoint {
+        let x = model.corner[0] + voxel[0] as i32;
+        let y = model.corner[1] + voxel[1] as i32;
+        let z = model.corner[2] + voxel[2] as i32;
+        self.origin + vec3(x - self.low[0], z - self.low[2], self.high[1] - y)
+    }
+}
+
+impl VoxFile {
+    /// Inclusive bounds of every model, in vox coordinates.
+    pub fn bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+        let mut bounds: Option<([i32; 3], [i32; 3])> = None;
+        for model in self.models.iter().filter(|m| m.size.iter().all(|&s| s > 0)) {
+            let (mut low, mut high) = bounds.unwrap_or((model.corner, model.corner));
+            for i in 0..3 {
+                low[i] = low[i].min(model.corner[i]);
+                high[i] = high[i].max(model.corner[i] + model.size[i] as i32 - 1);
+            }
+            bounds = Some((low, high));
+        }
+        bounds
+    }
+
+    /// Size of the file's bounding box in world axes.
+    pub fn world_size(&self) -> Vector3<i32> {
+        match self.bounds() {
+            Some((low, high)) => {
+                vec3(high[0] - low[0] + 1,
+                     high[2] - low[2] + 1,
+                     high[1] - low[1] + 1)
+            }
+            None => vec3(0, 0, 0),
+        }
+    }
+
+    /// Places the file's bounding box with its minimum world corner at
+    /// `origin`. Gives `None` if the file has no voxels.
+    pub fn placement(&self, origin: WorldPoint) -> Option<VoxPlacement> {
+        self.bounds().map(|(low, high)| {
+            VoxPlacement {
+                origin: origin,
+                low: low,
+                high: high,
+            }
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<VoxFile, String> {
+        use std::io::Read;
+        use std::fs::File;
+
+        let mut bytes = Vec::new();
+        try!(File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e)));
+        VoxFile::decode(&bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<VoxFile, String> {
+        let mut reader = Reader {
+            bytes: bytes,
+            pos: 0,
+        };
+        if try!(reader.take(4)) != b"VOX " {
+            return Err("Not a .vox file".into());
+        }
+        let _version = try!(reader.u32());
+
+        if try!(reader.take(4)) != b"MAIN" {
+            return Err("Missing MAIN chunk".into());
+        }
+        let _ = try!(reader.u32());
+        let _ = try!(reader.u32());
+
+        let mut sizes = Vec::new();
+        let mut voxels = Vec::new();
+        let mut palette = vec![[128, 128, 128, 255]; 255];
+        // Scene graph: transform node -> (child, translation), shape node -> model
+        let mut transforms: Vec<(i32, [i32; 3])> = Vec::new();
+        let mut shapes: HashMap<i32, i32> = HashMap::new();
+
+        while reader.pos < bytes.len() {
+            let id = try!(reader.take(4));
+            let content_len = try!(reader.u32()) as usize;
+            let children_len = try!(reader.u32()) as usize;
+            let mut content = Reader {
+                bytes: try!(reader.take(content_len)),
+                pos: 0,
+            };
+            try!(reader.take(children_len));
+
+            match id {
+                b"SIZE" => {
+                    sizes.push([try!(content.u32()), try!(content.u32()), try!(content.u32())])
+                }
+                b"XYZI" => {
+                    let count = try!(content.u32()) as usize;
+                    let data = try!(content.take(count * 4));
+                    voxels.push(data.chunks(4).map(|v| [v[0], v[1], v[2], v[3]]).collect());
+                }
+                b"RGBA" => {
+                    for i in 0..255 {
+                        let c = try!(content.take(4));
+                        palette[i] = [c[0], c[1], c[2], c[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let _node = try!(content.i32());
+                    let _attributes = try!(content.dict());
+                    let child = try!(content.i32());
+                    let _reserved = try!(content.i32());
+                    let _layer = try!(content.i32());
+                    let frames = try!(content.u32());
+                    let mut translation = [0; 3];
+                    if frames > 0 {
+                        if let Some(t) = try!(content.dict()).get("_t") {
+                            for (i, v) in t.split_whitespace().take(3).enumerate() {
+                                translation[i] = v.parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                    transforms.push((child, translation));
+                }
+                b"nSHP" => {
+                    let node = try!(content.i32());
+                    let _attributes = try!(content.dict());
+                    if try!(content.u32()) > 0 {
+                        shapes.insert(node, try!(content.i32()));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if sizes.len() != voxels.len() {
+            return Err("Mismatched SIZE and XYZI chunks".into());
+        }
+
+        let mut models: Vec<VoxModel> = sizes.into_iter()
+            .zip(voxels.into_iter())
+            .map(|(size, voxels)| {
+                VoxModel {
+                    size: size,
+                    corner: [0; 3],
+                    voxels: voxels,
+                }
+            })
+            .collect();
+
+        // Translations are to the centre of the model
+        for (child, t) in transforms {
+            if let Some(&model) = shapes.get(&child) {
+                if let Some(model) = models.get_mut(model as usize) {
+                    for i in 0..3 {
+                        model.corner[i] = t[i] - (model.size[i] / 2) as i32;
+                    }
+                }
+            }
+        }
+
+        Ok(VoxFile {
+            models: models,
+            palette: palette,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        use std::io::Write;
+        use std::fs::File;
+
+        try!(File::create(path)
+            .and_then(|mut f| f.write_all(&self.encode()))
+            .map_err(|e| format!("Could not write {:?}: {}", path, e)));
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+        for model in &self.models {
+            let mut size = Vec::new();
+            for &s in &model.size {
+                put_u32(&mut size, s);
+            }
+            put_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = Vec::new();
+            put_u32(&mut xyzi, model.voxels.len() as u32);
+            for v in &model.voxels {
+                xyzi.extend_from_slice(v);
+            }
+            put_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        // Root transform -> group -> one transform and shape per model
+        let mut root = Vec::new();
+        put_transform(&mut root, 0, 1, None);
+        put_chunk(&mut children, b"nTRN", &root);
+
+        let mut group = Vec::new();
+        put_u32(&mut group, 1);
+        put_u32(&mut group, 0);
+        put_u32(&mut group, self.models.len() as u32);
+        for i in 0..self.models.len() {
+            put_u32(&mut group, 2 + 2 * i as u32);
+        }
+        put_chunk(&mut children, b"nGRP", &group);
+
+        for (i, model) in self.models.iter().enumerate() {
+            let node = 2 + 2 * i as u32;
+            let mut centre = [0; 3];
+            for axis in 0..3 {
+                centre[axis] = model.corner[axis] + (model.size[axis] / 2) as i32;
+            }
+
+            let mut transform = Vec::new();
+            put_transform(&mut transform, node, node + 1, Some(centre));
+            put_chunk(&mut children, b"nTRN", &transform);
+
+            let mut shape = Vec::new();
+            put_u32(&mut shape, node + 1);
+            put_u32(&mut shape, 0);
+            put_u32(&mut shape, 1);
+            put_u32(&mut shape, i as u32);
+            put_u32(&mut shape, 0);
+            put_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        let mut rgba = Vec::new();
+        for i in 0..256 {
+            rgba.extend_from_slice(&self.palette.get(i).cloned().unwrap_or([0, 0, 0, 255]));
+        }
+        put_chunk(&mut children, b"RGBA", &rgba);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"VOX ");
+        put_u32(&mut out, 150);
+        out.extend_from_slice(b"MAIN");
+        put_u32(&mut out, 0);
+        put_u32(&mut out, children.len() as u32);
+        out.extend_from_slice(&children);
+        out
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[value as u8,
+                            (value >> 8) as u8,
+                            (value >> 16) as u8,
+                            (value >> 24) as u8]);
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn put_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    put_u32(out, content.len() as u32);
+    put_u32(out, 0);
+    out.extend_from_slice(content);
+}
+
+fn put_transform(out: &mut Vec<u8>, node: u32, child: u32, translation: Option<[i32; 3]>) {
+    put_u32(out, node);
+    put_u32(out, 0);
+    put_u32(out, child);
+    put_u32(out, !0);
+    put_u32(out, if translation.is_some() { 0 } else { !0 });
+    put_u32(out, 1);
+    match translation {
+        Some(t) => {
+            put_u32(out, 1);
+            put_string(out, "_t");
+            put_string(out, &format!("{} {} {}", t[0], t[1], t[2]));
+        }
+        None => put_u32(out, 0),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let file = VoxFile {
+        models: vec![VoxModel {
+                         size: [2, 3, 4],
+                         corner: [10, -4, 0],
+                         voxels: vec![[0, 0, 0, 1], [1, 2, 3, 7]],
+                     }],
+        palette: vec![[1, 2, 3, 255]; 255],
+    };
+
+    let decoded = VoxFile::decode(&file.encode()).unwrap();
+    assert_eq!(decoded.models.len(), 1);
+    assert_eq!(decoded.models[0].size, [2, 3, 4]);
+    assert_eq!(decoded.models[0].corner, [10, -4, 0]);
+    assert_eq!(decoded.models[0].voxels, vec![[0, 0, 0, 1], [1, 2, 3, 7]]);
+    assert_eq!(decoded.palette[0], [1, 2, 3, 255]);
+
+    assert_eq!(decoded.world_size(), vec3(2, 4, 3));
+    let model = &decoded.models[0];
+    let placement = decoded.placement(point3(0, 0, 0)).unwrap();
+    assert_eq!(placement.voxel_to_world(model, model.voxels[0]), point3(0, 0, 2));
+    assert_eq!(placement.voxel_to_world(model, model.voxels[1]), point3(1, 3, 0));
+}
+
+#[test]
+fn mappings() {
+    let mapping = VoxMapping::parse("# comment\n1 = stone\n 3 = grass 5a9e3a # green\n").unwrap();
+    assert_eq!(mapping.blocks.get(&1).unwrap(), "stone");
+    assert_eq!(mapping.blocks.get(&3).unwrap(), "grass");
+    assert_eq!(mapping.colors.get(&3), Some(&[0x5a, 0x9e, 0x3a, 255]));
+    assert!(VoxMapping::parse("0 = stone").is_err());
+    assert!(VoxMapping::parse("stone").is_err());
+}
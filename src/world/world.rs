@@ -1,4 +1,5 @@
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use prelude::*;
 use graphics;
@@ -9,18 +10,63 @@ use super::chunk::{Chunk, CHUNK_SIZE, CHUNK_EXTENTS_LESS_ONE};
 use super::block::*;
 use super::terrain::ChunkGenerator;
 use super::registry::Registry;
+use super::vox::{VoxFile, VoxModel, VoxMapping, MAX_MODEL_SIZE};
+use super::prefab::{Prefab, PasteOptions, AIR};
+use super::edit::Shape;
+use super::history::{History, Change, DEFAULT_BUDGET};
+use super::events::{Events, WorldEvent, Cause, ListenerId};
+use super::meta::WorldMeta;
+use super::lock::SessionLock;
 
 pub struct World {
     chunks: HashMap<WorldPoint, Chunk>,
     world_root: PathBuf,
     chunk_gen: Box<ChunkGenerator>,
     pub registry: Registry,
+    pub history: History,
+    events: Events,
 
     dirty_chunks: Vec<WorldPoint>,
+    /// Chunks changed since they were last saved.
+    unsaved: HashSet<WorldPoint>,
+    /// Held for as long as the world may be saved; `None` when read-only.
+    lock: Option<SessionLock>,
+}
+
+/// How a world directory is opened. Only one process at a time may open a
+/// world for writing; any number may read it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    ReadWrite,
+    /// Edits stay in memory and saving does nothing.
+    ReadOnly,
+}
+
+impl Access {
+    /// Takes the lock that opening `world_root` this way needs, if any.
+    pub fn lock(self, world_root: &Path) -> Result<Option<SessionLock>, String> {
+        match self {
+            Access::ReadWrite => SessionLock::acquire(world_root).map(Some),
+            Access::ReadOnly => Ok(None),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct RayHit {
+    pub loc: WorldPoint,
+    /// The face the ray entered through, or `None` if it started inside the
+    /// block.
+    pub face: Option<Face>,
+    pub distance: f32,
+    /// Where the ray meets the block's surface.
+    pub point: Point3<f32>,
+    pub block: Block,
 }
 
 #[inline]
-fn find_chunk_origin(point: WorldPoint) -> WorldPoint {
+/// The origin of the chunk holding `point`.
+pub fn find_chunk_origin(point: WorldPoint) -> WorldPoint {
     point3(point.x & (-CHUNK_SIZE),
            point.y & (-CHUNK_SIZE),
            point.z & (-CHUNK_SIZE))
@@ -36,10 +82,24 @@ fn origins() {
 
 
 impl World {
+    /// Loads every chunk in `extents`. Opening for writing fails if another
+    /// process has the world open for writing.
     pub fn from_path(world_root: &Path,
                      extents: (Vector3<i32>, Vector3<i32>),
-                     chunk_gen: Box<ChunkGenerator>)
-                     -> World {
+                     chunk_gen: Box<ChunkGenerator>,
+                     access: Access)
+                     -> Result<World, String> {
+        let lock = try!(access.lock(world_root));
+        World::from_path_locked(world_root, extents, chunk_gen, lock)
+    }
+
+    /// Like `from_path`, for callers that took the lock themselves before
+    /// reading the world's metadata. `None` opens the world read-only.
+    pub fn from_path_locked(world_root: &Path,
+                            extents: (Vector3<i32>, Vector3<i32>),
+                            chunk_gen: Box<ChunkGenerator>,
+                            lock: Option<SessionLock>)
+                            -> Result<World, String> {
         use num_iter::range_step;
 
         let mut world = World {
@@ -47,33 +107,170 @@ impl World {
             world_root: world_root.into(),
             chunk_gen: chunk_gen,
             registry: Registry::new(),
+            history: History::new(DEFAULT_BUDGET),
+            events: Events::new(),
 
             dirty_chunks: Vec::new(),
+            unsaved: HashSet::new(),
+            lock: lock,
         };
         for x in range_step(extents.0.x, extents.1.x, CHUNK_SIZE) {
             for y in range_step(extents.1.y - CHUNK_SIZE,
                                 extents.0.y - CHUNK_SIZE,
                                 -CHUNK_SIZE) {
                 for z in range_step(extents.0.z, extents.1.z, CHUNK_SIZE) {
-                    world.load_chunk(point3(x, y, z));
+                    try!(world.load_chunk(point3(x, y, z)));
                 }
             }
         }
 
         world.fix_visibility();
-        world
+        world.unsaved.clear();
+        Ok(world)
     }
 
-    fn load_chunk(&mut self, chunk_origin: WorldPoint) {
-        match Chunk::read(chunk_origin, &self.world_root) {
-            Some(chunk) => {
-                self.chunks.insert(chunk_origin, chunk);
+    /// Loads the area around the spawn point of the world at `world_root`,
+    /// creating its metadata with `default_generator` if the world is new.
+    pub fn open(world_root: &Path,
+                default_generator: &str,
+                access: Access)
+                -> Result<World, String> {
+        // Locked first, so that two processes cannot both create the metadata
+        let lock = try!(access.lock(world_root));
+        let chunk_gen = try!(WorldMeta::open_generator(world_root,
+                                                       default_generator,
+                                                       &Registry::new(),
+                                                       access));
+        World::from_path_locked(world_root,
+                                (vec3(-64, 0, -64), vec3(64, 64, 64)),
+                                chunk_gen,
+                                lock)
+    }
+
+    /// Reads the chunk at `chunk_origin`, or generates it if it was never
+    /// saved. Fails if the saved chunk cannot be read.
+    fn load_chunk(&mut self, chunk_origin: WorldPoint) -> Result<(), String> {
+        let chunk = match try!(Chunk::try_read(chunk_origin, &self.world_root)) {
+            Some(chunk) => chunk,
+            None => self.chunk_gen.generate_chunk(chunk_origin, &self.registry),
+        };
+        self.chunks.insert(chunk_origin, chunk);
+        self.events.emit(WorldEvent::ChunkLoaded(chunk_origin));
+        Ok(())
+    }
+
+    /// The chunk at `origin`, loading or generating it first if needed, with
+    /// face visibility fixed inside it and where it meets its neighbours.
+    pub fn require_chunk(&mut self, origin: WorldPoint) -> Result<&Chunk, String> {
+        if !self.chunks.contains_key(&origin) {
+            try!(self.load_chunk(origin));
+            self.fix_chunk_visibility(origin);
+            // Only the neighbours' faces changed in a way worth saving
+            self.unsaved.remove(&origin);
+        }
+        Ok(&self.chunks[&origin])
+    }
+
+    /// Saves the chunk at `origin` and drops it from memory. Returns false
+    /// if it was not loaded. As with the other saves, wait for any autosave
+    /// writer first.
+    pub fn unload_chunk(&mut self, origin: WorldPoint) -> bool {
+        if !self.chunks.contains_key(&origin) {
+            return false;
+        }
+
+        self.events.emit(WorldEvent::ChunkUnloaded(origin));
+        let chunk = self.chunks.remove(&origin).unwrap();
+        if !self.is_read_only() {
+            chunk.write(&self.world_root);
+        }
+        self.dirty_chunks.retain(|&o| o != origin);
+        self.unsaved.remove(&origin);
+        true
+    }
+
+    /// Calls `listener` for every block change, chunk load and chunk unload
+    /// from now on. Chunks loaded by `from_path` are not reported.
+    pub fn subscribe(&mut self, listener: Box<FnMut(&WorldEvent)>) -> ListenerId {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.world_root
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    /// Swaps the generator used for chunks that are not saved, then
+    /// regenerates every loaded chunk that was never saved and has no edits
+    /// waiting to be. The undo history is cleared, since it may no longer
+    /// match the terrain. Returns how many chunks were regenerated.
+    pub fn regenerate_unsaved(&mut self, chunk_gen: Box<ChunkGenerator>) -> usize {
+        self.chunk_gen = chunk_gen;
+
+        let unsaved: Vec<WorldPoint> = self.chunks
+            .keys()
+            .filter(|&origin| !self.unsaved.contains(origin))
+            .filter(|&&origin| !Chunk::path(origin, &self.world_root).exists())
+            .cloned()
+            .collect();
+        for &origin in &unsaved {
+            let mut chunk = self.chunk_gen.generate_chunk(origin, &self.registry);
+            chunk.dirty = true;
+            self.chunks.insert(origin, chunk);
+            if !self.dirty_chunks.contains(&origin) {
+                self.dirty_chunks.push(origin);
             }
-            None => {
-                self.chunks.insert(chunk_origin,
-                                   self.chunk_gen.generate_chunk(chunk_origin, &self.registry));
+        }
+
+        self.fix_visibility();
+        self.history.clear();
+        unsaved.len()
+    }
+
+    /// Throws away every loaded chunk and reads it again from disk, or
+    /// generates it with `chunk_gen` if it is not saved, as after the files
+    /// were replaced underneath the world. Unsaved edits and the undo history
+    /// are lost. Returns how many chunks were reloaded.
+    pub fn reload(&mut self, chunk_gen: Box<ChunkGenerator>) -> usize {
+        self.chunk_gen = chunk_gen;
+
+        let origins: Vec<WorldPoint> = self.chunks.keys().cloned().collect();
+        for &origin in &origins {
+            let mut chunk = match Chunk::read(origin, &self.world_root) {
+                Some(chunk) => chunk,
+                None => self.chunk_gen.generate_chunk(origin, &self.registry),
+            };
+            chunk.dirty = true;
+            self.chunks.insert(origin, chunk);
+            if !self.dirty_chunks.contains(&origin) {
+                self.dirty_chunks.push(origin);
             }
         }
+
+        self.fix_visibility();
+        self.unsaved.clear();
+        self.history.clear();
+        origins.len()
+    }
+
+    pub fn chunk(&self, origin: WorldPoint) -> Option<&Chunk> {
+        self.chunks.get(&origin)
+    }
+
+    /// The origins of every chunk in memory.
+    pub fn loaded_chunks(&self) -> Vec<WorldPoint> {
+        self.chunks.keys().cloned().collect()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
     }
 
     pub fn make_models<R, F: gfx::traits::FactoryExt<R>>(&self,
@@ -142,11 +339,33 @@ impl World {
         }
     }
 
+    /// Like `fix_visibility`, for a single newly loaded chunk and the blocks
+    /// of its neighbours that touch it.
+    fn fix_chunk_visibility(&mut self, origin: WorldPoint) {
+        let low = origin - vec3(1, 1, 1);
+        let high = origin + CHUNK_EXTENTS_LESS_ONE + vec3(1, 1, 1);
+        for loc in super::RegionIter::new(low, high) {
+            let block = self.get_block(loc);
+            if block.is_empty() {
+                continue;
+            }
+
+            let visibility = self.visibility_at(loc);
+            if find_chunk_origin(loc) == origin {
+                let light = LightKind::source(15, 15);
+                self.set_block_immediate(loc, Block::from_id(block.id, visibility, light));
+            } else if visibility != block.visibility {
+                self.set_block_immediate(loc, Block::from_id(block.id, visibility, block.light));
+            }
+        }
+    }
+
     fn set_block_immediate(&mut self, loc: WorldPoint, block: Block) {
         let chunk_origin = find_chunk_origin(loc);
         let new_chunk = match self.chunks.get_mut(&chunk_origin) {
             Some(chunk) => {
                 chunk.set_block_immediate(loc, block);
+                self.unsaved.insert(chunk_origin);
                 if !chunk.dirty {
                     chunk.dirty = true;
                     self.dirty_chunks.push(chunk_origin);
@@ -159,10 +378,171 @@ impl World {
         if let Some(mut chunk) = new_chunk {
             chunk.set_block_immediate(loc, block);
             self.chunks.insert(chunk_origin, chunk);
+            self.unsaved.insert(chunk_origin);
+        }
+    }
+
+    fn visibility_at(&self, loc: WorldPoint) -> FaceVisibility {
+        let mut visibility = VISIBLE_NONE;
+        for face in Face::iter() {
+            if self.get_block(loc + face.normal()).is_empty() {
+                visibility |= face.to_visible_mask();
+            }
         }
+        visibility
+    }
+
+    /// Sets many blocks at once. Unlike `place_block` and `break_block`,
+    /// face visibility is recomputed a single time for the changed blocks and
+    /// their neighbours, and each touched chunk is queued for remeshing once.
+    /// The changes are recorded as one undo step. Returns the number of blocks
+    /// that actually changed.
+    pub fn apply_changes<I>(&mut self, changes: I) -> usize
+        where I: IntoIterator<Item = (WorldPoint, BlockID)>
+    {
+        let changes = self.write_changes(changes, Cause::Edit);
+        let count = changes.len();
+        self.history.record(changes);
+        count
+    }
+
+    fn write_changes<I>(&mut self, changes: I, cause: Cause) -> Vec<Change>
+        where I: IntoIterator<Item = (WorldPoint, BlockID)>
+    {
+        let mut written = Vec::new();
+        let mut touched = HashSet::new();
+        for (loc, id) in changes {
+            let old = self.get_block(loc).id;
+            if old == id {
+                continue;
+            }
+
+            let block = Block::from_id(id, VISIBLE_NONE, LightKind::source(15, 15));
+            self.set_block_immediate(loc, block);
+            touched.insert(loc);
+            written.push(Change {
+                loc: loc,
+                old: old,
+                new: id,
+            });
+        }
+
+        let mut affected = touched.clone();
+        for &loc in &touched {
+            for face in Face::iter() {
+                affected.insert(loc + face.normal());
+            }
+        }
+
+        for loc in affected {
+            let block = self.get_block(loc);
+            if block.is_empty() {
+                continue;
+            }
+
+            let visibility = self.visibility_at(loc);
+            if touched.contains(&loc) || visibility != block.visibility {
+                self.set_block_immediate(loc, Block::from_id(block.id, visibility, block.light));
+            }
+        }
+
+        // Listeners only hear about the changes once the faces are right
+        for change in &written {
+            self.events.emit(WorldEvent::BlockChanged {
+                loc: change.loc,
+                old: change.old,
+                new: change.new,
+                cause: cause,
+            });
+        }
+
+        written
+    }
+
+    fn record_change(&mut self, loc: WorldPoint, old: BlockID, new: BlockID, cause: Cause) {
+        if old != new {
+            self.history.record(Some(Change {
+                loc: loc,
+                old: old,
+                new: new,
+            }));
+            self.events.emit(WorldEvent::BlockChanged {
+                loc: loc,
+                old: old,
+                new: new,
+                cause: cause,
+            });
+        }
+    }
+
+    /// Groups every change until the matching `end_edit` into one undo step.
+    pub fn begin_edit(&mut self) {
+        self.history.begin();
+    }
+
+    pub fn end_edit(&mut self) {
+        self.history.end();
+    }
+
+    /// Reverts the most recent edit. Returns false if there was nothing to
+    /// undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop_undo() {
+            Some(transaction) => {
+                // Reverse order so the earliest value of a repeated block wins
+                self.write_changes(transaction.iter().rev().map(|c| (c.loc, c.old)),
+                                   Cause::Undo);
+                self.history.push_redo(transaction);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Re-applies the most recently undone edit. Returns false if there was
+    /// nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.history.pop_redo() {
+            Some(transaction) => {
+                self.write_changes(transaction.iter().map(|c| (c.loc, c.new)), Cause::Redo);
+                self.history.push_undo(transaction);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets every block of `shape` to `id`.
+    pub fn fill_shape(&mut self, shape: &Shape, id: BlockID) -> usize {
+        self.apply_changes(shape.positions().into_iter().map(|loc| (loc, id)))
+    }
+
+    /// Swaps every `from` block in `shape` for `to`.
+    pub fn replace_in_shape(&mut self, shape: &Shape, from: BlockID, to: BlockID) -> usize {
+        let matching: Vec<WorldPoint> = shape.positions()
+            .into_iter()
+            .filter(|&loc| self.get_block(loc).id == from)
+            .collect();
+        self.apply_changes(matching.into_iter().map(|loc| (loc, to)))
+    }
+
+    /// Builds the walls of the inclusive box `from`..`to` out of `id` and
+    /// empties everything inside.
+    pub fn hollow_box(&mut self, from: WorldPoint, to: WorldPoint, id: BlockID) -> usize {
+        let (low, high) = Shape::Box(from, to).bounds();
+        let walls = Shape::Walls(low, high).positions().into_iter().map(|loc| (loc, id));
+        let inside = if high.x - low.x > 1 && high.y - low.y > 1 && high.z - low.z > 1 {
+            Shape::Box(low + vec3(1, 1, 1), high - vec3(1, 1, 1)).positions()
+        } else {
+            Vec::new()
+        };
+
+        self.apply_changes(walls.chain(inside.into_iter().map(|loc| (loc, BlockID(0)))))
     }
 
     pub fn place_block(&mut self, loc: WorldPoint, id: BlockID) {
+        let old = self.get_block(loc).id;
+
         let mut visibility = VISIBLE_NONE;
         for face in Face::iter() {
             let neighbor = self.get_block(loc + face.normal());
@@ -180,9 +560,13 @@ impl World {
 
         let block = Block::from_id(id, visibility, LightKind::source(15, 15));
         self.set_block_immediate(loc, block);
+        // Only once the block is written, so listeners see the new world
+        self.record_change(loc, old, id, Cause::Place);
     }
 
     pub fn break_block(&mut self, loc: WorldPoint) {
+        let old = self.get_block(loc).id;
+
         let block = Block::from_id(BlockID(0), VISIBLE_NONE, LightKind::source(15, 15));
         self.set_block_immediate(loc, block);
 
@@ -197,73 +581,598 @@ impl World {
                 self.set_block_immediate(loc + face.normal(), new_neighbor);
             }
         }
+        self.record_change(loc, old, BlockID(0), Cause::Break);
+    }
+
+    /// Places every voxel of `vox` with the file's bounding box at `origin`,
+    /// looking up block names for palette indices in `mapping`. Voxels with
+    /// unmapped colours are skipped. Returns the number of blocks changed.
+    pub fn import_vox(&mut self,
+                      vox: &VoxFile,
+                      origin: WorldPoint,
+                      mapping: &VoxMapping)
+                      -> Result<usize, String> {
+        let mut ids = HashMap::new();
+        for (&index, name) in &mapping.blocks {
+            ids.insert(index,
+                       try!(self.registry
+                           .lookup_id(name)
+                           .ok_or(format!("Unknown block \"{}\" in mapping", name))));
+        }
+
+        let placement = match vox.placement(origin) {
+            Some(placement) => placement,
+            None => return Ok(0),
+        };
+        let mut changes = Vec::new();
+        let mut skipped = 0;
+        for model in &vox.models {
+            for &voxel in &model.voxels {
+                match ids.get(&voxel[3]) {
+                    Some(&id) => changes.push((placement.voxel_to_world(model, voxel), id)),
+                    None => skipped += 1,
+                }
+            }
+        }
+
+        if skipped > 0 {
+            warn!("Skipped {} voxels with no block mapping", skipped);
+        }
+        Ok(self.apply_changes(changes))
+    }
+
+    /// Copies the inclusive box `from`..`to` into a `.vox` file, split into
+    /// models of at most `MAX_MODEL_SIZE` along each side. Blocks missing from
+    /// `mapping` are given unused palette entries. Palette entries without a
+    /// colour in `mapping` take the block's colour from `colors`.
+    pub fn export_vox(&self,
+                      from: WorldPoint,
+                      to: WorldPoint,
+                      mapping: &VoxMapping,
+                      colors: &HashMap<BlockID, [u8; 4]>)
+                      -> Result<VoxFile, String> {
+        let low = point3(from.x.min(to.x), from.y.min(to.y), from.z.min(to.z));
+        let high = point3(from.x.max(to.x), from.y.max(to.y), from.z.max(to.z));
+        // Size in vox axes
+        let size = [(high.x - low.x + 1) as u32,
+                    (high.z - low.z + 1) as u32,
+                    (high.y - low.y + 1) as u32];
+
+        let mut palette = vec![[128, 128, 128, 255]; 255];
+        let mut indices: HashMap<BlockID, u8> = HashMap::new();
+        for (&index, name) in &mapping.blocks {
+            let id = self.registry.lookup_id(name);
+            if let Some(id) = id {
+                indices.entry(id).or_insert(index);
+            }
+            let color = mapping.colors.get(&index).or(id.and_then(|id| colors.get(&id)));
+            if let Some(&color) = color {
+                palette[index as usize - 1] = color;
+            }
+        }
+        let mut used: Vec<u8> = indices.values().cloned().collect();
+
+        let mut models = Vec::new();
+        for mx in 0..(size[0] + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE {
+            for my in 0..(size[1] + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE {
+                for mz in 0..(size[2] + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE {
+                    let corner = [mx * MAX_MODEL_SIZE, my * MAX_MODEL_SIZE, mz * MAX_MODEL_SIZE];
+                    let mut model = VoxModel {
+                        size: [(size[0] - corner[0]).min(MAX_MODEL_SIZE),
+                               (size[1] - corner[1]).min(MAX_MODEL_SIZE),
+                               (size[2] - corner[2]).min(MAX_MODEL_SIZE)],
+                        corner: [corner[0] as i32, corner[1] as i32, corner[2] as i32],
+                        voxels: Vec::new(),
+                    };
+
+                    for x in 0..model.size[0] {
+                        for y in 0..model.size[1] {
+                            for z in 0..model.size[2] {
+                                let loc = low +
+                                          vec3((corner[0] + x) as i32,
+                                               (corner[2] + z) as i32,
+                                               (size[1] - 1 - corner[1] - y) as i32);
+                                let block = self.get_block(loc);
+                                if block.is_empty() {
+                                    continue;
+                                }
+
+                                let index = match indices.get(&block.id) {
+                                    Some(&index) => index,
+                                    None => {
+                                        let free = try!((1..256u32)
+                                            .map(|i| i as u8)
+                                            .find(|i| !used.contains(i))
+                                            .ok_or("Region uses more than 255 block types"));
+                                        used.push(free);
+                                        if let Some(&color) = colors.get(&block.id) {
+                                            palette[free as usize - 1] = color;
+                                        }
+                                        free
+                                    }
+                                };
+                                indices.insert(block.id, index);
+                                model.voxels.push([x as u8, y as u8, z as u8, index]);
+                            }
+                        }
+                    }
+
+                    models.push(model);
+                }
+            }
+        }
+
+        Ok(VoxFile {
+            models: models,
+            palette: palette,
+        })
+    }
+
+    /// Saves the inclusive box `from`..`to` as a prefab, with blocks stored by
+    /// registry name. Fails on a block missing from the registry rather than
+    /// saving it as air.
+    pub fn copy_prefab(&self, from: WorldPoint, to: WorldPoint) -> Result<Prefab, String> {
+        let low = point3(from.x.min(to.x), from.y.min(to.y), from.z.min(to.z));
+        let high = point3(from.x.max(to.x), from.y.max(to.y), from.z.max(to.z));
+        let size = ((high.x - low.x + 1) as u32,
+                    (high.y - low.y + 1) as u32,
+                    (high.z - low.z + 1) as u32);
+
+        let mut names = Vec::new();
+        for loc in super::RegionIter::new(low, high) {
+            let block = self.get_block(loc);
+            if block.is_empty() {
+                names.push(None);
+                continue;
+            }
+            let name = try!(self.registry
+                .lookup_name(block.id)
+                .ok_or(format!("Unregistered block {} at {:?}", block.id.0, loc)));
+            names.push(Some(name));
+        }
+        Ok(Prefab::from_names(size, names.into_iter()))
+    }
+
+    /// Pastes `prefab` with the minimum corner of the transformed box at
+    /// `origin`. Returns the number of blocks changed.
+    pub fn paste_prefab(&mut self,
+                        prefab: &Prefab,
+                        origin: WorldPoint,
+                        options: PasteOptions)
+                        -> Result<usize, String> {
+        let mut ids = HashMap::new();
+        for name in prefab.block_names() {
+            ids.insert(name.clone(),
+                       try!(self.registry
+                           .lookup_id(name)
+                           .ok_or(format!("Unknown block \"{}\" in prefab", name))));
+        }
+
+        let changes: Vec<(WorldPoint, BlockID)> = prefab.blocks(options)
+            .into_iter()
+            .map(|(offset, name)| {
+                (origin + offset, if name == AIR { BlockID(0) } else { ids[name] })
+            })
+            .collect();
 
+        Ok(self.apply_changes(changes))
     }
 
-    pub fn write_all_chunks(&self) {
+    pub fn write_all_chunks(&mut self) {
+        if self.is_read_only() {
+            warn!("Not saving {:?}, which is open read-only", self.world_root);
+            return;
+        }
         for (_, chunk) in &self.chunks {
             chunk.write(&self.world_root);
         }
+        self.unsaved.clear();
     }
 
-    pub fn cast_ray(&self,
-                    origin: Point3<f32>,
-                    direction: Vector3<f32>)
-                    -> Option<(WorldPoint, Face)> {
-        use num::signum;
-        use cgmath::prelude::*;
+    /// Saves only the chunks changed since they were last saved. Chunks that
+    /// cannot be written are logged and stay unsaved. Returns how many were
+    /// written.
+    pub fn save_changed(&mut self) -> usize {
+        let changed = self.take_changed();
+        let mut failed = Vec::new();
+        for &(origin, ref bytes) in &changed {
+            if let Err(e) = Chunk::write_bytes(origin, &self.world_root, bytes) {
+                error!("{}", e);
+                failed.push(origin);
+            }
+        }
+        let written = changed.len() - failed.len();
+        self.mark_unsaved(failed);
+        written
+    }
 
-        let mut origin_vox = point3(origin.x as i32, origin.y as i32, origin.z as i32);
-        let step = vec3(signum(direction.x),
-                        signum(direction.y),
-                        signum(direction.z));
-        let mut max = point3(distance_from_edge(origin.x, direction.x),
-                             distance_from_edge(origin.y, direction.y),
-                             distance_from_edge(origin.z, direction.z));
-        let delta = step.div_element_wise(direction);
+    /// Encodes the chunks changed since they were last saved and counts them
+    /// as saved, leaving the writing to the caller. Gives nothing if the
+    /// world is read-only.
+    pub fn take_changed(&mut self) -> Vec<(WorldPoint, Vec<u8>)> {
+        let mut changed = Vec::new();
+        if self.is_read_only() {
+            return changed;
+        }
+        for origin in self.unsaved.drain() {
+            if let Some(chunk) = self.chunks.get(&origin) {
+                changed.push((origin, chunk.to_bytes()));
+            }
+        }
+        changed
+    }
 
-        loop {
-            let normal = if max.x < max.y && max.x < max.z {
-                if max.x.abs() > direction.x.abs() {
-                    break;
-                }
+    /// Counts chunks taken by `take_changed` as unsaved again, as when
+    /// writing them failed. Chunks no longer loaded are ignored.
+    pub fn mark_unsaved(&mut self, origins: Vec<WorldPoint>) {
+        for origin in origins {
+            if self.chunks.contains_key(&origin) {
+                self.unsaved.insert(origin);
+            }
+        }
+    }
 
-                origin_vox.x += step.x as i32;
-                max.x += delta.x;
-                point3(-step.x as i32, 0, 0)
-            } else if max.y < max.x && max.y < max.z {
-                if max.y.abs() > direction.y.abs() {
-                    break;
-                }
+    /// Walks the blocks along `direction` from `origin`, as far as the
+    /// length of `direction`, and returns the first non-empty one.
+    pub fn cast_ray(&self, origin: Point3<f32>, direction: Vector3<f32>) -> Option<RayHit> {
+        self.cast_ray_filtered(origin, direction, |block| !block.is_empty())
+    }
+
+    /// Like `cast_ray`, but stops at the first block for which `hits` returns
+    /// true, so callers can see through particular kinds of block.
+    pub fn cast_ray_filtered<P>(&self,
+                                origin: Point3<f32>,
+                                direction: Vector3<f32>,
+                                hits: P)
+                                -> Option<RayHit>
+        where P: Fn(&Block) -> bool
+    {
+        use cgmath::prelude::*;
 
-                origin_vox.y += step.y as i32;
-                max.y += delta.y;
-                point3(0, -step.y as i32, 0)
+        let mut loc = point3(origin.x.floor() as i32,
+                             origin.y.floor() as i32,
+                             origin.z.floor() as i32);
+        let block = self.get_block(loc);
+        if hits(&block) {
+            return Some(RayHit {
+                loc: loc,
+                face: None,
+                distance: 0.0,
+                point: origin,
+                block: block,
+            });
+        }
+
+        let length = direction.magnitude();
+        if length == 0.0 {
+            return None;
+        }
+        let direction = direction / length;
+
+        // Distance along the ray to the next boundary on each axis, and
+        // between boundaries on each axis
+        let start = [origin.x, origin.y, origin.z];
+        let dir = [direction.x, direction.y, direction.z];
+        let mut next = [0.0f32; 3];
+        let mut delta = [0.0f32; 3];
+        let mut step = [0i32; 3];
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                next[axis] = (start[axis].floor() + 1.0 - start[axis]) / dir[axis];
+                delta[axis] = 1.0 / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                next[axis] = (start[axis] - start[axis].floor()) / -dir[axis];
+                delta[axis] = -1.0 / dir[axis];
             } else {
-                assert!(max.z <= max.x && max.z <= max.y);
-                if max.z.abs() > direction.z.abs() {
-                    break;
-                }
+                next[axis] = ::std::f32::INFINITY;
+                delta[axis] = ::std::f32::INFINITY;
+            }
+        }
 
-                origin_vox.z += step.z as i32;
-                max.z += delta.z;
-                point3(0, 0, -step.z as i32)
+        loop {
+            let axis = if next[0] <= next[1] && next[0] <= next[2] {
+                0
+            } else if next[1] <= next[2] {
+                1
+            } else {
+                2
             };
 
-            let block = self.get_block(origin_vox);
-            if !block.is_empty() {
-                return Some((origin_vox, Face::from_normal(normal)));
+            let distance = next[axis];
+            if distance > length {
+                return None;
             }
-        }
 
-        None
+            let mut normal = point3(0, 0, 0);
+            match axis {
+                0 => {
+                    loc.x += step[0];
+                    normal.x = -step[0];
+                }
+                1 => {
+                    loc.y += step[1];
+                    normal.y = -step[1];
+                }
+                _ => {
+                    loc.z += step[2];
+                    normal.z = -step[2];
+                }
+            }
+            next[axis] += delta[axis];
+
+            let block = self.get_block(loc);
+            if hits(&block) {
+                return Some(RayHit {
+                    loc: loc,
+                    face: Some(Face::from_normal(normal)),
+                    distance: distance,
+                    point: origin + direction * distance,
+                    block: block,
+                });
+            }
+        }
     }
 }
 
-fn distance_from_edge(value: f32, direction: f32) -> f32 {
-    (if direction > 0.0 {
-        value.ceil() - value
-    } else {
-        value - value.floor()
-    }) / direction.abs()
+#[cfg(test)]
+fn ray_world(blocks: &[WorldPoint]) -> World {
+    use super::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(0, 0, 0)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    let stone = world.registry.lookup_id(&"stone".into()).unwrap();
+    world.apply_changes(blocks.iter().map(|&loc| (loc, stone)));
+    world
+}
+
+#[test]
+fn rays_at_negative_coordinates() {
+    let world = ray_world(&[point3(-3, -1, -2)]);
+
+    // Truncating -0.5 towards zero would start the walk in the wrong block
+    let hit = world.cast_ray(Point3::new(-0.5, -0.5, -1.5), vec3(-5.0, 0.0, 0.0)).unwrap();
+    assert_eq!(hit.loc, point3(-3, -1, -2));
+    assert_eq!(hit.face.unwrap().normal(), vec3(1, 0, 0));
+    assert!((hit.distance - 1.5).abs() < 1e-5);
+    assert!((hit.point.x - -2.0).abs() < 1e-5);
+
+    assert!(world.cast_ray(Point3::new(-0.5, -0.5, -1.5), vec3(-1.0, 0.0, 0.0)).is_none());
+}
+
+#[test]
+fn axis_aligned_rays() {
+    let world = ray_world(&[point3(0, 0, 4), point3(0, -3, 0), point3(2, 0, 0)]);
+    let origin = Point3::new(0.5, 0.5, 0.5);
+
+    let hit = world.cast_ray(origin, vec3(0.0, 0.0, 10.0)).unwrap();
+    assert_eq!(hit.loc, point3(0, 0, 4));
+    assert_eq!(hit.face.unwrap().normal(), vec3(0, 0, -1));
+    assert!((hit.distance - 3.5).abs() < 1e-5);
+
+    let hit = world.cast_ray(origin, vec3(0.0, -10.0, 0.0)).unwrap();
+    assert_eq!(hit.loc, point3(0, -3, 0));
+    assert_eq!(hit.face.unwrap().normal(), vec3(0, 1, 0));
+
+    let hit = world.cast_ray(origin, vec3(10.0, 0.0, 0.0)).unwrap();
+    assert_eq!(hit.loc, point3(2, 0, 0));
+    assert!(world.cast_ray(origin, vec3(-10.0, 0.0, 0.0)).is_none());
+}
+
+#[test]
+fn rays_starting_inside_blocks() {
+    let world = ray_world(&[point3(1, 1, 1), point3(3, 1, 1)]);
+
+    let hit = world.cast_ray(Point3::new(1.5, 1.5, 1.5), vec3(5.0, 0.0, 0.0)).unwrap();
+    assert_eq!(hit.loc, point3(1, 1, 1));
+    assert!(hit.face.is_none());
+    assert_eq!(hit.distance, 0.0);
+
+    // A filter can look through the block we start in
+    let mut world = world;
+    let dirt = world.registry.lookup_id(&"dirt".into()).unwrap();
+    let stone = world.registry.lookup_id(&"stone".into()).unwrap();
+    world.apply_changes(Some((point3(1, 1, 1), dirt)));
+    let hit = world.cast_ray_filtered(Point3::new(1.5, 1.5, 1.5),
+                                      vec3(5.0, 0.0, 0.0),
+                                      |block| block.id == stone)
+        .unwrap();
+    assert_eq!(hit.loc, point3(3, 1, 1));
+    assert_eq!(hit.face.unwrap().normal(), vec3(-1, 0, 0));
+    assert!((hit.distance - 1.5).abs() < 1e-5);
+}
+
+#[test]
+fn regenerating_keeps_edits() {
+    use super::terrain::FlatGenerator;
+
+    let mut world = ray_world(&[point3(1, 1, 1)]);
+    world.require_chunk(point3(16, 0, 0)).unwrap();
+    let stone = world.registry.lookup_id(&"stone".into()).unwrap();
+    let dirt = world.registry.lookup_id(&"dirt".into()).unwrap();
+
+    // Only the chunk without edits takes the new terrain
+    let regenerated = world.regenerate_unsaved(Box::new(FlatGenerator::new(0, 0, "dirt".into())));
+    assert_eq!(regenerated, 1);
+    assert_eq!(world.get_block(point3(1, 1, 1)).id, stone);
+    assert_eq!(world.get_block(point3(17, 0, 0)).id, dirt);
+    assert!(!world.undo());
+}
+
+#[test]
+fn required_chunks_get_visibility() {
+    let mut world = ray_world(&[]);
+    let stone = world.registry.lookup_id(&"stone".into()).unwrap();
+
+    let top = world.require_chunk(point3(0, 0, 0)).unwrap().get_block(point3(5, 0, 5));
+    assert_eq!(top.id, stone);
+    assert_eq!(top.visibility, VISIBLE_TOP | VISIBLE_BOTTOM);
+    assert!(world.get_block(point3(15, 0, 5)).is_visible(Face::Right));
+
+    // The edge of the first chunk is hidden once the next one is there
+    world.require_chunk(point3(16, 0, 0)).unwrap();
+    assert!(!world.get_block(point3(15, 0, 5)).is_visible(Face::Right));
+    assert!(!world.get_block(point3(16, 0, 5)).is_visible(Face::Left));
+}
+
+#[test]
+fn failed_saves_stay_unsaved() {
+    use std::env;
+    use std::fs;
+    use super::terrain::FlatGenerator;
+
+    let root = env::temp_dir().join("subsurface-save-changed-test");
+    let _ = fs::remove_dir_all(&root);
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(&root,
+                                     (vec3(0, 0, 0), vec3(32, 16, 16)),
+                                     chunk_gen,
+                                     Access::ReadWrite)
+        .unwrap();
+    fs::create_dir_all(Chunk::path(point3(16, 0, 0), &root).join("chunk.bincode")).unwrap();
+    world.apply_changes(vec![(point3(1, 1, 1), BlockID(2)), (point3(17, 1, 1), BlockID(2))]);
+
+    assert_eq!(world.save_changed(), 1);
+    let retry: Vec<WorldPoint> = world.take_changed().into_iter().map(|c| c.0).collect();
+    assert_eq!(retry, vec![point3(16, 0, 0)]);
+
+    drop(world);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn large_vox_exports_split() {
+    use super::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(304, 16, 16)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    world.apply_changes(vec![(point3(260, 0, 0), BlockID(2))]);
+
+    let mapping = VoxMapping::parse("1 = stone").unwrap();
+    let mut colors = HashMap::new();
+    colors.insert(BlockID(2), [1, 2, 3, 255]);
+    let vox = world.export_vox(point3(0, 0, 0), point3(299, 0, 0), &mapping, &colors).unwrap();
+
+    let layout: Vec<([u32; 3], [i32; 3], usize)> =
+        vox.models.iter().map(|m| (m.size, m.corner, m.voxels.len())).collect();
+    assert_eq!(layout, vec![([256, 1, 1], [0, 0, 0], 256), ([44, 1, 1], [256, 0, 0], 44)]);
+
+    // Dirt gets the first free index, coloured from `colors`
+    let model = &vox.models[1];
+    let dirt: Vec<[u8; 4]> = model.voxels.iter().filter(|v| v[3] != 1).cloned().collect();
+    assert_eq!(dirt, vec![[4, 0, 0, 2]]);
+    assert_eq!(vox.palette[1], [1, 2, 3, 255]);
+    let placement = vox.placement(point3(0, 0, 0)).unwrap();
+    assert_eq!(placement.voxel_to_world(model, dirt[0]), point3(260, 0, 0));
+}
+
+#[test]
+fn vox_imports_through_mapping() {
+    use super::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(16, 16, 16)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    let vox = VoxFile {
+        models: vec![VoxModel {
+                         size: [2, 2, 1],
+                         corner: [0, 0, 0],
+                         voxels: vec![[0, 0, 0, 1], [1, 1, 0, 9]],
+                     }],
+        palette: vec![[0, 0, 0, 255]; 255],
+    };
+
+    // Vox y runs towards world -z, and index 9 has no block
+    let mapping = VoxMapping::parse("1 = dirt").unwrap();
+    assert_eq!(world.import_vox(&vox, point3(2, 3, 4), &mapping).unwrap(), 1);
+    assert_eq!(world.get_block(point3(2, 3, 5)).id, BlockID(2));
+    assert!(world.get_block(point3(3, 3, 4)).is_empty());
+
+    let unknown = VoxMapping::parse("1 = lava").unwrap();
+    assert!(world.import_vox(&vox, point3(2, 3, 4), &unknown).is_err());
+}
+
+#[test]
+fn prefabs_refuse_unregistered_blocks() {
+    let mut world = ray_world(&[point3(2, 2, 2)]);
+    let prefab = world.copy_prefab(point3(1, 1, 1), point3(3, 3, 3)).unwrap();
+    assert_eq!(prefab.block_names(), &["stone".to_string()]);
+
+    world.apply_changes(vec![(point3(3, 3, 3), BlockID(99))]);
+    assert!(world.copy_prefab(point3(1, 1, 1), point3(3, 3, 3)).is_err());
+}
+
+#[test]
+fn edits_emit_events() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut world = ray_world(&[]);
+    let heard = Rc::new(RefCell::new(Vec::new()));
+    let log = heard.clone();
+    world.subscribe(Box::new(move |event| {
+        log.borrow_mut().push(match *event {
+            WorldEvent::BlockChanged { loc, old, new, cause } => {
+                format!("{:?} {:?} {} -> {}", cause, (loc.x, loc.y, loc.z), old.0, new.0)
+            }
+            WorldEvent::ChunkLoaded(origin) => format!("loaded {:?}", origin),
+            WorldEvent::ChunkUnloaded(origin) => format!("unloaded {:?}", origin),
+        });
+    }));
+
+    world.place_block(point3(1, 1, 1), BlockID(2));
+    // Blocks that already hold the new ID are not reported
+    world.apply_changes(vec![(point3(2, 1, 1), BlockID(2)), (point3(1, 1, 1), BlockID(2))]);
+    assert!(world.undo());
+    assert!(world.unload_chunk(point3(0, 0, 0)));
+
+    assert_eq!(*heard.borrow(),
+               vec!["Place (1, 1, 1) 0 -> 2".to_string(),
+                    "Edit (2, 1, 1) 0 -> 2".to_string(),
+                    "Undo (2, 1, 1) 2 -> 0".to_string(),
+                    format!("unloaded {:?}", point3(0, 0, 0))]);
+}
+
+#[test]
+fn fills_across_chunk_borders() {
+    use super::terrain::FlatGenerator;
+
+    let chunk_gen = Box::new(FlatGenerator::new(0, 0, "stone".into()));
+    let mut world = World::from_path(Path::new("/nonexistent"),
+                                     (vec3(0, 0, 0), vec3(32, 16, 16)),
+                                     chunk_gen,
+                                     Access::ReadOnly)
+        .unwrap();
+    // As if both chunks had been meshed already
+    for origin in world.dirty_chunks.drain(..) {
+        world.chunks.get_mut(&origin).unwrap().dirty = false;
+    }
+
+    let filled = world.fill_shape(&Shape::Box(point3(14, 1, 2), point3(17, 1, 3)), BlockID(2));
+    assert_eq!(filled, 8);
+    let mut dirty = world.dirty_chunks.clone();
+    dirty.sort_by_key(|o| o.x);
+    assert_eq!(dirty, vec![point3(0, 0, 0), point3(16, 0, 0)]);
+
+    // Faces meeting across the border are hidden on both sides
+    assert!(!world.get_block(point3(15, 1, 2)).is_visible(Face::Right));
+    assert!(!world.get_block(point3(16, 1, 2)).is_visible(Face::Left));
+    assert!(world.get_block(point3(17, 1, 2)).is_visible(Face::Right));
+    assert!(world.get_block(point3(15, 1, 2)).is_visible(Face::Top));
+    for x in 14..18 {
+        assert!(!world.get_block(point3(x, 0, 2)).is_visible(Face::Top));
+    }
+    assert!(world.get_block(point3(18, 0, 2)).is_visible(Face::Top));
 }